@@ -0,0 +1,42 @@
+#![no_main]
+
+use std::io;
+
+use libfuzzer_sys::fuzz_target;
+
+use lc3box::display::Display;
+use lc3box::hardware::Registers;
+use lc3box::image::load_image;
+use lc3box::jit::{Jit, DEFAULT_THRESHOLD};
+use lc3box::keyboard::Keyboard;
+use lc3box::vm::{PSR_USER, VM};
+
+/// Instructions a fuzzed image may run before it counts as still running.
+const STEP_LIMIT: u64 = 20_000;
+
+// The first byte picks the dispatch mode and whether the program runs in user
+// mode with access control, the rest is an object file run from its origin.
+// The VM must end in HALT, a fault or the step limit, never panic.
+fuzz_target!(|data: &[u8]| {
+    let Some((&mode, image)) = data.split_first() else { return };
+    let mut vm = VM::new();
+    vm.set_predecode(mode & 1 != 0);
+    if mode & 2 != 0 {
+        vm.set_jit(Jit::new(DEFAULT_THRESHOLD));
+    }
+    vm.set_display(Display::discard());
+    vm.set_keyboard(Keyboard::stream(io::empty()));
+    let Ok((origin, _)) = load_image(image, &mut vm) else { return };
+    vm.register_write(Registers::R_PC.into(), origin);
+    if mode & 4 != 0 {
+        vm.set_access_control(true);
+        vm.set_psr(PSR_USER);
+    }
+    while vm.state_read() && vm.steps() < STEP_LIMIT {
+        vm.run_block();
+    }
+    if !vm.state_read() && vm.fault().is_none() {
+        let pc = vm.register_read(Registers::R_PC.into());
+        assert_eq!(vm.memory_peek(pc.wrapping_sub(1)) & 0xF0FF, 0xF025, "stopped without HALT or a fault");
+    }
+});
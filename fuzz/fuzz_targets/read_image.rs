@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use lc3box::display::Display;
+use lc3box::hardware::MEMORY_MAX;
+use lc3box::image::load_image;
+use lc3box::vm::VM;
+
+// Feeds arbitrary bytes to the loader behind `read_image`: it either rejects
+// them or places every whole word at origin onwards.
+fuzz_target!(|data: &[u8]| {
+    let mut vm = VM::new();
+    vm.set_display(Display::discard());
+    match load_image(data, &mut vm) {
+        Ok((origin, words)) => {
+            assert_eq!(words, ((data.len() - 2) / 2).min(MEMORY_MAX));
+            if let Some(last) = words.checked_sub(1) {
+                let expected = u16::from_be_bytes([data[2 + 2 * last], data[3 + 2 * last]]);
+                assert_eq!(vm.memory_peek(origin.wrapping_add(last as u16)), expected);
+            }
+        }
+        Err(_) => assert!(data.len() < 2),
+    }
+});
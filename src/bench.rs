@@ -0,0 +1,480 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::display::Display;
+use crate::hardware::Registers;
+use crate::image;
+use crate::jit::{self, Jit};
+use crate::keyboard::Keyboard;
+use crate::vm::VM;
+
+/// A program to time: `(origin, words)` segments, started at `start`.
+pub struct Program
+{
+    pub name: String,
+    pub segments: Vec<(u16, Vec<u16>)>,
+    pub start: u16,
+}
+
+impl Program
+{
+    fn at_x3000(name: &str, words: Vec<u16>) -> Self
+    {
+        Self { name: name.to_string(), segments: vec![(0x3000, words)], start: 0x3000 }
+    }
+
+    /// Object file started at x3000 like a normal run. It must reach HALT
+    /// without keyboard input.
+    pub fn from_image(path: &str) -> Result<Self, String>
+    {
+        let mut scratch = VM::new();
+        let (origin, length) = image::read_image(path, &mut scratch)?;
+        let words = (0..length).map(|i| scratch.memory_peek(origin.wrapping_add(i as u16))).collect();
+        Ok(Self { name: path.to_string(), segments: vec![(origin, words)], start: 0x3000 })
+    }
+}
+
+/// Nested counting loop of ADD/AND/NOT/BR, about `outer * inner * 5` instructions.
+pub fn arithmetic_loop(outer: u16, inner: u16) -> Program
+{
+    let words = vec![
+        0x5020, // AND R0, R0, #0
+        0x2209, // LD R1, OUTER
+        0x2409, // LD R2, INNER          ; x3002
+        0x1023, // ADD R0, R0, #3        ; x3003
+        0x5627, // AND R3, R0, #7
+        0x98FF, // NOT R4, R3
+        0x14BF, // ADD R2, R2, #-1
+        0x03FB, // BRp x3003
+        0x127F, // ADD R1, R1, #-1
+        0x03F8, // BRp x3002
+        0xF025, // HALT
+        outer,
+        inner,
+    ];
+    Program::at_x3000("arithmetic", words)
+}
+
+/// Sums a 256-word table with LDR, `passes` times.
+pub fn checksum(passes: u16) -> Program
+{
+    let mut words = vec![
+        0x220D, // LD R1, PASSES
+        0xE40E, // LEA R2, DATA          ; x3001
+        0x260C, // LD R3, LENGTH
+        0x6880, // LDR R4, R2, #0        ; x3003
+        0x1004, // ADD R0, R0, R4
+        0x14A1, // ADD R2, R2, #1
+        0x16FF, // ADD R3, R3, #-1
+        0x03FB, // BRp x3003
+        0x127F, // ADD R1, R1, #-1
+        0x03F7, // BRp x3001
+        0xF025, // HALT
+        0,
+        0,
+        0,
+        passes,
+        256,
+    ];
+    words.extend((0..256).map(|i| i * 7));
+    Program::at_x3000("checksum", words)
+}
+
+/// Copies a 256-word block from x3100 to x3200 with LDR/STR, `passes` times.
+pub fn memory_copy(passes: u16) -> Program
+{
+    let words = vec![
+        0x220C, // LD R1, PASSES
+        0x240C, // LD R2, SOURCE         ; x3001
+        0x260C, // LD R3, DESTINATION
+        0x280C, // LD R4, LENGTH
+        0x6A80, // LDR R5, R2, #0        ; x3004
+        0x7AC0, // STR R5, R3, #0
+        0x14A1, // ADD R2, R2, #1
+        0x16E1, // ADD R3, R3, #1
+        0x193F, // ADD R4, R4, #-1
+        0x03FA, // BRp x3004
+        0x127F, // ADD R1, R1, #-1
+        0x03F5, // BRp x3001
+        0xF025, // HALT
+        passes,
+        0x3100,
+        0x3200,
+        256,
+    ];
+    let data = (0..256).map(|i| i ^ 0x5A5A).collect();
+    let mut program = Program::at_x3000("memory-copy", words);
+    program.segments.push((0x3100, data));
+    program
+}
+
+/// Naive recursive Fibonacci of `n` with a stack in R6, result in R1.
+pub fn fibonacci(n: u16) -> Program
+{
+    let words = vec![
+        0x2C04, // LD R6, STACK
+        0x2002, // LD R0, N
+        0x4803, // JSR FIB
+        0xF025, // HALT
+        n,
+        0x4000, // STACK
+        0x123E, // FIB: ADD R1, R0, #-2  ; x3006
+        0x0602, // BRzp RECURSE
+        0x1220, // ADD R1, R0, #0
+        0xC1C0, // RET
+        0x1DBF, // RECURSE: ADD R6, R6, #-1
+        0x7F80, // STR R7, R6, #0
+        0x1DBF, // ADD R6, R6, #-1
+        0x7180, // STR R0, R6, #0
+        0x103F, // ADD R0, R0, #-1
+        0x4FF6, // JSR FIB
+        0x6180, // LDR R0, R6, #0
+        0x7380, // STR R1, R6, #0
+        0x103E, // ADD R0, R0, #-2
+        0x4FF2, // JSR FIB
+        0x6580, // LDR R2, R6, #0
+        0x1242, // ADD R1, R1, R2
+        0x1DA1, // ADD R6, R6, #1
+        0x6F80, // LDR R7, R6, #0
+        0x1DA1, // ADD R6, R6, #1
+        0xC1C0, // RET
+    ];
+    Program::at_x3000("fibonacci", words)
+}
+
+pub const GREETING: &str = "Hello, LC-3!\n";
+
+/// Prints `GREETING` with PUTS `lines` times.
+pub fn string_output(lines: u16) -> Program
+{
+    let mut words = vec![
+        0x2205, // LD R1, LINES
+        0xE005, // LEA R0, TEXT          ; x3001
+        0xF022, // PUTS
+        0x127F, // ADD R1, R1, #-1
+        0x03FC, // BRp x3001
+        0xF025, // HALT
+        lines,
+    ];
+    words.extend(GREETING.bytes().map(u16::from));
+    words.push(0);
+    Program::at_x3000("string-output", words)
+}
+
+pub fn builtin() -> Vec<Program>
+{
+    vec![
+        arithmetic_loop(1000, 1000),
+        checksum(4000),
+        memory_copy(4000),
+        fibonacci(24),
+        string_output(20000),
+    ]
+}
+
+pub struct Measurement
+{
+    pub steps: u64,
+    pub elapsed: Duration,
+}
+
+impl Measurement
+{
+    pub fn per_second(&self) -> f64
+    {
+        self.steps as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+}
+
+/// How the VM runs the program.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode
+{
+    /// Every fetched word dispatched through `OPCODE_TABLE`.
+    Table,
+    /// Predecoded instruction cache.
+    Decoded,
+    /// Translated hot blocks on top of the predecoded cache.
+    Jit,
+}
+
+pub const MODES: [Mode; 3] = [Mode::Table, Mode::Decoded, Mode::Jit];
+
+impl Mode
+{
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Mode::Table => "table",
+            Mode::Decoded => "decoded",
+            Mode::Jit => "jit",
+        }
+    }
+}
+
+/// VM with `program` loaded, no keyboard input and output thrown away.
+pub fn load(program: &Program, mode: Mode) -> VM
+{
+    let mut vm = VM::new();
+    vm.set_predecode(mode != Mode::Table);
+    if mode == Mode::Jit
+    {
+        vm.set_jit(Jit::new(jit::DEFAULT_THRESHOLD));
+    }
+    vm.set_keyboard(Keyboard::stream(io::empty()));
+    vm.set_display(Display::discard());
+    for (origin, words) in &program.segments
+    {
+        for (i, &word) in words.iter().enumerate()
+        {
+            vm.memory_write(origin.wrapping_add(i as u16), word);
+        }
+    }
+    vm.register_write(Registers::R_PC.into(), program.start);
+    vm
+}
+
+/// Instructions a program may run before `measure` gives up on it, far
+/// more than any of the built-in programs need.
+pub const DEFAULT_MAX_STEPS: u64 = 100_000_000;
+
+/// Runs `program` to HALT, timing only the fetch/dispatch loop. A program
+/// still running after `max_steps` instructions, e.g. one waiting for a key
+/// that never comes, is an error.
+pub fn measure(program: &Program, mode: Mode, max_steps: u64) -> Result<Measurement, String>
+{
+    let mut vm = load(program, mode);
+    let start = Instant::now();
+    while vm.state_read() && vm.steps() < max_steps
+    {
+        vm.run_block();
+    }
+    if vm.state_read()
+    {
+        return Err(format!("{} did not halt within {max_steps} steps", program.name));
+    }
+    Ok(Measurement { steps: vm.steps(), elapsed: start.elapsed() })
+}
+
+/// Best of `repeat` runs, which is the least disturbed by other load.
+pub fn best_of(program: &Program, mode: Mode, repeat: usize, max_steps: u64) -> Result<Measurement, String>
+{
+    let runs = (0..repeat.max(1)).map(|_| measure(program, mode, max_steps)).collect::<Result<Vec<_>, _>>()?;
+    Ok(runs.into_iter().min_by_key(|m| m.elapsed).unwrap())
+}
+
+/// Throughput of one program in each of `MODES`, in instructions per second.
+pub struct Row
+{
+    pub name: String,
+    pub steps: u64,
+    pub rates: [f64; 3],
+}
+
+impl Row
+{
+    pub fn rate(&self, mode: Mode) -> f64
+    {
+        self.rates[mode as usize]
+    }
+}
+
+pub fn run_suite(programs: &[Program], repeat: usize, max_steps: u64) -> Result<Vec<Row>, String>
+{
+    programs
+        .iter()
+        .map(|program|
+        {
+            let [table, decoded, jit] = MODES.map(|mode| best_of(program, mode, repeat, max_steps));
+            let measurements = [table?, decoded?, jit?];
+            Ok(Row { name: program.name.clone(), steps: measurements[0].steps, rates: measurements.map(|m| m.per_second()) })
+        })
+        .collect()
+}
+
+/// Instructions per second saved by an earlier run, keyed by program and
+/// mode:
+///
+/// ```text
+/// # lc3box bench baseline v1
+/// arithmetic table 92200000
+/// arithmetic decoded 102200000
+/// arithmetic jit 131800000
+/// "my programs/sort.obj" table 88400000
+/// ```
+///
+/// Names with whitespace, `#`, `"` or `\` are quoted, with `\` escaping the
+/// last two.
+#[derive(Default, PartialEq, Debug)]
+pub struct Baseline
+{
+    rates: BTreeMap<(String, String), f64>,
+}
+
+pub const BASELINE_HEADER: &str = "# lc3box bench baseline v1";
+
+fn quote(name: &str) -> String
+{
+    if !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || matches!(c, '#' | '"' | '\\'))
+    {
+        return name.to_string();
+    }
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The program name at the start of `line`, quoted or not, and the rest of the line.
+fn split_name(line: &str) -> Option<(String, &str)>
+{
+    let Some(quoted) = line.strip_prefix('"') else
+    {
+        return line.split_once(char::is_whitespace).map(|(name, rest)| (name.to_string(), rest));
+    };
+    let mut name = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next()
+    {
+        match c
+        {
+            '"' => return Some((name, &quoted[i + 1..])),
+            '\\' => name.push(chars.next()?.1),
+            c => name.push(c),
+        }
+    }
+    None
+}
+
+impl Baseline
+{
+    pub fn from_rows(rows: &[Row]) -> Self
+    {
+        let mut rates = BTreeMap::new();
+        for row in rows
+        {
+            for mode in MODES
+            {
+                rates.insert((row.name.clone(), mode.name().to_string()), row.rate(mode));
+            }
+        }
+        Self { rates }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String>
+    {
+        let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        Self::parse(&text).map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String>
+    {
+        let mut rates = BTreeMap::new();
+        for (number, line) in text.lines().enumerate()
+        {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#')
+            {
+                continue;
+            }
+            let error = || format!("line {}: expected `<program> <table|decoded|jit> <instructions per second>`", number + 1);
+            let (name, rest) = split_name(line).ok_or_else(error)?;
+            let fields: Vec<&str> = rest.split('#').next().unwrap_or("").split_whitespace().collect();
+            let [mode @ ("table" | "decoded" | "jit"), rate] = fields[..] else
+            {
+                return Err(error());
+            };
+            let rate = rate.parse::<f64>().ok().filter(|rate| *rate > 0.0).ok_or_else(error)?;
+            rates.insert((name, mode.to_string()), rate);
+        }
+        Ok(Self { rates })
+    }
+
+    pub fn to_text(&self) -> String
+    {
+        let mut text = format!("{BASELINE_HEADER}\n");
+        for ((name, mode), rate) in &self.rates
+        {
+            text += &format!("{} {mode} {rate:.0}\n", quote(name));
+        }
+        text
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String>
+    {
+        fs::write(path, self.to_text()).map_err(|e| format!("{path}: {e}"))
+    }
+
+    /// Percent change of `rate` against the saved rate, positive when faster.
+    pub fn change(&self, name: &str, mode: &str, rate: f64) -> Option<f64>
+    {
+        self.rates.get(&(name.to_string(), mode.to_string())).map(|base| (rate / base - 1.0) * 100.0)
+    }
+}
+
+fn change_column(baseline: Option<&Baseline>, name: &str, mode: &str, rate: f64) -> String
+{
+    match baseline.map(|baseline| baseline.change(name, mode, rate))
+    {
+        Some(Some(change)) => format!("{change:+.1}%"),
+        Some(None) => "new".to_string(),
+        None => String::new(),
+    }
+}
+
+/// Table of throughput in millions of instructions per second and the
+/// speedup over `OPCODE_TABLE` dispatch, with the change against `baseline`
+/// when one is given.
+pub fn report(rows: &[Row], baseline: Option<&Baseline>) -> String
+{
+    let mut out = format!(
+        "{:<16} {:>14} {:>12} {:>12} {:>10} {:>8} {:>8}",
+        "program", "instructions", "table MIPS", "decoded MIPS", "jit MIPS", "decoded", "jit"
+    );
+    if baseline.is_some()
+    {
+        out += &format!(" {:>9} {:>9} {:>9}", "table", "decoded", "jit");
+    }
+    out += "\n";
+    for row in rows
+    {
+        let table = row.rate(Mode::Table).max(1e-9);
+        out += &format!(
+            "{:<16} {:>14} {:>12.1} {:>12.1} {:>10.1} {:>7.2}x {:>7.2}x",
+            row.name,
+            row.steps,
+            row.rate(Mode::Table) / 1e6,
+            row.rate(Mode::Decoded) / 1e6,
+            row.rate(Mode::Jit) / 1e6,
+            row.rate(Mode::Decoded) / table,
+            row.rate(Mode::Jit) / table
+        );
+        for mode in MODES.iter().filter(|_| baseline.is_some())
+        {
+            out += &format!(" {:>9}", change_column(baseline, &row.name, mode.name(), row.rate(*mode)));
+        }
+        out += "\n";
+    }
+    out
+}
+
+/// Programs and modes that got more than `max_percent` slower than `baseline`.
+pub fn regressions(rows: &[Row], baseline: &Baseline, max_percent: f64) -> Vec<String>
+{
+    let mut slower = Vec::new();
+    for row in rows
+    {
+        for mode in MODES
+        {
+            if let Some(change) = baseline.change(&row.name, mode.name(), row.rate(mode))
+                && change < -max_percent
+            {
+                slower.push(format!("{} ({}) is {:.1}% slower than the baseline", row.name, mode.name(), -change));
+            }
+        }
+    }
+    slower
+}
+
+#[cfg(test)]
+mod tests;
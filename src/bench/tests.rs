@@ -0,0 +1,99 @@
+use super::*;
+
+fn run(program: &Program) -> VM {
+    let mut vm = load(program, Mode::Decoded);
+    vm.set_display(Display::captured_only());
+    while vm.state_read() {
+        vm.step();
+    }
+    vm
+}
+
+#[test]
+fn test_builtin_programs_compute_the_right_thing() {
+    let vm = run(&arithmetic_loop(3, 4));
+    assert_eq!(vm.steps(), 2 + 3 * (3 + 4 * 5) + 1);
+
+    let mut vm = run(&checksum(2));
+    let sum = (0..256u16).fold(0u16, |sum, i| sum.wrapping_add(i * 7));
+    assert_eq!(vm.register_read(0), sum.wrapping_mul(2));
+
+    let vm = run(&memory_copy(2));
+    assert!((0..256).all(|i| vm.memory_peek(0x3200 + i) == i ^ 0x5A5A));
+
+    let mut vm = run(&fibonacci(10));
+    assert_eq!(vm.register_read(1), 55);
+    assert_eq!(vm.register_read(6), 0x4000);
+
+    let mut vm = run(&string_output(3));
+    assert_eq!(vm.display().captured(), GREETING.repeat(3).as_bytes());
+}
+
+#[test]
+fn test_all_modes_agree() {
+    for program in [arithmetic_loop(7, 9), checksum(3), memory_copy(1), fibonacci(8), string_output(2)] {
+        let table = measure(&program, Mode::Table, DEFAULT_MAX_STEPS).unwrap();
+        for mode in [Mode::Decoded, Mode::Jit] {
+            assert_eq!(measure(&program, mode, DEFAULT_MAX_STEPS).unwrap().steps, table.steps, "{} {mode:?}", program.name);
+        }
+    }
+}
+
+#[test]
+fn test_programs_that_never_halt_time_out() {
+    // Waits for a key, but the benchmark keyboard has none
+    let program = Program::at_x3000("waits", vec![0xA002, 0x07FE, 0xF025, 0xFE00]);
+    for mode in MODES {
+        assert_eq!(measure(&program, mode, 1000).err().unwrap(), "waits did not halt within 1000 steps");
+    }
+    assert!(run_suite(&[arithmetic_loop(2, 2), program], 1, 1000).is_err());
+}
+
+#[test]
+fn test_report_lists_every_program() {
+    let rows = run_suite(&[arithmetic_loop(2, 2), checksum(1)], 1, DEFAULT_MAX_STEPS).unwrap();
+    let report = report(&rows, None);
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("arithmetic") && lines[1].contains(" 29 "));
+    assert!(lines[2].starts_with("checksum") && lines[2].ends_with('x'));
+}
+
+fn row(name: &str, table: f64, decoded: f64, jit: f64) -> Row {
+    Row { name: name.to_string(), steps: 1, rates: [table, decoded, jit] }
+}
+
+#[test]
+fn test_baseline_round_trip_and_comparison() {
+    let baseline = Baseline::from_rows(&[row("loop", 50e6, 100e6, 200e6)]);
+    let text = baseline.to_text();
+    assert_eq!(text, "# lc3box bench baseline v1\nloop decoded 100000000\nloop jit 200000000\nloop table 50000000\n");
+    assert_eq!(Baseline::parse(&text).unwrap(), baseline);
+
+    let rows = [row("loop", 55e6, 80e6, 200e6), row("other", 1e6, 1e6, 1e6)];
+    let report = report(&rows, Some(&baseline));
+    assert!(report.lines().nth(1).unwrap().ends_with("+10.0%    -20.0%     +0.0%"));
+    assert!(report.lines().nth(2).unwrap().ends_with("new       new       new"));
+
+    assert_eq!(regressions(&rows, &baseline, 25.0), Vec::<String>::new());
+    assert_eq!(regressions(&rows, &baseline, 5.0), ["loop (decoded) is 20.0% slower than the baseline"]);
+}
+
+#[test]
+fn test_baseline_quotes_names() {
+    let baseline = Baseline::from_rows(&[row("my programs/a#1.obj", 1e6, 2e6, 3e6), row("say \"hi\"", 1e6, 1e6, 1e6)]);
+    let text = baseline.to_text();
+    assert!(text.contains("\n\"my programs/a#1.obj\" jit 3000000\n"));
+    assert!(text.contains("\n\"say \\\"hi\\\"\" table 1000000\n"));
+    assert_eq!(Baseline::parse(&text).unwrap(), baseline);
+    assert!(Baseline::parse("\"unterminated table 100").is_err());
+}
+
+#[test]
+fn test_baseline_parse_errors() {
+    assert!(Baseline::parse("loop fast 100").is_err());
+    assert_eq!(
+        Baseline::parse("\nloop table soon").unwrap_err(),
+        "line 2: expected `<program> <table|decoded|jit> <instructions per second>`"
+    );
+}
@@ -0,0 +1,136 @@
+use crate::disk::SECTOR_WORDS;
+use crate::hardware::Registers;
+use crate::vm::VM;
+
+/// The boot ROM occupies xFD00-xFDFF, just below the device page.
+pub const ROM_START: u16 = 0xFD00;
+pub const ROM_WORDS: usize = 256;
+/// Where execution starts after reset: the first word of the ROM.
+pub const RESET_VECTOR: u16 = ROM_START;
+
+/// First word of a bootable disk, "L3".
+pub const BOOT_MAGIC: u16 = 0x4C33;
+/// Where the ROM reads the boot table to. Programs may be loaded over it.
+pub const BOOT_SCRATCH: u16 = 0xFC00;
+/// Entries that fit in the boot table after the magic and entry point.
+pub const MAX_ENTRIES: usize = (SECTOR_WORDS as usize - 2) / 3;
+
+/// The boot program. Sector 0 of the disk holds the boot table:
+///
+/// | word    | contents                                              |
+/// |---------|-------------------------------------------------------|
+/// | 0       | `BOOT_MAGIC`                                          |
+/// | 1       | entry point                                           |
+/// | 2+3i    | entry i: first sector, sector count, load address     |
+///
+/// The table ends at the first entry with a count of 0. The ROM loads every
+/// entry's sectors one after another to consecutive addresses, then jumps to
+/// the entry point in supervisor mode. It keeps everything in registers and
+/// reads the table again before each entry, so an image may be loaded over
+/// `BOOT_SCRATCH`. Without the magic, or when a transfer fails, it prints
+/// "Boot failed" and halts.
+pub const BOOT_ROM: [u16; 59] = [
+    0x5260, // xFD00 BOOT     AND R1, R1, #0     ; R1 = offset of the next entry
+    0x54A0, // xFD01 NEXT     AND R2, R2, #0     ; read the boot table from sector 0
+    0x2823, // xFD02          LD R4, SCRATCH
+    0x4815, // xFD03          JSR DMA
+    0x2C21, // xFD04          LD R6, SCRATCH
+    0x6180, // xFD05          LDR R0, R6, #0
+    0x2E20, // xFD06          LD R7, NMAGIC
+    0x1007, // xFD07          ADD R0, R0, R7
+    0x0A1A, // xFD08          BRnp FAIL          ; not a boot disk
+    0x6B81, // xFD09          LDR R5, R6, #1     ; R5 = entry point
+    0x1D81, // xFD0A          ADD R6, R6, R1
+    0x6582, // xFD0B          LDR R2, R6, #2     ; R2 = first sector
+    0x6783, // xFD0C          LDR R3, R6, #3     ; R3 = sector count
+    0x6984, // xFD0D          LDR R4, R6, #4     ; R4 = load address
+    0x1263, // xFD0E          ADD R1, R1, #3
+    0x16E0, // xFD0F          ADD R3, R3, #0
+    0x0407, // xFD10          BRz START          ; end of the table
+    0x4807, // xFD11 LOAD     JSR DMA
+    0x14A1, // xFD12          ADD R2, R2, #1
+    0x2014, // xFD13          LD R0, WORDS
+    0x1900, // xFD14          ADD R4, R4, R0
+    0x16FF, // xFD15          ADD R3, R3, #-1
+    0x03FA, // xFD16          BRp LOAD
+    0x0FE9, // xFD17          BRnzp NEXT
+    0xC140, // xFD18 START    JMP R5
+    0xB411, // xFD19 DMA      STI R2, PSECTOR    ; read sector R2 to R4
+    0xB811, // xFD1A          STI R4, PADDRESS
+    0x5020, // xFD1B          AND R0, R0, #0
+    0x1021, // xFD1C          ADD R0, R0, #1
+    0xB00C, // xFD1D          STI R0, PCOMMAND
+    0xA00A, // xFD1E WAIT     LDI R0, PSTATUS
+    0x07FE, // xFD1F          BRzp WAIT          ; until ready
+    0x5021, // xFD20          AND R0, R0, #1
+    0x0201, // xFD21          BRp FAIL           ; error bit
+    0xC1C0, // xFD22          RET
+    0xE009, // xFD23 FAIL     LEA R0, MESSAGE
+    0xF022, // xFD24          PUTS
+    0xF025, // xFD25          HALT
+    0xFC00, // xFD26 SCRATCH  .FILL xFC00
+    0xB3CD, // xFD27 NMAGIC   .FILL -x4C33
+    0x0100, // xFD28 WORDS    .FILL #256
+    0xFE10, // xFD29 PSTATUS  .FILL xFE10
+    0xFE12, // xFD2A PCOMMAND .FILL xFE12
+    0xFE14, // xFD2B PSECTOR  .FILL xFE14
+    0xFE16, // xFD2C PADDRESS .FILL xFE16
+    0x000A, 0x0042, 0x006F, 0x006F, 0x0074, 0x0020, 0x0066, 0x0061, 0x0069, 0x006C, 0x0065, 0x0064, 0x000A, 0x0000,
+    // xFD2D MESSAGE .STRINGZ "\nBoot failed\n"
+];
+
+/// Puts `rom` in the ROM region and resets the VM to run it: PC at the
+/// reset vector and TRAPs going through the vector table the booted OS
+/// fills in. The disk must already be attached.
+pub fn boot(vm: &mut VM, rom: &[u16]) -> Result<(), String>
+{
+    if rom.len() > ROM_WORDS
+    {
+        return Err(format!("boot ROM of {} words does not fit in {ROM_WORDS}", rom.len()));
+    }
+    let mut words = rom.to_vec();
+    words.resize(ROM_WORDS, 0);
+    vm.set_rom(ROM_START, &words);
+    vm.set_vectored_traps(true);
+    vm.register_write(Registers::R_PC.into(), RESET_VECTOR);
+    Ok(())
+}
+
+/// A bootable disk image holding `images`, each an origin and its words,
+/// that starts at `entry`. Later images are loaded after earlier ones, so
+/// they win where the zero padding of a last sector overlaps them.
+pub fn make_disk(images: &[(u16, Vec<u16>)], entry: u16) -> Result<Vec<u8>, String>
+{
+    if images.len() > MAX_ENTRIES
+    {
+        return Err(format!("a boot disk holds at most {MAX_ENTRIES} images"));
+    }
+    let mut table = vec![BOOT_MAGIC, entry];
+    let mut sectors: Vec<Vec<u16>> = Vec::new();
+    for (origin, words) in images
+    {
+        let chunks: Vec<Vec<u16>> = words.chunks(SECTOR_WORDS as usize).map(<[u16]>::to_vec).collect();
+        if chunks.is_empty()
+        {
+            continue;
+        }
+        if *origin as usize + chunks.len() * SECTOR_WORDS as usize > 0xFE00
+        {
+            return Err(format!("image at x{origin:04X} reaches the device page once padded to whole sectors"));
+        }
+        table.extend([sectors.len() as u16 + 1, chunks.len() as u16, *origin]);
+        sectors.extend(chunks);
+    }
+    table.extend([0, 0, 0]);
+    table.resize(SECTOR_WORDS as usize, 0);
+    let mut bytes: Vec<u8> = table.iter().flat_map(|word| word.to_be_bytes()).collect();
+    for mut sector in sectors
+    {
+        sector.resize(SECTOR_WORDS as usize, 0);
+        bytes.extend(sector.iter().flat_map(|word| word.to_be_bytes()));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,110 @@
+use super::*;
+
+use std::io::Cursor;
+
+use crate::disk::Disk;
+use crate::display::Display;
+use crate::snapshot::Snapshot;
+
+/// OUT at x0400 as an OS would write it: poll DSR, write DDR, RTI.
+const OUT_ROUTINE: [u16; 6] = [0xA203, 0x07FE, 0xB002, 0x8000, 0xFE04, 0xFE06];
+
+/// Sets up a stack, prints 'A' with TRAP x21 and halts.
+const PROGRAM: [u16; 6] = [0x2C03, 0x2003, 0xF021, 0xF025, 0x2F00, 0x0041];
+
+fn booted(disk: Vec<u8>) -> VM {
+    let mut vm = VM::new();
+    vm.set_display(Display::captured_only());
+    vm.set_disk(Disk::new(Box::new(Cursor::new(disk))).unwrap());
+    boot(&mut vm, &BOOT_ROM).unwrap();
+    vm
+}
+
+fn run(vm: &mut VM) {
+    while vm.state_read() && vm.steps() < 100_000 {
+        vm.step();
+    }
+    assert!(!vm.state_read());
+}
+
+#[test]
+fn test_boots_os_and_program_from_disk() {
+    let images = [(0x0021, vec![0x0400]), (0x0400, OUT_ROUTINE.to_vec()), (0x3000, PROGRAM.to_vec())];
+    let mut vm = booted(make_disk(&images, 0x3000).unwrap());
+    assert_eq!(vm.register_read(Registers::R_PC.into()), RESET_VECTOR);
+    run(&mut vm);
+    assert_eq!(vm.fault(), None);
+    assert_eq!(vm.display().captured(), b"A");
+    // The OS routine ran, not the built-in one
+    assert_eq!(vm.register_read(1), 0x8000);
+    assert_eq!(vm.register_read(6), 0x2F00);
+    assert_eq!(vm.memory_peek(0x0400), OUT_ROUTINE[0]);
+}
+
+#[test]
+fn test_disk_layout() {
+    let bytes = make_disk(&[(0x3000, vec![0x1234; 300]), (0x4000, vec![0xABCD])], 0x3000).unwrap();
+    assert_eq!(bytes.len(), 4 * 512);
+    let word = |index: usize| u16::from_be_bytes([bytes[2 * index], bytes[2 * index + 1]]);
+    let table: Vec<u16> = (0..12).map(word).collect();
+    assert_eq!(table, [BOOT_MAGIC, 0x3000, 1, 2, 0x3000, 3, 1, 0x4000, 0, 0, 0, 0]);
+    assert_eq!(word(256 + 299), 0x1234);
+    assert_eq!(word(256 + 300), 0);
+    assert_eq!(word(3 * 256), 0xABCD);
+}
+
+#[test]
+fn test_rom_is_read_only() {
+    let mut vm = booted(vec![0; 512]);
+    vm.memory_write(ROM_START, 0x1234);
+    assert_eq!(vm.memory_peek(ROM_START), BOOT_ROM[0]);
+    assert_eq!(vm.memory_peek(ROM_START + BOOT_ROM.len() as u16), 0);
+    vm.memory_write(ROM_START - 1, 0x1234);
+    assert_eq!(vm.memory_peek(ROM_START - 1), 0x1234);
+}
+
+#[test]
+fn test_blank_disk_fails_to_boot() {
+    let mut vm = booted(vec![0; 512]);
+    run(&mut vm);
+    assert_eq!(vm.fault(), None);
+    assert_eq!(vm.display().captured(), b"\nBoot failed\n");
+}
+
+#[test]
+fn test_make_disk_errors() {
+    assert!(make_disk(&[(0xFD01, vec![1])], 0x3000).is_err());
+    assert!(make_disk(&vec![(0x3000, vec![1]); MAX_ENTRIES + 1], 0x3000).is_err());
+    assert!(make_disk(&vec![(0x3000, vec![1]); MAX_ENTRIES], 0x3000).is_ok());
+}
+
+#[test]
+fn test_rom_must_fit() {
+    let mut vm = VM::new();
+    assert!(boot(&mut vm, &[0; ROM_WORDS + 1]).is_err());
+}
+
+#[test]
+fn test_booted_machine_resumes_from_snapshot() {
+    // TRAP x26 has no built-in routine, only the OS's
+    let program = [0x2C03, 0x2003, 0xF026, 0xF025, 0x2F00, 0x0041];
+    let images = [(0x0026, vec![0x0400]), (0x0400, OUT_ROUTINE.to_vec()), (0x3000, program.to_vec())];
+    let disk = make_disk(&images, 0x3000).unwrap();
+    let mut vm = booted(disk.clone());
+    while vm.register_read(Registers::R_PC.into()) != 0x3000 {
+        vm.step();
+    }
+    let snapshot = Snapshot::from_bytes(&Snapshot::capture(&mut vm).to_bytes()).unwrap();
+    assert_eq!(snapshot.rom, Some((ROM_START, ROM_START + ROM_WORDS as u16 - 1)));
+
+    let mut resumed = VM::new();
+    resumed.set_display(Display::captured_only());
+    resumed.set_disk(Disk::new(Box::new(Cursor::new(disk))).unwrap());
+    snapshot.restore(&mut resumed).unwrap();
+    run(&mut resumed);
+    assert_eq!(resumed.fault(), None);
+    assert_eq!(resumed.display().captured(), b"A");
+    assert_eq!(resumed.register_read(1), 0x8000);
+    resumed.memory_write(ROM_START, 0x1234);
+    assert_eq!(resumed.memory_peek(ROM_START), BOOT_ROM[0]);
+}
@@ -0,0 +1,510 @@
+use crate::bench;
+use crate::protection::Protection;
+use crate::timing::TimingModel;
+use crate::trace::{TraceFilter, TraceFormat};
+
+pub enum Command
+{
+    /// `lc3box [run] [options] image...` or `lc3box resume [options] snapshot`
+    Run(Options),
+    /// `lc3box test suite.toml [--json report.json] [--junit report.xml]`
+    Test(TestOptions),
+    /// `lc3box profile [--sym prog.sym] [--folded out.folded] [--top N] [options] image...`
+    Profile(ProfileOptions),
+    /// `lc3box coverage [--sym prog.sym] [--source-map prog.map] [--lcov out.info] [options] image...`
+    Coverage(CoverageOptions),
+    /// `lc3box micro [--dump] [--dump-file out.txt] [--check] [options] image...`
+    Micro(MicroOptions),
+    /// `lc3box tui [--sym prog.sym] [--break ADDR] [options] image...`
+    Tui(TuiOptions),
+    /// `lc3box bench [--repeat N] [--max-steps N] [--baseline file] [--save-baseline file] [--max-regression PCT] [image...]`
+    Bench(BenchOptions),
+    /// `lc3box mkdisk [--entry ADDR] disk.img image...`
+    Mkdisk(MkdiskOptions),
+}
+
+/// Command line options for a VM run.
+#[derive(Default)]
+pub struct Options
+{
+    pub images: Vec<String>,
+    /// Run without touching the terminal and read input from the stdin byte stream.
+    pub headless: bool,
+    /// Keystrokes with the step at which each becomes available, see `InputScript`.
+    pub input_script: Option<String>,
+    /// File receiving a copy of everything the program prints.
+    pub output: Option<String>,
+    /// Golden file the program output is compared against at HALT.
+    pub expect: Option<String>,
+    /// Log every executed instruction, to stderr unless `trace_file` is set.
+    pub trace: bool,
+    pub trace_file: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_filter: TraceFilter,
+    /// Count cycles with the microsequencer timing model and report them at exit.
+    pub timing: Option<TimingModel>,
+    /// Log every keyboard answer here, see `InputLog`.
+    pub record_input: Option<String>,
+    /// Take keyboard input from a log written by `record_input`.
+    pub replay_input: Option<String>,
+    /// Save a `Snapshot` of the VM here once the program halts.
+    pub snapshot_on_halt: Option<String>,
+    /// Continue from this snapshot instead of loading images.
+    pub resume: Option<String>,
+    /// Dispatch every instruction through `OPCODE_TABLE` instead of the decoded cache.
+    pub no_predecode: bool,
+    /// Translate hot blocks, see `Jit`.
+    pub jit: bool,
+    /// Service the convenience traps x26-x29, see `VM::set_extended_traps`.
+    pub extended_traps: bool,
+    /// Directory LC-3 programs may open files in through the host file traps, see `hostfs`.
+    pub sandbox: Option<String>,
+    /// Image file backing the disk device, see `Disk`.
+    pub disk: Option<String>,
+    /// Start at the reset vector in the boot ROM, which loads the programs from the disk.
+    pub boot: bool,
+    /// Object file to boot with instead of the built-in `BOOT_ROM`.
+    pub rom: Option<String>,
+    /// Check the memory accesses of user mode, see `VM::set_access_control`. Implied by `strict`.
+    pub access_control: bool,
+    /// Start the loaded images in user mode.
+    pub user_mode: bool,
+    /// Images among `images` with all their words protected.
+    pub protected_images: Vec<(String, Protection)>,
+    /// Address ranges protected without loading anything.
+    pub protected_ranges: Vec<(u16, u16, Protection)>,
+    /// Run the LC-3 exactly as specified, refusing extensions such as `extended_traps`
+    /// and checking accesses from user mode.
+    pub strict: bool,
+}
+
+#[derive(Default)]
+pub struct TestOptions
+{
+    pub suite: String,
+    pub json: Option<String>,
+    pub junit: Option<String>,
+}
+
+pub struct ProfileOptions
+{
+    pub run: Options,
+    pub symbols: Vec<String>,
+    pub folded: Option<String>,
+    pub top: usize,
+}
+
+pub struct CoverageOptions
+{
+    pub run: Options,
+    pub symbols: Vec<String>,
+    pub source_map: Option<String>,
+    pub lcov: Option<String>,
+}
+
+pub struct MicroOptions
+{
+    pub run: Options,
+    /// Print the datapath after every microstep, to stderr unless `dump_file` is set.
+    pub dump: bool,
+    pub dump_file: Option<String>,
+    /// Compare every instruction against the ISA-level implementation.
+    pub check: bool,
+}
+
+pub struct TuiOptions
+{
+    pub run: Options,
+    pub symbols: Vec<String>,
+    /// Addresses or labels to stop at.
+    pub breakpoints: Vec<String>,
+}
+
+pub struct BenchOptions
+{
+    /// Object files to time instead of the built-in programs.
+    pub images: Vec<String>,
+    /// Runs per program and mode, the fastest is reported.
+    pub repeat: usize,
+    /// Earlier results to compare against, see `Baseline`.
+    pub baseline: Option<String>,
+    pub save_baseline: Option<String>,
+    /// Fail when a program got more than this many percent slower than `baseline`.
+    pub max_regression: Option<f64>,
+    /// Instructions a program may run before it counts as hung.
+    pub max_steps: u64,
+}
+
+pub struct MkdiskOptions
+{
+    pub output: String,
+    pub images: Vec<String>,
+    /// Where the boot ROM jumps once everything is loaded, the origin of the first image by default.
+    pub entry: Option<u16>,
+}
+
+pub fn parse(args: &[String]) -> Result<Command, String>
+{
+    match args.first().map(String::as_str)
+    {
+        Some("run") => parse_run(&args[1..]).map(Command::Run),
+        Some("resume") => parse_resume(&args[1..]).map(Command::Run),
+        Some("test") => parse_test(&args[1..]).map(Command::Test),
+        Some("profile") => parse_profile(&args[1..]).map(Command::Profile),
+        Some("coverage") => parse_coverage(&args[1..]).map(Command::Coverage),
+        Some("micro") => parse_micro(&args[1..]).map(Command::Micro),
+        Some("tui") => parse_tui(&args[1..]).map(Command::Tui),
+        Some("bench") => parse_bench(&args[1..]).map(Command::Bench),
+        Some("mkdisk") => parse_mkdisk(&args[1..]).map(Command::Mkdisk),
+        _ => parse_run(args).map(Command::Run),
+    }
+}
+
+fn parse_test(args: &[String]) -> Result<TestOptions, String>
+{
+    let mut options = TestOptions::default();
+    let mut suite = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--json" => options.json = Some(value(arg, args.next())?),
+            "--junit" => options.junit = Some(value(arg, args.next())?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option: {flag}")),
+            path if suite.is_none() => suite = Some(path.to_string()),
+            extra => return Err(format!("unexpected argument: {extra}")),
+        }
+    }
+    options.suite = suite.ok_or("test expects a suite file")?;
+    Ok(options)
+}
+
+fn parse_profile(args: &[String]) -> Result<ProfileOptions, String>
+{
+    let mut symbols = Vec::new();
+    let mut folded = None;
+    let mut top = 20;
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--sym" => symbols.push(value(arg, args.next())?),
+            "--folded" => folded = Some(value(arg, args.next())?),
+            "--top" => top = value(arg, args.next())?.parse().map_err(|_| "--top expects a number")?,
+            _ => rest.push(arg.clone()),
+        }
+    }
+    Ok(ProfileOptions { run: parse_run(&rest)?, symbols, folded, top })
+}
+
+fn parse_coverage(args: &[String]) -> Result<CoverageOptions, String>
+{
+    let mut symbols = Vec::new();
+    let mut source_map = None;
+    let mut lcov = None;
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--sym" => symbols.push(value(arg, args.next())?),
+            "--source-map" => source_map = Some(value(arg, args.next())?),
+            "--lcov" => lcov = Some(value(arg, args.next())?),
+            _ => rest.push(arg.clone()),
+        }
+    }
+    if lcov.is_some() && source_map.is_none()
+    {
+        return Err("--lcov needs a --source-map to know the source lines".to_string());
+    }
+    Ok(CoverageOptions { run: parse_run(&rest)?, symbols, source_map, lcov })
+}
+
+fn parse_micro(args: &[String]) -> Result<MicroOptions, String>
+{
+    let mut dump = false;
+    let mut dump_file = None;
+    let mut check = false;
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--dump" => dump = true,
+            "--dump-file" =>
+            {
+                dump = true;
+                dump_file = Some(value(arg, args.next())?);
+            }
+            "--check" => check = true,
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let run = parse_run(&rest)?;
+    if run.disk.is_some()
+    {
+        return Err("micro does not model the disk device".to_string());
+    }
+    if run.access_control || run.user_mode
+    {
+        return Err("micro does not model user mode access control".to_string());
+    }
+    if !run.protected_images.is_empty() || !run.protected_ranges.is_empty()
+    {
+        return Err("micro does not model protected memory".to_string());
+    }
+    if check && (run.extended_traps || run.sandbox.is_some())
+    {
+        return Err("--check compares against the standard TRAP routines only; drop --extended-traps and --sandbox".to_string());
+    }
+    Ok(MicroOptions { run, dump, dump_file, check })
+}
+
+fn parse_tui(args: &[String]) -> Result<TuiOptions, String>
+{
+    let mut symbols = Vec::new();
+    let mut breakpoints = Vec::new();
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--sym" => symbols.push(value(arg, args.next())?),
+            "--break" => breakpoints.push(value(arg, args.next())?),
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let run = parse_run(&rest)?;
+    let unsupported = [
+        ("--jit", run.jit),
+        ("--timing", run.timing.is_some()),
+        ("--trace", run.trace),
+        ("--record-input", run.record_input.is_some()),
+        ("--replay-input", run.replay_input.is_some()),
+        ("--expect", run.expect.is_some()),
+        ("--snapshot-on-halt", run.snapshot_on_halt.is_some()),
+        ("--no-predecode", run.no_predecode),
+    ];
+    if let Some((flag, _)) = unsupported.iter().find(|(_, given)| *given)
+    {
+        return Err(format!("tui does not support {flag}"));
+    }
+    Ok(TuiOptions { run, symbols, breakpoints })
+}
+
+fn parse_bench(args: &[String]) -> Result<BenchOptions, String>
+{
+    let mut options = BenchOptions
+    {
+        images: Vec::new(),
+        repeat: 3,
+        baseline: None,
+        save_baseline: None,
+        max_regression: None,
+        max_steps: bench::DEFAULT_MAX_STEPS,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--repeat" => options.repeat = value(arg, args.next())?.parse().map_err(|_| "--repeat expects a number")?,
+            "--baseline" => options.baseline = Some(value(arg, args.next())?),
+            "--save-baseline" => options.save_baseline = Some(value(arg, args.next())?),
+            "--max-steps" => options.max_steps = value(arg, args.next())?.parse().map_err(|_| "--max-steps expects a number")?,
+            "--max-regression" =>
+            {
+                let percent = value(arg, args.next())?.parse().map_err(|_| "--max-regression expects a percentage")?;
+                options.max_regression = Some(percent);
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option: {flag}")),
+            image => options.images.push(image.to_string()),
+        }
+    }
+    if options.max_regression.is_some() && options.baseline.is_none()
+    {
+        return Err("--max-regression needs a --baseline to compare against".to_string());
+    }
+    Ok(options)
+}
+
+fn parse_mkdisk(args: &[String]) -> Result<MkdiskOptions, String>
+{
+    let mut entry = None;
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--entry" => entry = Some(parse_number(&value(arg, args.next())?)?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option: {flag}")),
+            file => files.push(file.to_string()),
+        }
+    }
+    if files.len() < 2
+    {
+        return Err("mkdisk expects the disk image to write and at least one object file".to_string());
+    }
+    let output = files.remove(0);
+    Ok(MkdiskOptions { output, images: files, entry })
+}
+
+fn parse_resume(args: &[String]) -> Result<Options, String>
+{
+    let mut options = parse_run(args)?;
+    if !options.protected_images.is_empty()
+    {
+        return Err("resume keeps the protected regions of the snapshot; --read-only and --no-exec load images".to_string());
+    }
+    if options.images.len() != 1
+    {
+        return Err("resume expects exactly one snapshot file".to_string());
+    }
+    options.resume = options.images.pop();
+    Ok(options)
+}
+
+fn parse_run(args: &[String]) -> Result<Options, String>
+{
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--headless" => options.headless = true,
+            "--input-script" => options.input_script = Some(value(arg, args.next())?),
+            "--output" => options.output = Some(value(arg, args.next())?),
+            "--expect" => options.expect = Some(value(arg, args.next())?),
+            "--trace" => options.trace = true,
+            "--trace-json" =>
+            {
+                options.trace = true;
+                options.trace_format = TraceFormat::Json;
+            }
+            "--trace-file" =>
+            {
+                options.trace = true;
+                options.trace_file = Some(value(arg, args.next())?);
+            }
+            "--trace-range" => options.trace_filter.add_range(&value(arg, args.next())?)?,
+            "--trace-op" => options.trace_filter.add_opcodes(&value(arg, args.next())?)?,
+            "--timing" =>
+            {
+                options.timing.get_or_insert_with(TimingModel::default);
+            }
+            "--record-input" => options.record_input = Some(value(arg, args.next())?),
+            "--replay-input" => options.replay_input = Some(value(arg, args.next())?),
+            "--no-predecode" => options.no_predecode = true,
+            "--jit" => options.jit = true,
+            "--extended-traps" => options.extended_traps = true,
+            "--strict" => options.strict = true,
+            "--sandbox" => options.sandbox = Some(value(arg, args.next())?),
+            "--disk" => options.disk = Some(value(arg, args.next())?),
+            "--boot" => options.boot = true,
+            "--acv" => options.access_control = true,
+            "--user" => options.user_mode = true,
+            "--read-only" | "--no-exec" =>
+            {
+                let image = value(arg, args.next())?;
+                let protection = if arg == "--read-only" { Protection::ReadOnly } else { Protection::NoExecute };
+                options.images.push(image.clone());
+                options.protected_images.push((image, protection));
+            }
+            "--read-only-range" =>
+            {
+                let (first, last) = parse_range(&value(arg, args.next())?)?;
+                options.protected_ranges.push((first, last, Protection::ReadOnly));
+            }
+            "--no-exec-range" =>
+            {
+                let (first, last) = parse_range(&value(arg, args.next())?)?;
+                options.protected_ranges.push((first, last, Protection::NoExecute));
+            }
+            "--rom" =>
+            {
+                options.boot = true;
+                options.rom = Some(value(arg, args.next())?);
+            }
+            "--snapshot-on-halt" => options.snapshot_on_halt = Some(value(arg, args.next())?),
+            "--memory-latency" =>
+            {
+                let latency = value(arg, args.next())?.parse().map_err(|_| "--memory-latency expects a number of cycles")?;
+                options.timing.get_or_insert_with(TimingModel::default).memory_latency = latency;
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option: {flag}")),
+            image => options.images.push(image.to_string()),
+        }
+    }
+    let extensions = [("--extended-traps", options.extended_traps), ("--sandbox", options.sandbox.is_some()), ("--disk", options.disk.is_some())];
+    if options.strict
+        && let Some((flag, _)) = extensions.iter().find(|(_, used)| *used)
+    {
+        return Err(format!("{flag} is not part of the LC-3 and cannot be used with --strict"));
+    }
+    if options.boot && options.disk.is_none()
+    {
+        return Err("--boot needs a --disk to load the programs from".to_string());
+    }
+    if options.boot && options.user_mode
+    {
+        return Err("--user cannot be used with --boot, the booted OS enters user mode itself".to_string());
+    }
+    if options.boot && !options.images.is_empty()
+    {
+        return Err("--boot loads programs from the disk; put them on it with mkdisk".to_string());
+    }
+    Ok(options)
+}
+
+fn value(flag: &str, value: Option<&String>) -> Result<String, String>
+{
+    value.cloned().ok_or_else(|| format!("{flag} expects a value"))
+}
+
+/// Parses `x3000`, `0x3000`, `#-5` or `12`.
+pub fn parse_number(text: &str) -> Result<u16, String>
+{
+    let trimmed = text.trim();
+    let hex = trimmed.strip_prefix("0x").or_else(|| trimmed.strip_prefix('x')).or_else(|| trimmed.strip_prefix('X'));
+    let parsed = match hex
+    {
+        Some(digits) => i64::from_str_radix(digits, 16),
+        None => trimmed.strip_prefix('#').unwrap_or(trimmed).parse::<i64>(),
+    };
+    parsed.map_err(|_| format!("invalid number `{text}`")).and_then(|n| number(n, text))
+}
+
+/// Parses an inclusive range `x3000:x30FF`, or a single address.
+pub fn parse_range(text: &str) -> Result<(u16, u16), String>
+{
+    let (start, end) = match text.split_once(':')
+    {
+        Some((start, end)) => (parse_number(start)?, parse_number(end)?),
+        None => (parse_number(text)?, parse_number(text)?),
+    };
+    if start > end
+    {
+        return Err(format!("empty address range `{text}`"));
+    }
+    Ok((start, end))
+}
+
+fn number(n: i64, text: &str) -> Result<u16, String>
+{
+    if (-0x8000..=0xFFFF).contains(&n)
+    {
+        Ok(n as u16)
+    }
+    else
+    {
+        Err(format!("`{text}` does not fit in 16 bits"))
+    }
+}
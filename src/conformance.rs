@@ -0,0 +1,177 @@
+use std::io;
+
+use crate::disasm::disassemble;
+use crate::display::Display;
+use crate::hardware::{Registers, MEMORY_MAX};
+use crate::keyboard::Keyboard;
+use crate::vm::{ArchState, VM};
+
+mod reference;
+
+use reference::Reference;
+
+/// xorshift64*, enough to make reproducible random programs from a seed.
+struct Rng(u64);
+
+impl Rng
+{
+    fn new(seed: u64) -> Self
+    {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64
+    {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn word(&mut self) -> u16
+    {
+        (self.next() >> 32) as u16
+    }
+
+    /// A random instruction that both models implement: anything but TRAP,
+    /// RTI and the reserved opcode.
+    fn instruction(&mut self) -> u16
+    {
+        loop
+        {
+            let word = self.word();
+            if !matches!(word >> 12, 0x8 | 0xD | 0xF)
+            {
+                return word;
+            }
+        }
+    }
+}
+
+/// Memory full of random instructions, random registers and a random PC,
+/// so every encoding, address and wraparound gets exercised.
+fn random_state(rng: &mut Rng) -> ArchState
+{
+    let memory = (0..MEMORY_MAX).map(|_| rng.instruction()).collect();
+    let mut registers = [0; Registers::R_COUNT as usize];
+    for register in &mut registers[..8]
+    {
+        *register = rng.word();
+    }
+    registers[Registers::R_PC as usize] = rng.word();
+    registers[Registers::R_COND as usize] = 1 << (rng.next() % 3);
+    ArchState { registers, memory }
+}
+
+fn reference(state: &ArchState) -> Reference
+{
+    let mut reference = Reference {
+        r: state.registers[..8].try_into().unwrap(),
+        pc: state.registers[Registers::R_PC as usize],
+        n: false,
+        z: false,
+        p: false,
+        memory: state.memory.to_vec(),
+        output: Vec::new(),
+        touched: Vec::new(),
+    };
+    reference.set_cond(state.registers[Registers::R_COND as usize]);
+    reference
+}
+
+fn vm(state: &ArchState, predecode: bool) -> VM
+{
+    let mut vm = VM::new();
+    vm.set_predecode(predecode);
+    vm.set_display(Display::captured_only());
+    vm.set_keyboard(Keyboard::stream(io::empty()));
+    vm.set_arch_state(state);
+    vm
+}
+
+/// Runs the program from `seed` on the VM and the reference for up to
+/// `steps` instructions, comparing registers, flags and the memory each instruction
+/// touched after every step and all of memory at the end.
+fn check(seed: u64, steps: usize, predecode: bool) -> Result<(), String>
+{
+    let state = random_state(&mut Rng::new(seed));
+    let mut expected = reference(&state);
+    let mut actual = vm(&state, predecode);
+    for step in 0..steps
+    {
+        let pc = expected.pc;
+        let instruction = expected.memory[pc as usize];
+        if matches!(instruction >> 12, 0x8 | 0xD | 0xF)
+        {
+            // A store produced an instruction the reference does not model.
+            break;
+        }
+        expected.step();
+        actual.step();
+        let context = || format!("seed {seed}, step {step}, x{pc:04X}: {}", disassemble(pc, instruction));
+        for (i, &value) in expected.r.iter().enumerate()
+        {
+            let got = actual.register_read(i);
+            if got != value
+            {
+                return Err(format!("{}: R{i} is x{got:04X}, expected x{value:04X}", context()));
+            }
+        }
+        let got = actual.register_read(Registers::R_PC.into());
+        if got != expected.pc
+        {
+            return Err(format!("{}: PC is x{got:04X}, expected x{:04X}", context(), expected.pc));
+        }
+        let got = actual.register_read(Registers::R_COND.into());
+        if got != expected.cond()
+        {
+            return Err(format!("{}: COND is {got:03b}, expected {:03b}", context(), expected.cond()));
+        }
+        for &address in &expected.touched
+        {
+            let (got, value) = (actual.memory_peek(address), expected.memory[address as usize]);
+            if got != value
+            {
+                return Err(format!("{}: M[x{address:04X}] is x{got:04X}, expected x{value:04X}", context()));
+            }
+        }
+    }
+    if actual.arch_state().memory[..] != expected.memory[..]
+    {
+        return Err(format!("seed {seed}: memory differs at the end"));
+    }
+    if actual.display().captured() != expected.output
+    {
+        return Err(format!("seed {seed}: display output differs"));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_random_programs_match_reference() {
+    for seed in 0..150 {
+        check(seed, 400, true).unwrap();
+    }
+}
+
+#[test]
+fn test_random_programs_match_reference_with_table_dispatch() {
+    for seed in 1000..1150 {
+        check(seed, 400, false).unwrap();
+    }
+}
+
+#[test]
+fn test_reference_jsrr_r7_jumps_to_old_r7() {
+    let mut state = ArchState { registers: [0; Registers::R_COUNT as usize], memory: vec![0; MEMORY_MAX].into() };
+    state.memory[0x3000] = 0x41C0; // JSRR R7
+    state.registers[7] = 0x4000;
+    state.registers[Registers::R_PC as usize] = 0x3000;
+    let mut expected = reference(&state);
+    expected.step();
+    assert_eq!((expected.pc, expected.r[7]), (0x4000, 0x3001));
+    let mut actual = vm(&state, true);
+    actual.step();
+    assert_eq!(actual.register_read(Registers::R_PC.into()), 0x4000);
+    assert_eq!(actual.register_read(7), 0x3001);
+}
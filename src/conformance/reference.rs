@@ -0,0 +1,152 @@
+const KBSR: u16 = 0xFE00;
+const DSR: u16 = 0xFE04;
+const DDR: u16 = 0xFE06;
+
+/// The LC-3 ISA transcribed from Appendix A of Patt & Patel, written without
+/// reusing anything from the VM so the two can be compared.
+pub struct Reference
+{
+    pub r: [u16; 8],
+    pub pc: u16,
+    pub n: bool,
+    pub z: bool,
+    pub p: bool,
+    pub memory: Vec<u16>,
+    /// Bytes written to DDR.
+    pub output: Vec<u8>,
+    /// Addresses read or written by the last instruction, fetch included.
+    pub touched: Vec<u16>,
+}
+
+/// `SEXT(value[bits-1:0])`
+fn sext(value: u16, bits: u32) -> u16
+{
+    let sign = 1u16 << (bits - 1);
+    let field = value & ((1u32 << bits) - 1) as u16;
+    (field ^ sign).wrapping_sub(sign)
+}
+
+impl Reference
+{
+    /// Condition codes in the bit order of BR's nzp field.
+    pub fn cond(&self) -> u16
+    {
+        (self.n as u16) << 2 | (self.z as u16) << 1 | self.p as u16
+    }
+
+    pub fn set_cond(&mut self, cond: u16)
+    {
+        self.n = cond & 4 != 0;
+        self.z = cond & 2 != 0;
+        self.p = cond & 1 != 0;
+    }
+
+    /// Memory read, with no keyboard attached and the display always ready.
+    fn read(&mut self, address: u16) -> u16
+    {
+        self.touched.push(address);
+        match address
+        {
+            KBSR => self.memory[address as usize] = 0,
+            DSR => self.memory[address as usize] = 0x8000,
+            _ => {}
+        }
+        self.memory[address as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u16)
+    {
+        self.touched.push(address);
+        if address == DDR
+        {
+            self.output.push(value as u8);
+        }
+        self.memory[address as usize] = value;
+    }
+
+    fn setcc(&mut self, value: u16)
+    {
+        self.n = value & 0x8000 != 0;
+        self.z = value == 0;
+        self.p = !self.n && !self.z;
+    }
+
+    /// Executes one instruction. TRAP, RTI and the reserved opcode are not
+    /// modelled.
+    pub fn step(&mut self)
+    {
+        self.touched.clear();
+        let ir = self.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        let dr = (ir >> 9 & 7) as usize;
+        let sr1 = (ir >> 6 & 7) as usize;
+        let sr2 = (ir & 7) as usize;
+        let second = if ir & 0x20 != 0 { sext(ir, 5) } else { self.r[sr2] };
+        let pc_offset9 = self.pc.wrapping_add(sext(ir, 9));
+        let base_offset6 = self.r[sr1].wrapping_add(sext(ir, 6));
+        match ir >> 12
+        {
+            0b0000 =>
+            {
+                let n = ir & 0x0800 != 0;
+                let z = ir & 0x0400 != 0;
+                let p = ir & 0x0200 != 0;
+                if (n && self.n) || (z && self.z) || (p && self.p)
+                {
+                    self.pc = pc_offset9;
+                }
+            }
+            0b0001 =>
+            {
+                self.r[dr] = self.r[sr1].wrapping_add(second);
+                self.setcc(self.r[dr]);
+            }
+            0b0101 =>
+            {
+                self.r[dr] = self.r[sr1] & second;
+                self.setcc(self.r[dr]);
+            }
+            0b1001 =>
+            {
+                self.r[dr] = !self.r[sr1];
+                self.setcc(self.r[dr]);
+            }
+            0b0010 =>
+            {
+                self.r[dr] = self.read(pc_offset9);
+                self.setcc(self.r[dr]);
+            }
+            0b1010 =>
+            {
+                let address = self.read(pc_offset9);
+                self.r[dr] = self.read(address);
+                self.setcc(self.r[dr]);
+            }
+            0b0110 =>
+            {
+                self.r[dr] = self.read(base_offset6);
+                self.setcc(self.r[dr]);
+            }
+            0b1110 =>
+            {
+                self.r[dr] = pc_offset9;
+                self.setcc(self.r[dr]);
+            }
+            0b0011 => self.write(pc_offset9, self.r[dr]),
+            0b1011 =>
+            {
+                let address = self.read(pc_offset9);
+                self.write(address, self.r[dr]);
+            }
+            0b0111 => self.write(base_offset6, self.r[dr]),
+            0b1100 => self.pc = self.r[sr1],
+            0b0100 =>
+            {
+                let temp = self.pc;
+                self.pc = if ir & 0x0800 != 0 { self.pc.wrapping_add(sext(ir, 11)) } else { self.r[sr1] };
+                self.r[7] = temp;
+            }
+            opcode => panic!("the reference model does not implement opcode {opcode:04b}"),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::cli::parse_number;
+use crate::disasm::disassemble;
+use crate::hardware::MEMORY_MAX;
+use crate::symbols::SymbolTable;
+
+/// Which instructions ran, and which way every conditional branch went.
+pub struct Coverage
+{
+    executed: Vec<u64>,
+    branches: BTreeMap<u16, (u64, u64)>, // address -> (taken, not taken)
+}
+
+/// Maps instruction addresses to the source lines they were assembled from.
+/// One entry per line: `x3000 prog.asm:12`; `#` starts a comment. Only
+/// instruction addresses should be listed, data lines would count as never run.
+#[derive(Default)]
+pub struct SourceMap
+{
+    lines: BTreeMap<u16, (String, u32)>,
+}
+
+impl SourceMap
+{
+    pub fn load(path: &str) -> Result<Self, String>
+    {
+        let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        Self::parse(&text).map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String>
+    {
+        let mut map = Self::default();
+        for (number, line) in text.lines().enumerate()
+        {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty()
+            {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {message}", number + 1);
+            let (address, location) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| error("expected `<address> <file>:<line>`"))?;
+            let (file, source_line) = location.trim().rsplit_once(':').ok_or_else(|| error("expected `<file>:<line>`"))?;
+            let source_line = source_line.parse().map_err(|_| error("invalid line number"))?;
+            map.lines.insert(parse_number(address).map_err(|e| error(&e))?, (file.to_string(), source_line));
+        }
+        Ok(map)
+    }
+
+    pub fn addresses(&self) -> Vec<u16>
+    {
+        self.lines.keys().copied().collect()
+    }
+}
+
+/// True for BR instructions that can go either way: BRnzp always jumps and
+/// a BR testing no condition never does.
+fn is_conditional_branch(inst: u16) -> bool
+{
+    let nzp = (inst >> 9) & 7;
+    inst >> 12 == 0 && nzp != 0 && nzp != 7
+}
+
+impl Default for Coverage
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl Coverage
+{
+    pub fn new() -> Self
+    {
+        Self { executed: vec![0; MEMORY_MAX], branches: BTreeMap::new() }
+    }
+
+    /// `cond` is the condition code the instruction saw before it executed.
+    pub fn record(&mut self, pc: u16, inst: u16, cond: u16)
+    {
+        self.executed[pc as usize] += 1;
+        if is_conditional_branch(inst)
+        {
+            let branch = self.branches.entry(pc).or_default();
+            if (inst >> 9) & cond != 0
+            {
+                branch.0 += 1;
+            }
+            else
+            {
+                branch.1 += 1;
+            }
+        }
+    }
+
+    pub fn hits(&self, address: u16) -> u64
+    {
+        self.executed[address as usize]
+    }
+
+    /// (taken, not taken) counts of the conditional branch at `address`.
+    pub fn branch(&self, address: u16) -> (u64, u64)
+    {
+        self.branches.get(&address).copied().unwrap_or_default()
+    }
+
+    /// Text report over `addresses`, the instructions that make up the program.
+    /// `memory` supplies the words to disassemble.
+    pub fn report(&self, addresses: &[u16], symbols: &SymbolTable, map: Option<&SourceMap>, memory: impl Fn(u16) -> u16) -> String
+    {
+        let mut executed = 0;
+        let (mut directions, mut directions_hit) = (0, 0);
+        let mut listing = String::new();
+        for &address in addresses
+        {
+            let inst = memory(address);
+            let hits = self.hits(address);
+            executed += (hits > 0) as usize;
+            let label = symbols.label(address).unwrap_or("");
+            let mut line = match hits
+            {
+                0 => format!("  x{address:04X}  {label:<12} {:<20} {:>10}", disassemble(address, inst), "never"),
+                _ => format!("  x{address:04X}  {label:<12} {:<20} {hits:>10}", disassemble(address, inst)),
+            };
+            if is_conditional_branch(inst)
+            {
+                let (taken, not_taken) = self.branch(address);
+                directions += 2;
+                directions_hit += (taken > 0) as usize + (not_taken > 0) as usize;
+                line += &format!("  taken {taken}, not taken {not_taken}");
+                if taken == 0 || not_taken == 0
+                {
+                    line += "  <- partial";
+                }
+            }
+            listing += line.trim_end();
+            listing.push('\n');
+        }
+
+        let percent = |hit: usize, total: usize| if total == 0 { 100.0 } else { hit as f64 * 100.0 / total as f64 };
+        let mut out = format!(
+            "Instructions: {executed}/{} executed ({:.1}%)\nBranches: {directions_hit}/{directions} directions taken ({:.1}%)\n",
+            addresses.len(),
+            percent(executed, addresses.len()),
+            percent(directions_hit, directions)
+        );
+        out += "\nPer address:\n";
+        out += &listing;
+
+        if let Some(map) = map
+        {
+            out += "\nPer source line:\n";
+            for (file, lines) in self.by_line(map, &memory)
+            {
+                for (line, coverage) in lines
+                {
+                    let hits = if coverage.hits == 0 { "never".to_string() } else { coverage.hits.to_string() };
+                    out += &format!("  {file}:{line}  {hits}\n");
+                }
+            }
+        }
+        out
+    }
+
+    /// Coverage in the lcov tracefile format read by genhtml and most CI tools.
+    pub fn lcov(&self, map: &SourceMap, memory: impl Fn(u16) -> u16) -> String
+    {
+        let mut out = String::new();
+        for (file, lines) in self.by_line(map, &memory)
+        {
+            out += &format!("TN:\nSF:{file}\n");
+            for (line, coverage) in &lines
+            {
+                out += &format!("DA:{line},{}\n", coverage.hits);
+            }
+            let mut branches = (0, 0);
+            for (line, coverage) in &lines
+            {
+                for (block, (taken, not_taken)) in coverage.branches.iter().enumerate()
+                {
+                    let executed = taken + not_taken > 0;
+                    for (branch, count) in [taken, not_taken].into_iter().enumerate()
+                    {
+                        let count = if executed { count.to_string() } else { "-".to_string() };
+                        out += &format!("BRDA:{line},{block},{branch},{count}\n");
+                    }
+                    branches.0 += 2;
+                    branches.1 += (*taken > 0) as usize + (*not_taken > 0) as usize;
+                }
+            }
+            out += &format!("BRF:{}\nBRH:{}\n", branches.0, branches.1);
+            out += &format!("LF:{}\nLH:{}\n", lines.len(), lines.values().filter(|l| l.hits > 0).count());
+            out += "end_of_record\n";
+        }
+        out
+    }
+
+    /// Per file, per line: hits of the line's most executed instruction and
+    /// the (taken, not taken) counts of every conditional branch on it.
+    fn by_line(&self, map: &SourceMap, memory: &impl Fn(u16) -> u16) -> BTreeMap<String, BTreeMap<u32, LineCoverage>>
+    {
+        let mut files: BTreeMap<String, BTreeMap<u32, LineCoverage>> = BTreeMap::new();
+        for (&address, (file, line)) in &map.lines
+        {
+            let entry = files.entry(file.clone()).or_default().entry(*line).or_default();
+            entry.hits = entry.hits.max(self.hits(address));
+            if is_conditional_branch(memory(address))
+            {
+                entry.branches.push(self.branch(address));
+            }
+        }
+        files
+    }
+}
+
+#[derive(Default)]
+struct LineCoverage
+{
+    hits: u64,
+    branches: Vec<(u64, u64)>,
+}
+
+#[cfg(test)]
+mod tests;
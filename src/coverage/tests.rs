@@ -0,0 +1,104 @@
+use super::*;
+use crate::hardware::Registers;
+use crate::vm::VM;
+
+// x3000 AND R0, R0, #0 ; ADD R0, R0, #2
+// x3002 LOOP: ADD R0, R0, #-1 ; BRp LOOP
+// x3004 BRn DONE ; HALT
+// x3006 DONE: HALT
+const PROGRAM: [u16; 7] = [0x5020, 0x1022, 0x103F, 0x03FE, 0x0801, 0xF025, 0xF025];
+
+const MAP: &str = "# address  source
+x3000 count.asm:3
+x3001 count.asm:4
+x3002 count.asm:5
+x3003 count.asm:6
+x3004 count.asm:7
+x3005 count.asm:8
+x3006 count.asm:10
+";
+
+fn covered_run() -> VM {
+    let mut vm = VM::new();
+    for (i, word) in PROGRAM.iter().enumerate() {
+        vm.memory_write(0x3000 + i as u16, *word);
+    }
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    vm.set_coverage(Coverage::new());
+    while vm.state_read() {
+        vm.step();
+    }
+    vm
+}
+
+#[test]
+fn test_records_hits_and_branch_directions() {
+    let vm = covered_run();
+    let coverage = vm.coverage().unwrap();
+    assert_eq!(coverage.hits(0x3002), 2);
+    assert_eq!(coverage.hits(0x3006), 0);
+    assert_eq!(coverage.branch(0x3003), (1, 1));
+    assert_eq!(coverage.branch(0x3004), (0, 1));
+    // Unconditional code never shows up as a branch
+    assert_eq!(coverage.branch(0x3001), (0, 0));
+}
+
+#[test]
+fn test_text_report() {
+    let vm = covered_run();
+    let map = SourceMap::parse(MAP).unwrap();
+    let mut symbols = SymbolTable::default();
+    symbols.extend("LOOP x3002\nDONE x3006\n");
+    let report = vm.coverage().unwrap().report(&map.addresses(), &symbols, Some(&map), |a| vm.memory_peek(a));
+
+    assert!(report.starts_with("Instructions: 6/7 executed (85.7%)\nBranches: 3/4 directions taken (75.0%)\n"));
+    assert!(report.contains("  x3003               BRp x3002                     2  taken 1, not taken 1\n"));
+    assert!(report.contains("  x3004               BRn x3006                     1  taken 0, not taken 1  <- partial\n"));
+    assert!(report.contains("  x3006  DONE         HALT                      never\n"));
+    assert!(report.contains("  count.asm:10  never\n"));
+}
+
+#[test]
+fn test_lcov_output() {
+    let vm = covered_run();
+    let map = SourceMap::parse(MAP).unwrap();
+    let lcov = vm.coverage().unwrap().lcov(&map, |a| vm.memory_peek(a));
+    assert_eq!(
+        lcov,
+        "TN:\nSF:count.asm\n\
+         DA:3,1\nDA:4,1\nDA:5,2\nDA:6,2\nDA:7,1\nDA:8,1\nDA:10,0\n\
+         BRDA:6,0,0,1\nBRDA:6,0,1,1\nBRDA:7,0,0,0\nBRDA:7,0,1,1\n\
+         BRF:4\nBRH:3\nLF:7\nLH:6\nend_of_record\n"
+    );
+}
+
+#[test]
+fn test_unconditional_branch_is_fully_covered() {
+    // x3000 AND R0, R0, #0 ; ADD R0, R0, #2
+    // x3002 LOOP: ADD R0, R0, #-1 ; BRz DONE
+    // x3004 BRnzp LOOP ; DONE: HALT
+    let program = [0x5020, 0x1022, 0x103F, 0x0401, 0x0FFD, 0xF025];
+    let mut vm = VM::new();
+    for (i, word) in program.iter().enumerate() {
+        vm.memory_write(0x3000 + i as u16, *word);
+    }
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    vm.register_write(Registers::R_COND.into(), 2);
+    vm.set_coverage(Coverage::new());
+    while vm.state_read() {
+        vm.step();
+    }
+    let coverage = vm.coverage().unwrap();
+    assert_eq!(coverage.branch(0x3004), (0, 0));
+    let addresses: Vec<u16> = (0x3000..0x3006).collect();
+    let report = coverage.report(&addresses, &SymbolTable::default(), None, |a| vm.memory_peek(a));
+    assert!(report.starts_with("Instructions: 6/6 executed (100.0%)\nBranches: 2/2 directions taken (100.0%)\n"));
+    assert!(!report.contains("partial"));
+}
+
+#[test]
+fn test_source_map_errors() {
+    assert!(SourceMap::parse("x3000 prog.asm\n").is_err());
+    assert!(SourceMap::parse("x3000 prog.asm:ten\n").is_err());
+    assert!(SourceMap::parse("zzz prog.asm:1\n").is_err());
+}
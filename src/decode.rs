@@ -0,0 +1,176 @@
+use crate::hardware::Registers;
+use crate::operations::{sign_extension, OPCODE_TABLE};
+use crate::vm::VM;
+
+/// An instruction with its operand fields extracted and offsets already
+/// sign-extended, so executing it does no bit twiddling. `VM::step` caches
+/// these per address.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Decoded
+{
+    Br { nzp: u16, offset: u16 },
+    Add { dr: u8, sr1: u8, sr2: u8 },
+    AddImm { dr: u8, sr1: u8, imm: u16 },
+    And { dr: u8, sr1: u8, sr2: u8 },
+    AndImm { dr: u8, sr1: u8, imm: u16 },
+    Not { dr: u8, sr: u8 },
+    Ld { dr: u8, offset: u16 },
+    Ldi { dr: u8, offset: u16 },
+    Ldr { dr: u8, base: u8, offset: u16 },
+    Lea { dr: u8, offset: u16 },
+    St { sr: u8, offset: u16 },
+    Sti { sr: u8, offset: u16 },
+    Str { sr: u8, base: u8, offset: u16 },
+    Jmp { base: u8 },
+    Jsr { offset: u16 },
+    Jsrr { base: u8 },
+    /// RTI, the reserved opcode and TRAP run their `OPCODE_TABLE` handler.
+    Native(u16),
+}
+
+pub fn decode(inst: u16) -> Decoded
+{
+    let dr = ((inst >> 9) & 7) as u8;
+    let sr1 = ((inst >> 6) & 7) as u8;
+    let sr2 = (inst & 7) as u8;
+    let imm = sign_extension(inst & 0x1F, 5);
+    let offset6 = sign_extension(inst & 0x3F, 6);
+    let offset9 = sign_extension(inst & 0x1FF, 9);
+    let immediate = inst & 0x20 != 0;
+    match inst >> 12
+    {
+        0x0 => Decoded::Br { nzp: (inst >> 9) & 7, offset: offset9 },
+        0x1 if immediate => Decoded::AddImm { dr, sr1, imm },
+        0x1 => Decoded::Add { dr, sr1, sr2 },
+        0x2 => Decoded::Ld { dr, offset: offset9 },
+        0x3 => Decoded::St { sr: dr, offset: offset9 },
+        0x4 if inst & 0x0800 != 0 => Decoded::Jsr { offset: sign_extension(inst & 0x7FF, 11) },
+        0x4 => Decoded::Jsrr { base: sr1 },
+        0x5 if immediate => Decoded::AndImm { dr, sr1, imm },
+        0x5 => Decoded::And { dr, sr1, sr2 },
+        0x6 => Decoded::Ldr { dr, base: sr1, offset: offset6 },
+        0x7 => Decoded::Str { sr: dr, base: sr1, offset: offset6 },
+        0x9 => Decoded::Not { dr, sr: sr1 },
+        0xA => Decoded::Ldi { dr, offset: offset9 },
+        0xB => Decoded::Sti { sr: dr, offset: offset9 },
+        0xC => Decoded::Jmp { base: sr1 },
+        0xE => Decoded::Lea { dr, offset: offset9 },
+        _ => Decoded::Native(inst),
+    }
+}
+
+impl Decoded
+{
+    /// Same effect, register writes and memory accesses, in the same order,
+    /// as the `OPCODE_TABLE` handler for the original instruction.
+    #[inline(always)]
+    pub fn execute(self, vm: &mut VM)
+    {
+        const PC: usize = Registers::R_PC as usize;
+        let set = |vm: &mut VM, dr: u8, value: u16| {
+            vm.register_write(dr as usize, value);
+            vm.update_flags(dr as usize);
+        };
+        match self
+        {
+            Decoded::Br { nzp, offset } =>
+            {
+                if nzp & vm.register_read(Registers::R_COND.into()) != 0
+                {
+                    let pc = vm.register_read(PC);
+                    vm.register_write(PC, pc.wrapping_add(offset));
+                }
+            }
+            Decoded::Add { dr, sr1, sr2 } =>
+            {
+                let value = vm.register_read(sr1 as usize).wrapping_add(vm.register_read(sr2 as usize));
+                set(vm, dr, value);
+            }
+            Decoded::AddImm { dr, sr1, imm } =>
+            {
+                let value = vm.register_read(sr1 as usize).wrapping_add(imm);
+                set(vm, dr, value);
+            }
+            Decoded::And { dr, sr1, sr2 } =>
+            {
+                let value = vm.register_read(sr1 as usize) & vm.register_read(sr2 as usize);
+                set(vm, dr, value);
+            }
+            Decoded::AndImm { dr, sr1, imm } =>
+            {
+                let value = vm.register_read(sr1 as usize) & imm;
+                set(vm, dr, value);
+            }
+            Decoded::Not { dr, sr } =>
+            {
+                let value = !vm.register_read(sr as usize);
+                set(vm, dr, value);
+            }
+            Decoded::Ld { dr, offset } =>
+            {
+                let address = vm.register_read(PC).wrapping_add(offset);
+                let value = vm.memory_read(address);
+                set(vm, dr, value);
+            }
+            Decoded::Ldi { dr, offset } =>
+            {
+                let pointer = vm.register_read(PC).wrapping_add(offset);
+                let address = vm.memory_read(pointer);
+                let value = vm.memory_read(address);
+                set(vm, dr, value);
+            }
+            Decoded::Ldr { dr, base, offset } =>
+            {
+                let address = vm.register_read(base as usize).wrapping_add(offset);
+                let value = vm.memory_read(address);
+                set(vm, dr, value);
+            }
+            Decoded::Lea { dr, offset } =>
+            {
+                let value = vm.register_read(PC).wrapping_add(offset);
+                set(vm, dr, value);
+            }
+            Decoded::St { sr, offset } =>
+            {
+                let address = vm.register_read(PC).wrapping_add(offset);
+                let value = vm.register_read(sr as usize);
+                vm.memory_write(address, value);
+            }
+            Decoded::Sti { sr, offset } =>
+            {
+                let pointer = vm.register_read(PC).wrapping_add(offset);
+                let address = vm.memory_read(pointer);
+                let value = vm.register_read(sr as usize);
+                vm.memory_write(address, value);
+            }
+            Decoded::Str { sr, base, offset } =>
+            {
+                let address = vm.register_read(base as usize).wrapping_add(offset);
+                let value = vm.register_read(sr as usize);
+                vm.memory_write(address, value);
+            }
+            Decoded::Jmp { base } =>
+            {
+                let target = vm.register_read(base as usize);
+                vm.register_write(PC, target);
+            }
+            Decoded::Jsr { offset } =>
+            {
+                let pc = vm.register_read(PC);
+                vm.register_write(PC, pc.wrapping_add(offset));
+                vm.register_write(Registers::R_R7.into(), pc);
+            }
+            Decoded::Jsrr { base } =>
+            {
+                let pc = vm.register_read(PC);
+                let target = vm.register_read(base as usize);
+                vm.register_write(PC, target);
+                vm.register_write(Registers::R_R7.into(), pc);
+            }
+            Decoded::Native(inst) => OPCODE_TABLE[(inst >> 12) as usize](inst, vm),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
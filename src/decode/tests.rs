@@ -0,0 +1,78 @@
+use super::*;
+
+use crate::display::Display;
+use crate::keyboard::Keyboard;
+
+fn run(program: &[u16], predecode: bool) -> VM {
+    let mut vm = VM::new();
+    vm.set_predecode(predecode);
+    vm.set_display(Display::captured_only());
+    vm.set_keyboard(Keyboard::stream(std::io::empty()));
+    for (i, &word) in program.iter().enumerate() {
+        vm.memory_write(0x3000 + i as u16, word);
+    }
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    while vm.state_read() {
+        vm.step();
+    }
+    vm
+}
+
+#[test]
+fn test_decode_resolves_operands() {
+    assert_eq!(decode(0x1023), Decoded::AddImm { dr: 0, sr1: 0, imm: 3 });
+    assert_eq!(decode(0x14BF), Decoded::AddImm { dr: 2, sr1: 2, imm: 0xFFFF });
+    assert_eq!(decode(0x1004), Decoded::Add { dr: 0, sr1: 0, sr2: 4 });
+    assert_eq!(decode(0x03FB), Decoded::Br { nzp: 1, offset: 0xFFFB });
+    assert_eq!(decode(0x6880), Decoded::Ldr { dr: 4, base: 2, offset: 0 });
+    assert_eq!(decode(0x7A7F), Decoded::Str { sr: 5, base: 1, offset: 0xFFFF });
+    assert_eq!(decode(0x4C00), Decoded::Jsr { offset: 0xFC00 });
+    assert_eq!(decode(0x4080), Decoded::Jsrr { base: 2 });
+    assert_eq!(decode(0xC1C0), Decoded::Jmp { base: 7 });
+    assert_eq!(decode(0xF025), Decoded::Native(0xF025));
+    assert_eq!(decode(0x8000), Decoded::Native(0x8000));
+}
+
+#[test]
+fn test_predecoded_run_matches_table_dispatch() {
+    // Sums 10..=1 in a subroutine, then prints the sum with OUT.
+    let program = [
+        0xE203, // LEA R1, SUM
+        0x4040, // JSRR R1
+        0xF021, // OUT
+        0xF025, // HALT
+        0x5020, // SUM: AND R0, R0, #0
+        0x54A0, // AND R2, R2, #0
+        0x14AA, // ADD R2, R2, #10
+        0x1002, // ADD R0, R0, R2     ; x3007
+        0x14BF, // ADD R2, R2, #-1
+        0x03FD, // BRp x3007
+        0xC1C0, // RET
+    ];
+    let table = run(&program, false);
+    let mut decoded = run(&program, true);
+    assert!(decoded.arch_state() == table.arch_state());
+    assert_eq!(decoded.steps(), table.steps());
+    assert_eq!(decoded.register_read(0), 55);
+}
+
+#[test]
+fn test_stores_into_code_invalidate_the_cache() {
+    // Runs the ADD at x3001 once, overwrites it with ADD R0, R0, #5 and runs it again.
+    let program = [
+        0x5020, // AND R0, R0, #0
+        0x1021, // ADD R0, R0, #1     ; x3001
+        0x1261, // ADD R1, R1, #1
+        0x1A7E, // ADD R5, R1, #-2
+        0x0403, // BRz x3008
+        0x2603, // LD R3, PATCH
+        0x37FA, // ST R3, x3001
+        0x0FF9, // BRnzp x3001
+        0xF025, // HALT
+        0x1025, // PATCH: ADD R0, R0, #5
+    ];
+    let table = run(&program, false);
+    let mut decoded = run(&program, true);
+    assert!(decoded.arch_state() == table.arch_state());
+    assert_eq!(decoded.register_read(0), 6);
+}
@@ -0,0 +1,153 @@
+/// Lines of context kept around each change.
+const CONTEXT: usize = 3;
+
+/// Largest LCS table built for the lines between the common prefix and
+/// suffix; bigger differences only report where they start.
+const MAX_TABLE: usize = 1 << 22;
+
+enum Edit<'a>
+{
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Unified diff from `expected` to `actual`, or `None` when they are identical.
+pub fn unified_diff(expected: &str, actual: &str, expected_name: &str, actual_name: &str) -> Option<String>
+{
+    if expected == actual
+    {
+        return None;
+    }
+    let old: Vec<&str> = expected.split_inclusive('\n').collect();
+    let new: Vec<&str> = actual.split_inclusive('\n').collect();
+    let mut out = format!("--- {expected_name}\n+++ {actual_name}\n");
+    let Some(edits) = edit_script(&old, &new)
+    else
+    {
+        let first = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        out += &format!("first difference at line {}, too many lines differ to show them all\n", first + 1);
+        if let Some(line) = old.get(first)
+        {
+            push_line(&mut out, '-', line);
+        }
+        if let Some(line) = new.get(first)
+        {
+            push_line(&mut out, '+', line);
+        }
+        return Some(out);
+    };
+
+    let changed: Vec<usize> = (0..edits.len()).filter(|&i| !matches!(edits[i], Edit::Same(_))).collect();
+    let (mut old_line, mut new_line, mut counted) = (1, 1, 0);
+    let mut i = 0;
+    while i < changed.len()
+    {
+        // Grow the hunk while the next change is close enough to share context
+        let start = changed[i].saturating_sub(CONTEXT);
+        let mut last = changed[i];
+        while i + 1 < changed.len() && changed[i + 1] - last <= 2 * CONTEXT
+        {
+            i += 1;
+            last = changed[i];
+        }
+        let end = (last + CONTEXT + 1).min(edits.len());
+        i += 1;
+
+        for edit in &edits[counted..start]
+        {
+            match edit
+            {
+                Edit::Same(_) => { old_line += 1; new_line += 1; }
+                Edit::Removed(_) => old_line += 1,
+                Edit::Added(_) => new_line += 1,
+            }
+        }
+        counted = start;
+        let old_count = edits[start..end].iter().filter(|e| !matches!(e, Edit::Added(_))).count();
+        let new_count = edits[start..end].iter().filter(|e| !matches!(e, Edit::Removed(_))).count();
+        out += &format!("@@ -{} +{} @@\n", range(old_line, old_count), range(new_line, new_count));
+        for edit in &edits[start..end]
+        {
+            let (prefix, line) = match edit
+            {
+                Edit::Same(line) => (' ', line),
+                Edit::Removed(line) => ('-', line),
+                Edit::Added(line) => ('+', line),
+            };
+            push_line(&mut out, prefix, line);
+        }
+    }
+    Some(out)
+}
+
+fn push_line(out: &mut String, prefix: char, line: &str)
+{
+    out.push(prefix);
+    *out += line;
+    if !line.ends_with('\n')
+    {
+        *out += "\n\\ No newline at end of file\n";
+    }
+}
+
+fn range(start: usize, count: usize) -> String
+{
+    match count
+    {
+        0 => format!("{},0", start - 1),
+        1 => format!("{start}"),
+        _ => format!("{start},{count}"),
+    }
+}
+
+/// Longest-common-subsequence edit script. The common prefix and suffix are
+/// matched directly, and `None` means the lines left between them would need
+/// a table larger than `MAX_TABLE`.
+fn edit_script<'a>(old: &[&'a str], new: &[&'a str]) -> Option<Vec<Edit<'a>>>
+{
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old_rest, new_rest) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+    if (old_rest.len() + 1).saturating_mul(new_rest.len() + 1) > MAX_TABLE
+    {
+        return None;
+    }
+
+    let mut edits: Vec<Edit> = old[..prefix].iter().map(|line| Edit::Same(line)).collect();
+    let common_suffix = &old[old.len() - suffix..];
+    let (old, new) = (old_rest, new_rest);
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev()
+    {
+        for j in (0..new.len()).rev()
+        {
+            lcs[i][j] = if old[i] == new[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len()
+    {
+        if i < old.len() && j < new.len() && old[i] == new[j]
+        {
+            edits.push(Edit::Same(old[i]));
+            i += 1;
+            j += 1;
+        }
+        else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1])
+        {
+            edits.push(Edit::Removed(old[i]));
+            i += 1;
+        }
+        else
+        {
+            edits.push(Edit::Added(new[j]));
+            j += 1;
+        }
+    }
+    edits.extend(common_suffix.iter().map(|line| Edit::Same(line)));
+    Some(edits)
+}
+
+#[cfg(test)]
+mod tests;
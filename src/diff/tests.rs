@@ -0,0 +1,68 @@
+use super::*;
+
+#[test]
+fn test_identical_output_has_no_diff() {
+    assert!(unified_diff("a\nb\n", "a\nb\n", "expected", "actual").is_none());
+}
+
+#[test]
+fn test_changed_line() {
+    let diff = unified_diff("one\ntwo\nthree\n", "one\n2\nthree\n", "expected", "actual").unwrap();
+    assert_eq!(
+        diff,
+        "--- expected\n+++ actual\n@@ -1,3 +1,3 @@\n one\n-two\n+2\n three\n"
+    );
+}
+
+#[test]
+fn test_missing_trailing_newline_is_reported() {
+    let diff = unified_diff("done\n", "done", "expected", "actual").unwrap();
+    assert_eq!(
+        diff,
+        "--- expected\n+++ actual\n@@ -1 +1 @@\n-done\n+done\n\\ No newline at end of file\n"
+    );
+}
+
+#[test]
+fn test_distant_changes_get_separate_hunks() {
+    let expected: String = (1..=20).map(|n| format!("{n}\n")).collect();
+    let actual: String = (1..=20)
+        .map(|n| match n {
+            2 => "two\n".to_string(),
+            19 => "nineteen\n".to_string(),
+            _ => format!("{n}\n"),
+        })
+        .collect();
+    let diff = unified_diff(&expected, &actual, "expected", "actual").unwrap();
+    assert_eq!(diff.matches("@@ -").count(), 2);
+    assert!(diff.contains("@@ -1,5 +1,5 @@\n"));
+    assert!(diff.contains("@@ -16,5 +16,5 @@\n"));
+}
+
+#[test]
+fn test_empty_expected_output() {
+    let diff = unified_diff("", "hi\n", "expected", "actual").unwrap();
+    assert_eq!(diff, "--- expected\n+++ actual\n@@ -0,0 +1 @@\n+hi\n");
+}
+
+#[test]
+fn test_long_outputs_only_tabulate_the_changed_lines() {
+    let expected: String = (1..=100_000).map(|n| format!("{n}\n")).collect();
+    let actual = expected.replace("\n50000\n", "\nfifty thousand\n");
+    let diff = unified_diff(&expected, &actual, "expected", "actual").unwrap();
+    assert_eq!(
+        diff,
+        "--- expected\n+++ actual\n@@ -49997,7 +49997,7 @@\n 49997\n 49998\n 49999\n-50000\n+fifty thousand\n 50001\n 50002\n 50003\n"
+    );
+}
+
+#[test]
+fn test_large_differences_report_the_first_changed_line() {
+    let expected: String = (1..=5000).map(|n| format!("{n}\n")).collect();
+    let actual: String = (1..=5000).map(|n| if n < 3 { format!("{n}\n") } else { format!("line {n}\n") }).collect();
+    let diff = unified_diff(&expected, &actual, "expected", "actual").unwrap();
+    assert_eq!(
+        diff,
+        "--- expected\n+++ actual\nfirst difference at line 3, too many lines differ to show them all\n-3\n+line 3\n"
+    );
+}
@@ -0,0 +1,83 @@
+use crate::operations::sign_extension;
+use crate::traps::Traps;
+
+/// Mnemonics indexed by opcode, in `OPCODE_TABLE` order.
+pub const OPCODE_NAMES: [&str; 16] =
+[
+    "BR", "ADD", "LD", "ST", "JSR", "AND", "LDR", "STR",
+    "RTI", "NOT", "LDI", "STI", "JMP", "RES", "LEA", "TRAP",
+];
+
+/// Opcode number for a mnemonic, accepting the JSRR and RET aliases.
+pub fn opcode_from_name(name: &str) -> Option<u16>
+{
+    let name = name.to_ascii_uppercase();
+    match name.as_str()
+    {
+        "JSRR" => Some(4),
+        "RET" => Some(12),
+        _ => OPCODE_NAMES.iter().position(|&n| n == name).map(|op| op as u16),
+    }
+}
+
+fn reg(inst: u16, shift: u16) -> String
+{
+    format!("R{}", (inst >> shift) & 7)
+}
+
+/// Absolute target of a PC-relative operand, `address` being where `inst` lives.
+fn target(address: u16, inst: u16, bits: u8) -> String
+{
+    format!("x{:04X}", address.wrapping_add(1).wrapping_add(sign_extension(inst, bits)))
+}
+
+/// LC-3 assembly for the instruction word `inst` stored at `address`.
+pub fn disassemble(address: u16, inst: u16) -> String
+{
+    let op = inst >> 12;
+    let name = OPCODE_NAMES[op as usize];
+    match op
+    {
+        0x0 =>
+        {
+            let n = if inst & 0x0800 != 0 { "n" } else { "" };
+            let z = if inst & 0x0400 != 0 { "z" } else { "" };
+            let p = if inst & 0x0200 != 0 { "p" } else { "" };
+            if n.is_empty() && z.is_empty() && p.is_empty()
+            {
+                return "NOP".to_string();
+            }
+            format!("BR{n}{z}{p} {}", target(address, inst, 9))
+        }
+        0x1 | 0x5 if inst & 0x20 != 0 =>
+        {
+            format!("{name} {}, {}, #{}", reg(inst, 9), reg(inst, 6), sign_extension(inst, 5) as i16)
+        }
+        0x1 | 0x5 => format!("{name} {}, {}, {}", reg(inst, 9), reg(inst, 6), reg(inst, 0)),
+        0x2 | 0x3 | 0xA | 0xB | 0xE => format!("{name} {}, {}", reg(inst, 9), target(address, inst, 9)),
+        0x4 if inst & 0x0800 != 0 => format!("JSR {}", target(address, inst, 11)),
+        0x4 => format!("JSRR {}", reg(inst, 6)),
+        0x6 | 0x7 => format!("{name} {}, {}, #{}", reg(inst, 9), reg(inst, 6), sign_extension(inst, 6) as i16),
+        0x9 => format!("NOT {}, {}", reg(inst, 9), reg(inst, 6)),
+        0xC if (inst >> 6) & 7 == 7 => "RET".to_string(),
+        0xC => format!("JMP {}", reg(inst, 6)),
+        0xF => match Traps::from(inst & 0xFF)
+        {
+            Traps::TRAP_GETC => "GETC".to_string(),
+            Traps::TRAP_OUT => "OUT".to_string(),
+            Traps::TRAP_PUTS => "PUTS".to_string(),
+            Traps::TRAP_IN => "IN".to_string(),
+            Traps::TRAP_PUTSP => "PUTSP".to_string(),
+            Traps::TRAP_HALT => "HALT".to_string(),
+            Traps::TRAP_PUTD => "PUTD".to_string(),
+            Traps::TRAP_PUTX => "PUTX".to_string(),
+            Traps::TRAP_GETD => "GETD".to_string(),
+            Traps::TRAP_NEWLN => "NEWLN".to_string(),
+            Traps::TRAP_INVALID => format!("TRAP x{:02X}", inst & 0xFF),
+        },
+        _ => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests;
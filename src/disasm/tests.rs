@@ -0,0 +1,45 @@
+use super::*;
+
+#[test]
+fn test_operate_instructions() {
+    assert_eq!(disassemble(0x3000, 0b0001_000_001_1_00101), "ADD R0, R1, #5");
+    assert_eq!(disassemble(0x3000, 0b0001_010_011_0_00_100), "ADD R2, R3, R4");
+    assert_eq!(disassemble(0x3000, 0b0101_101_110_1_10000), "AND R5, R6, #-16");
+    assert_eq!(disassemble(0x3000, 0b1001_001_010_111111), "NOT R1, R2");
+}
+
+#[test]
+fn test_pc_relative_targets() {
+    assert_eq!(disassemble(0x3000, 0b0000_101_000000011), "BRnp x3004");
+    assert_eq!(disassemble(0x3000, 0b0000_111_111111111), "BRnzp x3000");
+    assert_eq!(disassemble(0x3000, 0x0000), "NOP");
+    assert_eq!(disassemble(0x3010, 0b0010_011_111110000), "LD R3, x3001");
+    assert_eq!(disassemble(0xFFFF, 0b1110_000_000000001), "LEA R0, x0001");
+    assert_eq!(disassemble(0x3000, 0b0100_1_00000001000), "JSR x3009");
+}
+
+#[test]
+fn test_base_register_instructions() {
+    assert_eq!(disassemble(0x3000, 0b0110_001_010_111101), "LDR R1, R2, #-3");
+    assert_eq!(disassemble(0x3000, 0b0111_001_010_000101), "STR R1, R2, #5");
+    assert_eq!(disassemble(0x3000, 0b0100_0_00_011_000000), "JSRR R3");
+    assert_eq!(disassemble(0x3000, 0b1100_000_111_000000), "RET");
+    assert_eq!(disassemble(0x3000, 0b1100_000_010_000000), "JMP R2");
+}
+
+#[test]
+fn test_traps_and_unused_opcodes() {
+    assert_eq!(disassemble(0x3000, 0xF025), "HALT");
+    assert_eq!(disassemble(0x3000, 0xF022), "PUTS");
+    assert_eq!(disassemble(0x3000, 0xF0FF), "TRAP xFF");
+    assert_eq!(disassemble(0x3000, 0x8000), "RTI");
+    assert_eq!(disassemble(0x3000, 0xD000), "RES");
+}
+
+#[test]
+fn test_opcode_names() {
+    assert_eq!(opcode_from_name("add"), Some(1));
+    assert_eq!(opcode_from_name("RET"), Some(12));
+    assert_eq!(opcode_from_name("JSRR"), Some(4));
+    assert_eq!(opcode_from_name("MUL"), None);
+}
@@ -0,0 +1,223 @@
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::vm::VM;
+
+/// Status: bit 15 ready, bit 14 interrupt enable, bit 0 error of the last command.
+/// Only the enable bit can be written.
+pub const DISK_SR: u16 = 0xFE10;
+/// Command: writing 1 reads the sector into memory, 2 writes memory to the sector.
+pub const DISK_CR: u16 = 0xFE12;
+/// Sector the next command transfers, counted from 0.
+pub const DISK_SECTOR: u16 = 0xFE14;
+/// First memory address of the transfer.
+pub const DISK_ADDRESS: u16 = 0xFE16;
+
+pub const DISK_READY: u16 = 1 << 15;
+pub const DISK_IE: u16 = 1 << 14;
+pub const DISK_ERROR: u16 = 1;
+
+pub const COMMAND_READ: u16 = 1;
+pub const COMMAND_WRITE: u16 = 2;
+
+/// Words per sector; each is stored big-endian, so a sector is 512 bytes.
+pub const SECTOR_WORDS: u16 = 256;
+/// Interrupt vector table entry x0181 holds the completion handler.
+pub const DISK_VECTOR: u8 = 0x81;
+/// Priority of the completion interrupt, the level the keyboard has on the LC-3.
+pub const DISK_PRIORITY: u8 = 4;
+/// Instructions a transfer takes by default.
+pub const DEFAULT_LATENCY: u32 = 100;
+
+/// What a disk image can be kept in: a file, or a `Cursor` in memory.
+pub trait Storage: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> Storage for T {}
+
+/// Registers and transfer progress of a `Disk`, everything but the image
+/// itself, for snapshots.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct DiskState
+{
+    pub status: u16,
+    pub sector: u16,
+    pub address: u16,
+    /// Command in progress and the instructions left until it completes.
+    pub busy: Option<(u16, u32)>,
+    /// Completion not yet taken as an interrupt.
+    pub requested: bool,
+}
+
+/// Block storage on the device page. A program puts a sector number and a
+/// memory address in DISK_SECTOR and DISK_ADDRESS and writes a command to
+/// DISK_CR; `latency` instructions later the device has copied the sector
+/// straight to or from memory and sets ready in DISK_SR, raising interrupt
+/// `DISK_VECTOR` if enabled. While a transfer is in progress only the enable
+/// bit can be changed.
+pub struct Disk
+{
+    storage: Box<dyn Storage>,
+    sectors: u16,
+    latency: u32,
+    status: u16,
+    sector: u16,
+    address: u16,
+    /// Command in progress and the instructions left until it completes.
+    busy: Option<(u16, u32)>,
+    /// Completion not yet taken as an interrupt.
+    requested: bool,
+}
+
+impl Disk
+{
+    /// A disk backed by `storage`, its size rounded up to whole sectors.
+    pub fn new(mut storage: Box<dyn Storage>) -> Result<Self, String>
+    {
+        let bytes = storage.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+        let sectors = bytes.div_ceil(SECTOR_WORDS as u64 * 2);
+        let sectors = u16::try_from(sectors).map_err(|_| format!("disk image of {bytes} bytes has more than {} sectors", u16::MAX))?;
+        Ok(Self { storage, sectors, latency: DEFAULT_LATENCY, status: DISK_READY, sector: 0, address: 0, busy: None, requested: false })
+    }
+
+    /// Opens the image at `path` for reading and writing.
+    pub fn open(path: &Path) -> Result<Self, String>
+    {
+        let file = OpenOptions::new().read(true).write(true).open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::new(Box::new(file)).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn set_latency(&mut self, latency: u32)
+    {
+        self.latency = latency;
+    }
+
+    pub fn sectors(&self) -> u16
+    {
+        self.sectors
+    }
+
+    pub fn state(&self) -> DiskState
+    {
+        DiskState { status: self.status, sector: self.sector, address: self.address, busy: self.busy, requested: self.requested }
+    }
+
+    /// Puts registers and a transfer in progress back, e.g. from a snapshot.
+    pub fn set_state(&mut self, state: DiskState)
+    {
+        self.status = state.status;
+        self.sector = state.sector;
+        self.address = state.address;
+        self.busy = state.busy;
+        self.requested = state.requested;
+    }
+
+    pub fn is_register(address: u16) -> bool
+    {
+        matches!(address, DISK_SR | DISK_CR | DISK_SECTOR | DISK_ADDRESS)
+    }
+
+    /// What a program reads from the register at `address`.
+    pub fn register(&self, address: u16) -> u16
+    {
+        match address
+        {
+            DISK_SR => self.status,
+            DISK_CR => self.busy.map_or(0, |(command, _)| command),
+            DISK_SECTOR => self.sector,
+            DISK_ADDRESS => self.address,
+            _ => 0,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u16)
+    {
+        match address
+        {
+            DISK_SR =>
+            {
+                self.status = self.status & !DISK_IE | value & DISK_IE;
+                self.requested &= value & DISK_IE != 0;
+            }
+            DISK_CR if self.busy.is_none() =>
+            {
+                self.busy = Some((value, self.latency));
+                self.status &= !(DISK_READY | DISK_ERROR);
+                self.requested = false;
+            }
+            DISK_SECTOR if self.busy.is_none() => self.sector = value,
+            DISK_ADDRESS if self.busy.is_none() => self.address = value,
+            // Only the enable bit can change while a transfer is in progress.
+            _ => {}
+        }
+    }
+
+    /// Advances a transfer in progress by one instruction, doing the DMA
+    /// into `vm` when it completes.
+    pub fn tick(&mut self, vm: &mut VM)
+    {
+        let Some((command, left)) = self.busy else
+        {
+            return;
+        };
+        if left > 0
+        {
+            self.busy = Some((command, left - 1));
+            return;
+        }
+        self.busy = None;
+        if self.transfer(command, vm).is_err()
+        {
+            self.status |= DISK_ERROR;
+        }
+        self.status |= DISK_READY;
+        self.requested = self.status & DISK_IE != 0;
+    }
+
+    fn transfer(&mut self, command: u16, vm: &mut VM) -> io::Result<()>
+    {
+        let invalid = || io::Error::from(io::ErrorKind::InvalidInput);
+        // DMA never reaches the device page.
+        if self.sector >= self.sectors || self.address as u32 + SECTOR_WORDS as u32 > 0xFE00
+        {
+            return Err(invalid());
+        }
+        self.storage.seek(SeekFrom::Start(self.sector as u64 * SECTOR_WORDS as u64 * 2))?;
+        match command
+        {
+            COMMAND_READ =>
+            {
+                let mut buffer = Vec::new();
+                Read::by_ref(&mut self.storage).take(SECTOR_WORDS as u64 * 2).read_to_end(&mut buffer)?;
+                // The last sector of an image that is not a whole number of them reads as zeros past the end.
+                buffer.resize(SECTOR_WORDS as usize * 2, 0);
+                for (i, pair) in buffer.chunks_exact(2).enumerate()
+                {
+                    vm.memory_write(self.address + i as u16, u16::from_be_bytes([pair[0], pair[1]]));
+                }
+                Ok(())
+            }
+            COMMAND_WRITE =>
+            {
+                let buffer: Vec<u8> = (0..SECTOR_WORDS).flat_map(|i| vm.memory_peek(self.address + i).to_be_bytes()).collect();
+                self.storage.write_all(&buffer)?;
+                self.storage.flush()
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    /// A transfer completed with interrupts enabled and has not been serviced yet.
+    pub fn interrupt_requested(&self) -> bool
+    {
+        self.requested
+    }
+
+    pub fn acknowledge(&mut self)
+    {
+        self.requested = false;
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,192 @@
+use super::*;
+
+use std::io::Cursor;
+
+use crate::display::Display;
+use crate::hardware::Registers;
+use crate::vm::Fault;
+
+/// A disk of `sectors` sectors where word i of sector s holds s << 8 | i.
+fn image(sectors: u16) -> Vec<u8> {
+    (0..sectors).flat_map(|sector| (0..SECTOR_WORDS).flat_map(move |i| (sector << 8 | i).to_be_bytes())).collect()
+}
+
+fn vm_with(disk: Disk) -> VM {
+    let mut vm = VM::new();
+    vm.set_display(Display::captured_only());
+    vm.set_disk(disk);
+    vm
+}
+
+fn disk(bytes: Vec<u8>, latency: u32) -> Disk {
+    let mut disk = Disk::new(Box::new(Cursor::new(bytes))).unwrap();
+    disk.set_latency(latency);
+    disk
+}
+
+/// Memory at x3000 filled with `words`, then `BR #-1` spinning at the end.
+fn load(vm: &mut VM, words: &[u16]) {
+    for (i, &word) in words.iter().chain(&[0x0FFF]).enumerate() {
+        vm.memory_write(0x3000 + i as u16, word);
+    }
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    vm.register_write(Registers::R_COND.into(), 2);
+}
+
+fn command(vm: &mut VM, command: u16, sector: u16, address: u16) {
+    vm.memory_write(DISK_SECTOR, sector);
+    vm.memory_write(DISK_ADDRESS, address);
+    vm.memory_write(DISK_CR, command);
+}
+
+fn run(vm: &mut VM, steps: u32) {
+    for _ in 0..steps {
+        vm.step();
+    }
+}
+
+#[test]
+fn test_read_sector_after_latency() {
+    let mut vm = vm_with(disk(image(4), 10));
+    load(&mut vm, &[]);
+    command(&mut vm, COMMAND_READ, 2, 0x4000);
+    assert_eq!(vm.memory_read(DISK_SR), 0);
+    run(&mut vm, 10);
+    assert_eq!(vm.memory_read(DISK_SR) & DISK_READY, 0);
+    assert_eq!(vm.memory_peek(0x4000), 0);
+    run(&mut vm, 1);
+    assert_eq!(vm.memory_read(DISK_SR), DISK_READY);
+    assert_eq!(vm.memory_peek(0x4000), 0x0200);
+    assert_eq!(vm.memory_peek(0x40FF), 0x02FF);
+    assert_eq!(vm.memory_peek(0x4100), 0);
+}
+
+#[test]
+fn test_write_sector() {
+    let mut vm = vm_with(disk(image(2), 0));
+    load(&mut vm, &[]);
+    for i in 0..SECTOR_WORDS {
+        vm.memory_write(0x5000 + i, 0xAB00 | i);
+    }
+    command(&mut vm, COMMAND_WRITE, 1, 0x5000);
+    run(&mut vm, 1);
+    command(&mut vm, COMMAND_READ, 1, 0x6000);
+    run(&mut vm, 1);
+    assert_eq!(vm.memory_read(DISK_SR), DISK_READY);
+    assert_eq!(vm.memory_peek(0x6000), 0xAB00);
+    assert_eq!(vm.memory_peek(0x60FF), 0xABFF);
+    // Sector 0 is untouched
+    command(&mut vm, COMMAND_READ, 0, 0x6000);
+    run(&mut vm, 1);
+    assert_eq!(vm.memory_peek(0x6001), 0x0001);
+}
+
+#[test]
+fn test_partial_last_sector_reads_as_zeros() {
+    let mut bytes = image(1);
+    bytes.extend([0x12, 0x34, 0x56]);
+    let disk = disk(bytes, 0);
+    assert_eq!(disk.sectors(), 2);
+    let mut vm = vm_with(disk);
+    load(&mut vm, &[]);
+    vm.memory_write(0x4001, 0xFFFF);
+    command(&mut vm, COMMAND_READ, 1, 0x4000);
+    run(&mut vm, 1);
+    assert_eq!(vm.memory_peek(0x4000), 0x1234);
+    assert_eq!(vm.memory_peek(0x4001), 0x5600);
+    assert_eq!(vm.memory_peek(0x4002), 0);
+}
+
+#[test]
+fn test_errors() {
+    let mut vm = vm_with(disk(image(2), 0));
+    load(&mut vm, &[]);
+    for (command_word, sector, address) in [(COMMAND_READ, 2, 0x4000), (COMMAND_READ, 0, 0xFD01), (3, 0, 0x4000)] {
+        command(&mut vm, command_word, sector, address);
+        run(&mut vm, 1);
+        assert_eq!(vm.memory_read(DISK_SR), DISK_READY | DISK_ERROR, "{command_word} {sector} x{address:04X}");
+    }
+    // The last sector that fits below the device page is fine
+    command(&mut vm, COMMAND_READ, 0, 0xFD00);
+    run(&mut vm, 1);
+    assert_eq!(vm.memory_read(DISK_SR), DISK_READY);
+    assert_eq!(vm.memory_peek(0xFDFF), 0x00FF);
+}
+
+#[test]
+fn test_commands_while_busy_are_ignored() {
+    let mut vm = vm_with(disk(image(2), 5));
+    load(&mut vm, &[]);
+    command(&mut vm, COMMAND_READ, 0, 0x4000);
+    run(&mut vm, 2);
+    command(&mut vm, COMMAND_READ, 1, 0x5000);
+    assert_eq!(vm.memory_read(DISK_CR), COMMAND_READ);
+    run(&mut vm, 4);
+    assert_eq!(vm.memory_peek(0x4001), 0x0001);
+    assert_eq!(vm.memory_read(DISK_SR), DISK_READY);
+}
+
+#[test]
+fn test_completion_interrupt() {
+    let mut vm = vm_with(disk(image(1), 3));
+    load(&mut vm, &[]);
+    vm.register_write(6, 0x2000);
+    // Handler: ADD R5, R5, #1; RTI
+    vm.memory_write(0x0100 + DISK_VECTOR as u16, 0x1000);
+    vm.memory_write(0x1000, 0x1B61);
+    vm.memory_write(0x1001, 0x8000);
+    vm.memory_write(DISK_SR, DISK_IE | DISK_ERROR);
+    assert_eq!(vm.memory_read(DISK_SR), DISK_READY | DISK_IE);
+    command(&mut vm, COMMAND_READ, 0, 0x4000);
+    run(&mut vm, 3);
+    assert_eq!(vm.register_read(Registers::R_PC.into()), 0x3000);
+    // The step that completes the transfer enters the handler and runs its first instruction
+    run(&mut vm, 1);
+    assert_eq!(vm.register_read(5), 1);
+    assert_eq!(vm.psr() >> 8 & 7, DISK_PRIORITY as u16);
+    assert_eq!(vm.register_read(6), 0x1FFE);
+    assert_eq!(vm.memory_peek(0x1FFE), 0x3000);
+    run(&mut vm, 1);
+    assert_eq!(vm.register_read(Registers::R_PC.into()), 0x3000);
+    assert_eq!(vm.register_read(6), 0x2000);
+    assert_eq!(vm.psr() >> 8 & 7, 0);
+    // Taken only once
+    run(&mut vm, 10);
+    assert_eq!(vm.register_read(5), 1);
+    assert!(vm.state_read());
+}
+
+#[test]
+fn test_interrupt_waits_for_lower_priority() {
+    let mut vm = vm_with(disk(image(1), 0));
+    load(&mut vm, &[]);
+    vm.set_psr((DISK_PRIORITY as u16) << 8 | 2);
+    vm.memory_write(DISK_SR, DISK_IE);
+    command(&mut vm, COMMAND_READ, 0, 0x4000);
+    run(&mut vm, 3);
+    assert_eq!(vm.register_read(Registers::R_PC.into()), 0x3000);
+    assert!(vm.disk().unwrap().interrupt_requested());
+    vm.set_psr(2);
+    vm.memory_write(0x0100 + DISK_VECTOR as u16, 0x1000);
+    vm.memory_write(0x1000, 0x0FFF);
+    run(&mut vm, 1);
+    assert_eq!(vm.register_read(Registers::R_PC.into()), 0x1000);
+}
+
+#[test]
+fn test_interrupt_without_a_handler_faults() {
+    let mut vm = vm_with(disk(image(1), 0));
+    load(&mut vm, &[]);
+    vm.memory_write(DISK_SR, DISK_IE);
+    command(&mut vm, COMMAND_READ, 0, 0x4000);
+    run(&mut vm, 10);
+    assert_eq!(vm.fault(), Some(Fault::UnhandledInterrupt { pc: 0x3000, vector: DISK_VECTOR }));
+    assert_eq!(vm.register_read(Registers::R_PC.into()), 0x3000);
+    assert!(vm.disk().unwrap().interrupt_requested());
+}
+
+#[test]
+fn test_open_missing_image() {
+    let error = Disk::open(Path::new("/nonexistent/disk.img")).err().unwrap();
+    assert!(error.starts_with("/nonexistent/disk.img: "), "{error}");
+}
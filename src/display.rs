@@ -0,0 +1,90 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// Everything the program prints: OUT/PUTS/PUTSP/IN and writes to DDR.
+pub struct Display
+{
+    echo: bool,                   // forward to stdout
+    tee: Option<BufWriter<File>>, // --output file
+    captured: Option<Vec<u8>>,    // kept in memory for --expect and tests
+}
+
+impl Default for Display
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl Display
+{
+    pub fn new() -> Self
+    {
+        Self { echo: true, tee: None, captured: None }
+    }
+
+    /// Keep output in memory only, nothing reaches stdout.
+    pub fn captured_only() -> Self
+    {
+        Self { echo: false, tee: None, captured: Some(Vec::new()) }
+    }
+
+    /// Drop all output, for timing runs.
+    pub fn discard() -> Self
+    {
+        Self { echo: false, tee: None, captured: None }
+    }
+
+    pub fn tee_to(&mut self, path: &str) -> io::Result<()>
+    {
+        self.tee = Some(BufWriter::new(File::create(path)?));
+        Ok(())
+    }
+
+    pub fn capture(&mut self)
+    {
+        self.captured.get_or_insert_with(Vec::new);
+    }
+
+    pub fn captured(&self) -> &[u8]
+    {
+        self.captured.as_deref().unwrap_or(&[])
+    }
+
+    pub fn write(&mut self, bytes: &[u8])
+    {
+        if self.echo
+        {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(bytes);
+            let _ = stdout.flush();
+        }
+        if let Some(tee) = &mut self.tee
+        {
+            let _ = tee.write_all(bytes);
+        }
+        if let Some(captured) = &mut self.captured
+        {
+            captured.extend_from_slice(bytes);
+        }
+    }
+
+    /// VM status line such as the HALT notice. Shown on the terminal but not
+    /// part of the program output.
+    pub fn status(&mut self, message: &str)
+    {
+        if self.echo
+        {
+            println!("{message}");
+        }
+    }
+
+    pub fn flush(&mut self)
+    {
+        if let Some(tee) = &mut self.tee
+        {
+            let _ = tee.flush();
+        }
+    }
+}
@@ -34,7 +34,9 @@ pub enum CondtionalFlags {
 pub enum Memory_Mapped_registers
 {
     MR_KBSR = 0xFE00, /* keyboard status */
-    MR_KBDR = 0xFE02  /* keyboard data */
+    MR_KBDR = 0xFE02, /* keyboard data */
+    MR_DSR = 0xFE04,  /* display status */
+    MR_DDR = 0xFE06   /* display data */
 }
 
 
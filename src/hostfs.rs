@@ -0,0 +1,252 @@
+use std::cell::RefCell;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use crate::hardware::Registers;
+use crate::vm::VM;
+
+/// R0 = path string (one character per word), R1 = mode. Returns a handle.
+pub const TRAP_FOPEN: u8 = 0x2A;
+/// R0 = handle.
+pub const TRAP_FCLOSE: u8 = 0x2B;
+/// R0 = handle, R1 = address, R2 = count, R3 = unit. Returns the count read, 0 at the end.
+pub const TRAP_FREAD: u8 = 0x2C;
+/// R0 = handle, R1 = address, R2 = count, R3 = unit. Returns the count written.
+pub const TRAP_FWRITE: u8 = 0x2D;
+/// R0 = handle, R1 = offset in bytes, R2 = whence: 0 start, 1 current, 2 end.
+/// The offset is unsigned from the start and signed otherwise.
+pub const TRAP_FSEEK: u8 = 0x2E;
+
+/// Files a program may have open at once.
+pub const MAX_FILES: usize = 8;
+
+/// Longest path FOPEN accepts, in characters.
+const MAX_PATH: usize = 255;
+
+/// Why a file trap failed. The code is returned in R0; it is negative so a
+/// program can test for errors with BRn.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HostError
+{
+    NotFound = -1,
+    /// The path leaves the sandbox, names a directory, or the host refused.
+    Denied = -2,
+    BadHandle = -3,
+    TooManyFiles = -4,
+    /// Unknown mode, unit or whence, a count above x7FFF or a bad path string.
+    BadArgument = -5,
+    Io = -6,
+}
+
+impl From<io::Error> for HostError
+{
+    fn from(error: io::Error) -> Self
+    {
+        match error.kind()
+        {
+            io::ErrorKind::NotFound => HostError::NotFound,
+            io::ErrorKind::PermissionDenied => HostError::Denied,
+            _ => HostError::Io,
+        }
+    }
+}
+
+/// Files opened by an LC-3 program, all inside one host directory.
+pub struct HostFs
+{
+    root: PathBuf,
+    files: Vec<Option<File>>,
+}
+
+impl HostFs
+{
+    pub fn new(root: &Path) -> Result<Self, String>
+    {
+        let canonical = fs::canonicalize(root).map_err(|e| format!("{}: {e}", root.display()))?;
+        if !canonical.is_dir()
+        {
+            return Err(format!("{}: not a directory", root.display()));
+        }
+        Ok(Self { root: canonical, files: (0..MAX_FILES).map(|_| None).collect() })
+    }
+
+    /// `name` inside the sandbox. Absolute paths, `..` and symbolic links
+    /// that lead out of it are refused.
+    fn resolve(&self, name: &str) -> Result<PathBuf, HostError>
+    {
+        if name.is_empty()
+        {
+            return Err(HostError::BadArgument);
+        }
+        let relative = Path::new(name);
+        if !relative.components().all(|part| matches!(part, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(HostError::Denied);
+        }
+        let path = self.root.join(relative);
+        // A file about to be created does not exist yet, its directory must.
+        let existing = if fs::symlink_metadata(&path).is_ok() { path.clone() } else { path.parent().ok_or(HostError::Denied)?.to_path_buf() };
+        if !fs::canonicalize(existing)?.starts_with(&self.root)
+        {
+            return Err(HostError::Denied);
+        }
+        Ok(path)
+    }
+
+    /// Opens `name` with `mode` 0 read, 1 write (created or truncated),
+    /// 2 append (created) or 3 read and write (existing).
+    pub fn open(&mut self, name: &str, mode: u16) -> Result<u16, HostError>
+    {
+        let mut options = OpenOptions::new();
+        match mode
+        {
+            0 => options.read(true),
+            1 => options.write(true).create(true).truncate(true),
+            2 => options.append(true).create(true),
+            3 => options.read(true).write(true),
+            _ => return Err(HostError::BadArgument),
+        };
+        let path = self.resolve(name)?;
+        // Checked before opening so that every mode gets the same answer.
+        if fs::metadata(&path).is_ok_and(|metadata| metadata.is_dir())
+        {
+            return Err(HostError::Denied);
+        }
+        let slot = self.files.iter().position(Option::is_none).ok_or(HostError::TooManyFiles)?;
+        let file = options.open(path)?;
+        self.files[slot] = Some(file);
+        Ok(slot as u16)
+    }
+
+    pub fn close(&mut self, handle: u16) -> Result<u16, HostError>
+    {
+        self.files.get_mut(handle as usize).and_then(Option::take).ok_or(HostError::BadHandle)?;
+        Ok(0)
+    }
+
+    fn file(&mut self, handle: u16) -> Result<&mut File, HostError>
+    {
+        self.files.get_mut(handle as usize).and_then(Option::as_mut).ok_or(HostError::BadHandle)
+    }
+
+    /// Reads up to `count` units into memory from `address`. Unit 0 is a
+    /// byte per word in bits [7:0], unit 1 a big-endian word as in object files.
+    pub fn read(&mut self, vm: &mut VM, handle: u16, address: u16, count: u16, unit: u16) -> Result<u16, HostError>
+    {
+        let size = unit_size(unit, count)?;
+        let file = self.file(handle)?;
+        let mut buffer = Vec::with_capacity(count as usize * size);
+        Read::by_ref(file).take((count as usize * size) as u64).read_to_end(&mut buffer)?;
+        if buffer.len() % size != 0
+        {
+            // Leave the odd byte of an incomplete word for the next read.
+            file.seek(SeekFrom::Current(-1))?;
+        }
+        let units: Vec<u16> = match size
+        {
+            1 => buffer.iter().map(|&byte| byte as u16).collect(),
+            _ => buffer.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect(),
+        };
+        for (i, &value) in units.iter().enumerate()
+        {
+            vm.memory_write(address.wrapping_add(i as u16), value);
+        }
+        Ok(units.len() as u16)
+    }
+
+    /// Writes `count` units from memory at `address`, see `read` for units.
+    pub fn write(&mut self, vm: &mut VM, handle: u16, address: u16, count: u16, unit: u16) -> Result<u16, HostError>
+    {
+        let size = unit_size(unit, count)?;
+        let mut buffer = Vec::with_capacity(count as usize * size);
+        for i in 0..count
+        {
+            let value = vm.memory_read(address.wrapping_add(i));
+            match size
+            {
+                1 => buffer.push(value as u8),
+                _ => buffer.extend_from_slice(&value.to_be_bytes()),
+            }
+        }
+        self.file(handle)?.write_all(&buffer)?;
+        Ok(count)
+    }
+
+    pub fn seek(&mut self, handle: u16, offset: u16, whence: u16) -> Result<u16, HostError>
+    {
+        let position = match whence
+        {
+            0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset as i16 as i64),
+            2 => SeekFrom::End(offset as i16 as i64),
+            _ => return Err(HostError::BadArgument),
+        };
+        self.file(handle)?.seek(position)?;
+        Ok(0)
+    }
+}
+
+fn unit_size(unit: u16, count: u16) -> Result<usize, HostError>
+{
+    match unit
+    {
+        _ if count > 0x7FFF => Err(HostError::BadArgument),
+        0 => Ok(1),
+        1 => Ok(2),
+        _ => Err(HostError::BadArgument),
+    }
+}
+
+/// The path FOPEN was given, ASCII one character per word up to x0000.
+fn read_path(vm: &mut VM, address: u16) -> Result<String, HostError>
+{
+    let mut path = String::new();
+    for i in 0..=MAX_PATH as u16
+    {
+        match vm.memory_read(address.wrapping_add(i))
+        {
+            0 => return Ok(path),
+            character @ 0x01..=0x7F => path.push(character as u8 as char),
+            _ => return Err(HostError::BadArgument),
+        }
+    }
+    Err(HostError::BadArgument)
+}
+
+type Service = fn(&mut HostFs, &mut VM, [u16; 4]) -> Result<u16, HostError>;
+
+fn register(vm: &mut VM, host: &Rc<RefCell<HostFs>>, vector: u8, service: Service)
+{
+    let host = host.clone();
+    vm.register_trap(vector, move |vm| {
+        let arguments = [0, 1, 2, 3].map(|register| vm.register_read(register));
+        let result = service(&mut host.borrow_mut(), vm, arguments);
+        let value = match result
+        {
+            Ok(value) => value,
+            Err(error) => error as i16 as u16,
+        };
+        vm.register_write(Registers::R_R0.into(), value);
+        vm.update_flags(Registers::R_R0.into());
+    });
+}
+
+/// Services the file traps x2A-x2E on `vm` with files confined to `root`.
+pub fn install(vm: &mut VM, root: &Path) -> Result<(), String>
+{
+    let host = Rc::new(RefCell::new(HostFs::new(root)?));
+    register(vm, &host, TRAP_FOPEN, |host, vm, [path, mode, ..]| {
+        let name = read_path(vm, path)?;
+        host.open(&name, mode)
+    });
+    register(vm, &host, TRAP_FCLOSE, |host, _, [handle, ..]| host.close(handle));
+    register(vm, &host, TRAP_FREAD, |host, vm, [handle, address, count, unit]| host.read(vm, handle, address, count, unit));
+    register(vm, &host, TRAP_FWRITE, |host, vm, [handle, address, count, unit]| host.write(vm, handle, address, count, unit));
+    register(vm, &host, TRAP_FSEEK, |host, _, [handle, offset, whence, _]| host.seek(handle, offset, whence));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests;
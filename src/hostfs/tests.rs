@@ -0,0 +1,260 @@
+use super::*;
+
+use crate::display::Display;
+use crate::hardware::CondtionalFlags;
+use crate::vm::Fault;
+
+/// An empty sandbox directory of its own for each test, removed when dropped.
+struct Sandbox(PathBuf);
+
+impl std::ops::Deref for Sandbox {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for Sandbox {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn sandbox(name: &str) -> Sandbox {
+    let dir = std::env::temp_dir().join(format!("lc3box-hostfs-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    Sandbox(dir)
+}
+
+fn vm_in(root: &Path) -> VM {
+    let mut vm = VM::new();
+    vm.set_display(Display::captured_only());
+    install(&mut vm, root).unwrap();
+    vm
+}
+
+/// Runs TRAP `vector` at x3000 with R0-R3 set from `arguments` and returns R0.
+fn trap(vm: &mut VM, vector: u8, arguments: &[u16]) -> i16 {
+    for (register, &value) in arguments.iter().enumerate() {
+        vm.register_write(register, value);
+    }
+    vm.memory_write(0x3000, 0xF000 | vector as u16);
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    vm.step();
+    assert_eq!(vm.register_read(7), 0x3001);
+    vm.register_read(0) as i16
+}
+
+fn put_string(vm: &mut VM, address: u16, text: &str) {
+    for (i, byte) in text.bytes().chain([0]).enumerate() {
+        vm.memory_write(address + i as u16, byte as u16);
+    }
+}
+
+fn open(vm: &mut VM, name: &str, mode: u16) -> i16 {
+    put_string(vm, 0x5000, name);
+    trap(vm, TRAP_FOPEN, &[0x5000, mode])
+}
+
+fn cond(vm: &mut VM) -> u16 {
+    vm.register_read(Registers::R_COND.into())
+}
+
+#[test]
+fn test_write_then_read_bytes() {
+    let root = sandbox("bytes");
+    let mut vm = vm_in(&root);
+    put_string(&mut vm, 0x4000, "hello");
+    let handle = open(&mut vm, "out.txt", 1) as u16;
+    assert_eq!(handle, 0);
+    assert_eq!(trap(&mut vm, TRAP_FWRITE, &[handle, 0x4000, 5, 0]), 5);
+    assert_eq!(trap(&mut vm, TRAP_FCLOSE, &[handle]), 0);
+    assert_eq!(fs::read(root.join("out.txt")).unwrap(), b"hello");
+
+    let handle = open(&mut vm, "out.txt", 0) as u16;
+    assert_eq!(trap(&mut vm, TRAP_FREAD, &[handle, 0x4100, 3, 0]), 3);
+    assert_eq!(trap(&mut vm, TRAP_FREAD, &[handle, 0x4103, 10, 0]), 2);
+    let read: Vec<u16> = (0x4100..0x4105).map(|address| vm.memory_peek(address)).collect();
+    assert_eq!(read, b"hello".map(|byte| byte as u16));
+    // At the end of the file a read returns 0 and sets Z
+    assert_eq!(trap(&mut vm, TRAP_FREAD, &[handle, 0x4100, 1, 0]), 0);
+    assert_eq!(cond(&mut vm), CondtionalFlags::FL_ZRO as u16);
+}
+
+#[test]
+fn test_words_are_big_endian() {
+    let root = sandbox("words");
+    let mut vm = vm_in(&root);
+    vm.memory_write(0x4000, 0x3000);
+    vm.memory_write(0x4001, 0xF025);
+    let handle = open(&mut vm, "halt.obj", 1) as u16;
+    assert_eq!(trap(&mut vm, TRAP_FWRITE, &[handle, 0x4000, 2, 1]), 2);
+    trap(&mut vm, TRAP_FCLOSE, &[handle]);
+    assert_eq!(fs::read(root.join("halt.obj")).unwrap(), [0x30, 0x00, 0xF0, 0x25]);
+
+    fs::write(root.join("odd.bin"), [0x12, 0x34, 0x56]).unwrap();
+    let handle = open(&mut vm, "odd.bin", 0) as u16;
+    assert_eq!(trap(&mut vm, TRAP_FREAD, &[handle, 0x4200, 2, 1]), 1);
+    assert_eq!(vm.memory_peek(0x4200), 0x1234);
+    // The odd byte is still there for a byte read
+    assert_eq!(trap(&mut vm, TRAP_FREAD, &[handle, 0x4201, 2, 0]), 1);
+    assert_eq!(vm.memory_peek(0x4201), 0x56);
+}
+
+#[test]
+fn test_seek() {
+    let root = sandbox("seek");
+    fs::write(root.join("abc.txt"), "abcdef").unwrap();
+    let mut vm = vm_in(&root);
+    let handle = open(&mut vm, "abc.txt", 0) as u16;
+    assert_eq!(trap(&mut vm, TRAP_FSEEK, &[handle, 2, 0]), 0);
+    trap(&mut vm, TRAP_FREAD, &[handle, 0x4000, 1, 0]);
+    assert_eq!(vm.memory_peek(0x4000), b'c' as u16);
+    trap(&mut vm, TRAP_FSEEK, &[handle, -1i16 as u16, 2]);
+    trap(&mut vm, TRAP_FREAD, &[handle, 0x4000, 1, 0]);
+    assert_eq!(vm.memory_peek(0x4000), b'f' as u16);
+    trap(&mut vm, TRAP_FSEEK, &[handle, -3i16 as u16, 1]);
+    trap(&mut vm, TRAP_FREAD, &[handle, 0x4000, 1, 0]);
+    assert_eq!(vm.memory_peek(0x4000), b'd' as u16);
+    assert_eq!(trap(&mut vm, TRAP_FSEEK, &[handle, 0, 3]), HostError::BadArgument as i16);
+}
+
+#[test]
+fn test_append_and_read_write_modes() {
+    let root = sandbox("modes");
+    fs::write(root.join("log.txt"), "ab").unwrap();
+    let mut vm = vm_in(&root);
+    put_string(&mut vm, 0x4000, "cd");
+    let handle = open(&mut vm, "log.txt", 2) as u16;
+    trap(&mut vm, TRAP_FWRITE, &[handle, 0x4000, 2, 0]);
+    trap(&mut vm, TRAP_FCLOSE, &[handle]);
+    assert_eq!(fs::read(root.join("log.txt")).unwrap(), b"abcd");
+
+    let handle = open(&mut vm, "log.txt", 3) as u16;
+    trap(&mut vm, TRAP_FSEEK, &[handle, 1, 0]);
+    trap(&mut vm, TRAP_FWRITE, &[handle, 0x4000, 1, 0]);
+    trap(&mut vm, TRAP_FCLOSE, &[handle]);
+    assert_eq!(fs::read(root.join("log.txt")).unwrap(), b"accd");
+    // Mode 3 does not create files
+    assert_eq!(open(&mut vm, "new.txt", 3), HostError::NotFound as i16);
+}
+
+#[test]
+fn test_paths_outside_the_sandbox_are_denied() {
+    let root = sandbox("escape");
+    fs::create_dir(root.join("inner")).unwrap();
+    fs::write(root.join("inner/data.txt"), "x").unwrap();
+    let mut vm = vm_in(&root.join("inner"));
+    for name in ["../escape.txt", "/etc/passwd", "a/../../b"] {
+        assert_eq!(open(&mut vm, name, 1), HostError::Denied as i16, "{name}");
+        assert_eq!(cond(&mut vm), CondtionalFlags::FL_NEG as u16);
+    }
+    assert!(!root.join("escape.txt").exists());
+    assert!(open(&mut vm, "./data.txt", 0) >= 0);
+    assert_eq!(open(&mut vm, ".", 0), HostError::Denied as i16);
+}
+
+#[test]
+fn test_directories_are_denied_in_every_mode() {
+    let root = sandbox("directories");
+    fs::create_dir(root.join("sub")).unwrap();
+    let mut vm = vm_in(&root);
+    for name in [".", "sub", "./sub"] {
+        for mode in 0..4 {
+            assert_eq!(open(&mut vm, name, mode), HostError::Denied as i16, "{name} mode {mode}");
+        }
+    }
+    assert_eq!(fs::read_dir(root.join("sub")).unwrap().count(), 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symbolic_links_out_of_the_sandbox_are_denied() {
+    let root = sandbox("symlink");
+    let outside = sandbox("symlink-outside");
+    fs::write(outside.join("secret.txt"), "x").unwrap();
+    std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("link.txt")).unwrap();
+    std::os::unix::fs::symlink(outside.join("new.txt"), root.join("dangling.txt")).unwrap();
+    std::os::unix::fs::symlink(&outside, root.join("dir")).unwrap();
+    let mut vm = vm_in(&root);
+
+    assert_eq!(open(&mut vm, "link.txt", 0), HostError::Denied as i16);
+    assert_eq!(open(&mut vm, "dir/secret.txt", 0), HostError::Denied as i16);
+    assert_eq!(open(&mut vm, "dir/created.txt", 1), HostError::Denied as i16);
+    assert!(open(&mut vm, "dangling.txt", 1) < 0);
+    assert!(!outside.join("new.txt").exists());
+    assert!(!outside.join("created.txt").exists());
+}
+
+#[test]
+fn test_errors_set_negative_codes() {
+    let root = sandbox("errors");
+    let mut vm = vm_in(&root);
+    assert_eq!(open(&mut vm, "missing.txt", 0), HostError::NotFound as i16);
+    assert_eq!(open(&mut vm, "", 0), HostError::BadArgument as i16);
+    assert_eq!(open(&mut vm, "x.txt", 4), HostError::BadArgument as i16);
+    assert_eq!(trap(&mut vm, TRAP_FCLOSE, &[3]), HostError::BadHandle as i16);
+    assert_eq!(trap(&mut vm, TRAP_FREAD, &[0xFFFF, 0x4000, 1, 0]), HostError::BadHandle as i16);
+
+    let handle = open(&mut vm, "x.txt", 1) as u16;
+    assert_eq!(trap(&mut vm, TRAP_FWRITE, &[handle, 0x4000, 0x8000, 0]), HostError::BadArgument as i16);
+    assert_eq!(trap(&mut vm, TRAP_FWRITE, &[handle, 0x4000, 1, 2]), HostError::BadArgument as i16);
+    // Reading a file opened only for writing is refused by the host
+    assert!(trap(&mut vm, TRAP_FREAD, &[handle, 0x4000, 1, 0]) < 0);
+    assert_eq!(trap(&mut vm, TRAP_FCLOSE, &[handle]), 0);
+    assert_eq!(trap(&mut vm, TRAP_FCLOSE, &[handle]), HostError::BadHandle as i16);
+    assert!(vm.state_read());
+}
+
+#[test]
+fn test_too_many_files() {
+    let root = sandbox("many");
+    let mut vm = vm_in(&root);
+    for expected in 0..MAX_FILES {
+        assert_eq!(open(&mut vm, &format!("f{expected}"), 1), expected as i16);
+    }
+    assert_eq!(open(&mut vm, "one-more", 1), HostError::TooManyFiles as i16);
+    trap(&mut vm, TRAP_FCLOSE, &[5]);
+    assert_eq!(open(&mut vm, "one-more", 1), 5);
+}
+
+#[test]
+fn test_path_must_be_terminated_ascii() {
+    let root = sandbox("path");
+    let mut vm = vm_in(&root);
+    vm.memory_write(0x5000, 0x00E9);
+    vm.memory_write(0x5001, 0);
+    assert_eq!(trap(&mut vm, TRAP_FOPEN, &[0x5000, 1]), HostError::BadArgument as i16);
+    for address in 0x6000..0x6200 {
+        vm.memory_write(address, b'a' as u16);
+    }
+    assert_eq!(trap(&mut vm, TRAP_FOPEN, &[0x6000, 1]), HostError::BadArgument as i16);
+}
+
+#[test]
+fn test_file_traps_are_off_without_install() {
+    let mut vm = VM::new();
+    vm.set_display(Display::captured_only());
+    vm.memory_write(0x3000, 0xF02A);
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    vm.step();
+    assert_eq!(vm.fault(), Some(Fault::UnknownTrap { pc: 0x3000, vector: 0x2A }));
+}
+
+#[test]
+fn test_install_needs_a_directory() {
+    let root = sandbox("install");
+    fs::write(root.join("file"), "").unwrap();
+    let mut vm = VM::new();
+    assert!(install(&mut vm, &root.join("file")).is_err());
+    assert!(install(&mut vm, &root.join("missing")).is_err());
+    assert_eq!(vm.trap_binding(TRAP_FOPEN), None);
+}
@@ -3,26 +3,46 @@ use crate::vm::VM;
 use crate::hardware::MEMORY_MAX;
 
 
-pub fn read_image(path: &str, vm: &mut VM) {
+/// Loads an LC-3 object file and returns its origin and length in words.
+pub fn read_image(path: &str, vm: &mut VM) -> Result<(u16, usize), String> {
     let buffer = match fs::read(path) {
         Ok(file) => file,
-        Err(_) => panic!("file not found"),
+        Err(e) => return Err(format!("{path}: {e}")),
     };
+    load_image(&buffer, vm).map_err(|e| format!("{path}: {e}"))
+}
+
+/// Loads an object file already in memory. A trailing odd byte is ignored.
+pub fn load_image(buffer: &[u8], vm: &mut VM) -> Result<(u16, usize), String> {
+    let (base, words) = parse_image(buffer)?;
+    for (i, &value) in words.iter().enumerate() {
+        vm.memory_write(base.wrapping_add(i as u16), value);
+    }
+    Ok((base, words.len()))
+}
 
+/// The origin and words of an object file without loading it.
+pub fn parse_image(buffer: &[u8]) -> Result<(u16, Vec<u16>), String> {
     if buffer.len() < 2 {
-        panic!("invalid image file: too small");
+        return Err("invalid image file: too small".to_string());
     }
 
     // first two bytes: origin (big endian in LC-3 format)
     let base: u16 = ((buffer[0] as u16) << 8) | buffer[1] as u16;
 
-    // number of 16-bit words we can actually read
-    let words_available = ((buffer.len() - 2) / 2) as u16;
+    // number of 16-bit words we can actually read; a longer file would only
+    // wrap around and overwrite its own start
+    let words_available = ((buffer.len() - 2) / 2).min(MEMORY_MAX);
 
-    for i in 0..words_available {
-        let idx = 2 + (i as usize * 2);
-        let value = ((buffer[idx] as u16) << 8) | (buffer[idx + 1] as u16);
-        vm.memory_write(base + i, value);
-    }
+    let words = buffer[2..2 + words_available * 2]
+        .chunks_exact(2)
+        .map(|pair| ((pair[0] as u16) << 8) | pair[1] as u16)
+        .collect();
+    Ok((base, words))
 }
 
+/// Reads an object file, see `parse_image`.
+pub fn read_words(path: &str) -> Result<(u16, Vec<u16>), String> {
+    let buffer = fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+    parse_image(&buffer).map_err(|e| format!("{path}: {e}"))
+}
@@ -1,5 +1,6 @@
 use crossterm::terminal::{enable_raw_mode, disable_raw_mode};
 use crossterm::event::{self, Event};
+use std::io::{self, IsTerminal};
 use std::time::Duration;
 
 /// Enable raw mode (equivalent to disable_input_buffering in C)
@@ -15,4 +16,9 @@ pub fn restore_input_buffering() {
 /// Check if a key has been pressed (non-blocking)
 pub fn check_key() -> bool {
     event::poll(Duration::from_millis(0)).unwrap_or(false)
-}
\ No newline at end of file
+}
+/// True when both stdin and stdout are attached to a terminal. Anything else
+/// (pipes, files, CI runners) has to run headless.
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal()
+}
@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+use std::fs;
+
+/// Keystrokes fed to the keyboard at fixed instruction counts so programs that
+/// poll KBSR see exactly the same input timing on every run.
+///
+/// Each non-empty line is `<when> <keys>`:
+///
+/// * `when` is either an absolute step number (`250`) or a delay relative to the
+///   previous entry (`+40`).
+/// * `keys` is a quoted string (`"ab\n"`, escapes `\n \t \r \0 \\ \" \xHH`), a
+///   named key (`enter`, `space`, `tab`, `esc`, `backspace`) or a hex byte (`x41`).
+///
+/// Everything after `#` on a line that is not inside a string is a comment.
+pub struct InputScript
+{
+    keys: VecDeque<(u64, u8)>,
+}
+
+impl InputScript
+{
+    pub fn load(path: &str) -> Result<Self, String>
+    {
+        let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        Self::parse(&text).map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String>
+    {
+        let mut keys = VecDeque::new();
+        let mut last_step = 0u64;
+        for (number, line) in text.lines().enumerate()
+        {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#')
+            {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {message}", number + 1);
+            let (when, rest) = line.split_once(char::is_whitespace).ok_or_else(|| error("expected `<step> <keys>`"))?;
+            let step = match when.strip_prefix('+')
+            {
+                Some(delay) => delay.parse::<u64>().map(|d| last_step + d),
+                None => when.parse::<u64>(),
+            }
+            .map_err(|_| error("invalid step"))?;
+            if step < last_step
+            {
+                return Err(error("steps must not decrease"));
+            }
+            for byte in parse_keys(rest.trim()).map_err(|e| error(&e))?
+            {
+                keys.push_back((step, byte));
+            }
+            last_step = step;
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn from_keys(keys: impl IntoIterator<Item = (u64, u8)>) -> Self
+    {
+        Self { keys: keys.into_iter().collect() }
+    }
+
+    /// Keys not delivered yet, with the step each becomes available at.
+    pub fn remaining(&self) -> impl Iterator<Item = (u64, u8)> + '_
+    {
+        self.keys.iter().copied()
+    }
+
+    /// Whether a key has become available by `step`.
+    pub fn key_ready(&self, step: u64) -> bool
+    {
+        self.keys.front().is_some_and(|&(at, _)| at <= step)
+    }
+
+    /// Next key in script order. GETC/IN block until a key arrives, so they
+    /// consume the next entry even if its step has not been reached yet.
+    pub fn next_key(&mut self) -> Option<u8>
+    {
+        self.keys.pop_front().map(|(_, key)| key)
+    }
+}
+
+fn parse_keys(keys: &str) -> Result<Vec<u8>, String>
+{
+    if let Some(quoted) = keys.strip_prefix('"')
+    {
+        return parse_string(quoted);
+    }
+    let token = keys.split('#').next().unwrap_or("").trim();
+    let byte = match token
+    {
+        "enter" => b'\n',
+        "space" => b' ',
+        "tab" => b'\t',
+        "esc" => 0x1B,
+        "backspace" => 0x08,
+        _ => match token.strip_prefix('x')
+        {
+            Some(hex) => u8::from_str_radix(hex, 16).map_err(|_| format!("invalid hex key `{token}`"))?,
+            None => return Err(format!("unknown key `{token}`")),
+        },
+    };
+    Ok(vec![byte])
+}
+
+fn parse_string(quoted: &str) -> Result<Vec<u8>, String>
+{
+    let mut bytes = Vec::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next()
+    {
+        match c
+        {
+            '"' =>
+            {
+                let rest = chars.as_str().trim();
+                if !rest.is_empty() && !rest.starts_with('#')
+                {
+                    return Err("unexpected text after string".to_string());
+                }
+                return Ok(bytes);
+            }
+            '\\' => bytes.push(match chars.next()
+            {
+                Some('n') => b'\n',
+                Some('t') => b'\t',
+                Some('r') => b'\r',
+                Some('0') => 0,
+                Some('\\') => b'\\',
+                Some('"') => b'"',
+                Some('x') =>
+                {
+                    let hex: String = chars.by_ref().take(2).collect();
+                    u8::from_str_radix(&hex, 16).map_err(|_| format!("invalid escape `\\x{hex}`"))?
+                }
+                other => return Err(format!("invalid escape `\\{}`", other.map_or(String::new(), String::from))),
+            }),
+            c if c.is_ascii() => bytes.push(c as u8),
+            c => return Err(format!("non-ASCII character `{c}`")),
+        }
+    }
+    Err("unterminated string".to_string())
+}
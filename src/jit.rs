@@ -0,0 +1,236 @@
+use std::rc::Rc;
+
+use crate::decode::{decode, Decoded};
+use crate::hardware::{Memory_Mapped_registers::MR_KBSR, MEMORY_MAX};
+use crate::vm::VM;
+
+/// Times a block entry must be reached by the interpreter before it is translated.
+pub const DEFAULT_THRESHOLD: u32 = 50;
+
+/// Instructions per block at most, so one block never runs unboundedly long.
+const MAX_BLOCK: usize = 64;
+
+/// One translated instruction: a closure with its operands baked in.
+pub struct Op
+{
+    /// PC value while the instruction runs, its address plus one.
+    pub next_pc: u16,
+    /// Writes memory, so it may invalidate the block it belongs to.
+    pub store: bool,
+    pub run: Box<dyn Fn(&mut VM)>,
+}
+
+/// Straight-line run of instructions ending at BR, JMP, JSR/JSRR, TRAP, RTI
+/// or the reserved opcode, whichever comes first.
+pub struct Block
+{
+    pub start: u16,
+    pub ops: Vec<Op>,
+}
+
+impl Block
+{
+    fn end(&self) -> u16
+    {
+        self.start + self.ops.len() as u16 - 1
+    }
+}
+
+fn ends_block(decoded: Decoded) -> bool
+{
+    matches!(
+        decoded,
+        Decoded::Br { .. } | Decoded::Jmp { .. } | Decoded::Jsr { .. } | Decoded::Jsrr { .. } | Decoded::Native(_)
+    )
+}
+
+/// Whether `instruction` transfers control, so the next PC may start a block.
+pub fn is_branch(instruction: u16) -> bool
+{
+    ends_block(decode(instruction))
+}
+
+/// Closure-threaded form of `decoded`, same effect as `Decoded::execute`.
+fn compile(decoded: Decoded) -> Box<dyn Fn(&mut VM)>
+{
+    match decoded
+    {
+        Decoded::AddImm { dr, sr1, imm } => Box::new(move |vm| {
+            let value = vm.register_read(sr1 as usize).wrapping_add(imm);
+            vm.register_write(dr as usize, value);
+            vm.update_flags(dr as usize);
+        }),
+        Decoded::Add { dr, sr1, sr2 } => Box::new(move |vm| {
+            let value = vm.register_read(sr1 as usize).wrapping_add(vm.register_read(sr2 as usize));
+            vm.register_write(dr as usize, value);
+            vm.update_flags(dr as usize);
+        }),
+        Decoded::AndImm { dr, sr1, imm } => Box::new(move |vm| {
+            let value = vm.register_read(sr1 as usize) & imm;
+            vm.register_write(dr as usize, value);
+            vm.update_flags(dr as usize);
+        }),
+        Decoded::Ldr { dr, base, offset } => Box::new(move |vm| {
+            let address = vm.register_read(base as usize).wrapping_add(offset);
+            let value = vm.memory_read(address);
+            vm.register_write(dr as usize, value);
+            vm.update_flags(dr as usize);
+        }),
+        Decoded::Str { sr, base, offset } => Box::new(move |vm| {
+            let address = vm.register_read(base as usize).wrapping_add(offset);
+            let value = vm.register_read(sr as usize);
+            vm.memory_write(address, value);
+        }),
+        other => Box::new(move |vm| other.execute(vm)),
+    }
+}
+
+/// Translates hot basic blocks and keeps them until the code under them is
+/// written. Device registers are still reached through `memory_read` and
+/// `memory_write`, so they behave exactly as in the interpreter.
+pub struct Jit
+{
+    threshold: u32,
+    counts: Vec<u32>,
+    blocks: Vec<Option<Rc<Block>>>,
+    /// Entries of the blocks in `blocks`.
+    live: Vec<u16>,
+    /// Number of blocks containing each address.
+    covered: Vec<u16>,
+    /// The interpreter just executed a branch, so PC is a block entry.
+    at_entry: bool,
+    /// Set by `invalidate` to stop the block that is running.
+    aborted: bool,
+    pub translated: u64,
+    pub invalidated: u64,
+}
+
+impl Jit
+{
+    pub fn new(threshold: u32) -> Self
+    {
+        Self {
+            threshold: threshold.max(1),
+            counts: vec![0; MEMORY_MAX],
+            blocks: vec![None; MEMORY_MAX],
+            live: Vec::new(),
+            covered: vec![0; MEMORY_MAX],
+            at_entry: true,
+            aborted: false,
+            translated: 0,
+            invalidated: 0,
+        }
+    }
+
+    /// The block starting at `pc`, translated from `memory` once the entry
+    /// got hot. `None` means the interpreter should run the instruction.
+    pub fn block_at(&mut self, pc: u16, memory: &[u16]) -> Option<Rc<Block>>
+    {
+        if let Some(block) = &self.blocks[pc as usize]
+        {
+            self.aborted = false;
+            return Some(block.clone());
+        }
+        if !self.at_entry
+        {
+            return None;
+        }
+        self.counts[pc as usize] += 1;
+        if self.counts[pc as usize] < self.threshold
+        {
+            return None;
+        }
+        self.counts[pc as usize] = 0;
+        let block = Rc::new(translate(pc, memory)?);
+        for address in block.start..=block.end()
+        {
+            self.covered[address as usize] += 1;
+        }
+        self.blocks[pc as usize] = Some(block.clone());
+        self.live.push(pc);
+        self.translated += 1;
+        self.aborted = false;
+        Some(block)
+    }
+
+    /// Notes what the interpreter executed instead of a block.
+    pub fn interpreted(&mut self, instruction: u16)
+    {
+        self.at_entry = is_branch(instruction);
+    }
+
+    /// Notes that a block finished, its last instruction was a branch or it
+    /// stopped early.
+    pub fn block_done(&mut self)
+    {
+        self.at_entry = true;
+    }
+
+    /// Drops every block containing `address`, called for each memory write.
+    pub fn invalidate(&mut self, address: u16)
+    {
+        if self.covered[address as usize] == 0
+        {
+            return;
+        }
+        let blocks = &mut self.blocks;
+        let covered = &mut self.covered;
+        let mut dropped = 0;
+        self.live.retain(|&entry| {
+            let block = blocks[entry as usize].as_ref().unwrap();
+            if !(block.start..=block.end()).contains(&address)
+            {
+                return true;
+            }
+            for address in block.start..=block.end()
+            {
+                covered[address as usize] -= 1;
+            }
+            blocks[entry as usize] = None;
+            dropped += 1;
+            false
+        });
+        self.invalidated += dropped;
+        self.aborted = true;
+    }
+
+    /// Whether a block was invalidated since the running block started.
+    pub fn aborted(&self) -> bool
+    {
+        self.aborted
+    }
+
+    /// Forgets all translations, for when memory is replaced wholesale.
+    pub fn flush(&mut self)
+    {
+        for entry in self.live.drain(..)
+        {
+            self.blocks[entry as usize] = None;
+        }
+        self.covered.fill(0);
+        self.counts.fill(0);
+        self.at_entry = true;
+    }
+}
+
+/// Block starting at `pc`, or `None` when `pc` is in the device page.
+fn translate(pc: u16, memory: &[u16]) -> Option<Block>
+{
+    let mut ops = Vec::new();
+    let mut address = pc;
+    while address < MR_KBSR as u16 && ops.len() < MAX_BLOCK
+    {
+        let decoded = decode(memory[address as usize]);
+        let store = matches!(decoded, Decoded::St { .. } | Decoded::Sti { .. } | Decoded::Str { .. } | Decoded::Native(_));
+        ops.push(Op { next_pc: address + 1, store, run: compile(decoded) });
+        address += 1;
+        if ends_block(decoded)
+        {
+            break;
+        }
+    }
+    (!ops.is_empty()).then_some(Block { start: pc, ops })
+}
+
+#[cfg(test)]
+mod tests;
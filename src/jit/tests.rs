@@ -0,0 +1,159 @@
+use super::*;
+
+use crate::bench;
+use crate::display::Display;
+use crate::hardware::Registers;
+use crate::input_script::InputScript;
+use crate::keyboard::Keyboard;
+use crate::replay::InputLog;
+use crate::vm::Fault;
+use crate::timing::TimingModel;
+
+fn load(program: &[u16], jit: bool) -> VM {
+    let mut vm = VM::new();
+    if jit {
+        vm.set_jit(Jit::new(DEFAULT_THRESHOLD));
+    }
+    vm.set_display(Display::captured_only());
+    vm.set_keyboard(Keyboard::stream(std::io::empty()));
+    for (i, &word) in program.iter().enumerate() {
+        vm.memory_write(0x3000 + i as u16, word);
+    }
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    vm
+}
+
+fn finish(mut vm: VM) -> VM {
+    while vm.state_read() {
+        vm.run_block();
+    }
+    vm
+}
+
+/// Runs `program` with and without the JIT and checks nothing observable differs.
+fn differential(program: &[u16]) -> VM {
+    let mut plain = finish(load(program, false));
+    let mut jitted = finish(load(program, true));
+    assert!(jitted.arch_state() == plain.arch_state());
+    assert_eq!(jitted.steps(), plain.steps());
+    assert_eq!(jitted.display().captured(), plain.display().captured());
+    jitted
+}
+
+#[test]
+fn test_hot_loops_are_translated() {
+    let mut vm = differential(&bench::arithmetic_loop(60, 30).segments[0].1);
+    let jit = vm.jit().unwrap();
+    assert!(jit.translated >= 2);
+    assert_eq!(jit.invalidated, 0);
+    assert_eq!(vm.register_read(0), (60 * 30 * 3) as u16);
+}
+
+#[test]
+fn test_builtin_benchmarks_match_the_interpreter() {
+    for program in [bench::checksum(3), bench::memory_copy(2), bench::fibonacci(12), bench::string_output(60)] {
+        let mut plain = bench::load(&program, bench::Mode::Table);
+        let mut jitted = bench::load(&program, bench::Mode::Jit);
+        while plain.state_read() {
+            plain.step();
+        }
+        while jitted.state_read() {
+            jitted.run_block();
+        }
+        assert!(jitted.arch_state() == plain.arch_state(), "{}", program.name);
+        assert_eq!(jitted.steps(), plain.steps(), "{}", program.name);
+        assert!(jitted.jit().unwrap().translated > 0, "{}", program.name);
+    }
+}
+
+#[test]
+fn test_store_into_the_running_block_leaves_it() {
+    // Every iteration rewrites x3006, the last instruction of its own block,
+    // into ADD R0, R0, #(R1 & 7).
+    let program = [
+        0x2209, // LD R1, COUNT
+        0x2609, // LD R3, TEMPLATE
+        0x5020, // AND R0, R0, #0
+        0x5867, // LOOP: AND R4, R1, #7
+        0x1903, // ADD R4, R4, R3
+        0x3800, // ST R4, x3006
+        0x0000, // patched
+        0x127F, // ADD R1, R1, #-1
+        0x03FA, // BRp LOOP
+        0xF025, // HALT
+        200,    // COUNT
+        0x1020, // TEMPLATE: ADD R0, R0, #0
+    ];
+    let mut vm = differential(&program);
+    assert_eq!(vm.register_read(0), (1..=200).map(|n| n & 7).sum::<u16>());
+    // An invalidated entry has to get hot again before it is retranslated.
+    let jit = vm.jit().unwrap();
+    assert_eq!((jit.translated, jit.invalidated), (3, 3));
+}
+
+#[test]
+fn test_keyboard_polling_sees_keys_at_the_same_step() {
+    // Polls KBSR until a key arrives, then reads it from KBDR.
+    let program = [
+        0xA003, // LDI R0, KBSR
+        0x07FE, // BRzp x3000
+        0xA202, // LDI R1, KBDR
+        0xF025, // HALT
+        0xFE00,
+        0xFE02,
+    ];
+    let run = |jit| {
+        let mut vm = load(&program, jit);
+        vm.set_keyboard(Keyboard::Script(InputScript::parse("501 \"z\"").unwrap()));
+        finish(vm)
+    };
+    let plain = run(false);
+    let mut jitted = run(true);
+    assert_eq!((jitted.steps(), jitted.register_read(1)), (plain.steps(), b'z' as u16));
+    assert!(jitted.arch_state() == plain.arch_state());
+    assert!(jitted.jit().unwrap().translated > 0);
+}
+
+#[test]
+fn test_fault_inside_a_block_stops_it() {
+    // The block ADD, LDI, BR polls KBSR at steps 1, 4, 7, ... but the
+    // recording saw a key at step 500, so replay diverges in the LDI.
+    let program = [
+        0x14A1, // ADD R2, R2, #1
+        0xA002, // LDI R0, KBSR
+        0x07FD, // BRzp x3000
+        0xF025, // HALT
+        0xFE00,
+    ];
+    let run = |jit| {
+        let mut vm = load(&program, jit);
+        vm.set_keyboard(Keyboard::Replay(InputLog::parse("ready 500\nbyte x7a").unwrap()));
+        finish(vm)
+    };
+    let plain = run(false);
+    let jitted = run(true);
+    assert_eq!(plain.fault(), Some(Fault::ReplayDiverged { step: 502, recorded: 500 }));
+    assert_eq!((jitted.fault(), jitted.steps()), (plain.fault(), plain.steps()));
+    assert!(jitted.arch_state() == plain.arch_state());
+    assert!(jitted.jit().unwrap().translated > 0);
+}
+
+#[test]
+fn test_observed_runs_use_the_interpreter() {
+    let mut vm = load(&bench::arithmetic_loop(10, 10).segments[0].1, true);
+    vm.set_timing(TimingModel::default());
+    let vm = finish(vm);
+    assert_eq!(vm.jit().unwrap().translated, 0);
+}
+
+#[test]
+fn test_flush_forgets_blocks() {
+    let mut jit = Jit::new(1);
+    let memory = [0x1021, 0x0FFE].repeat(2);
+    assert_eq!(jit.block_at(0, &memory).unwrap().ops.len(), 2);
+    assert!(jit.block_at(0, &memory).is_some());
+    jit.flush();
+    jit.invalidate(0);
+    assert_eq!(jit.invalidated, 0);
+    assert_eq!(jit.translated, 1);
+}
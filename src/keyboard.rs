@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use crate::input_buffering::check_key;
+use crate::input_script::InputScript;
+use crate::replay::{InputLog, Recorder};
+
+/// Value loaded into R0 by GETC/IN once a headless input stream is exhausted.
+pub const EOF_CHAR: u16 = 0xFFFF;
+
+/// Source of the bytes seen through KBSR/KBDR and the GETC/IN traps.
+pub enum Keyboard
+{
+    /// Interactive terminal in raw mode, polled through crossterm.
+    Terminal,
+    /// Piped or redirected stdin. A key is ready whenever another byte can be
+    /// read; after EOF the keyboard simply never becomes ready again.
+    Stream
+    {
+        reader: Box<dyn Read>,
+        pending: Option<u8>,
+        eof: bool,
+    },
+    /// Keys delivered at fixed instruction counts from an input script.
+    Script(InputScript),
+    /// Keys typed into the TUI console, handed to the program in order.
+    Queue(VecDeque<u8>),
+    /// Another keyboard whose answers are logged for later replay.
+    Record(Recorder),
+    /// Answers from a recorded session, given back at the same steps.
+    Replay(InputLog),
+}
+
+impl Keyboard
+{
+    pub fn stream(reader: impl Read + 'static) -> Self
+    {
+        Keyboard::Stream { reader: Box::new(reader), pending: None, eof: false }
+    }
+
+    /// Non-blocking check used by the KBSR device register. `step` is the
+    /// number of instructions executed so far.
+    pub fn key_ready(&mut self, step: u64) -> bool
+    {
+        match self
+        {
+            Keyboard::Terminal => check_key(),
+            Keyboard::Script(script) => script.key_ready(step),
+            Keyboard::Queue(keys) => !keys.is_empty(),
+            Keyboard::Record(recorder) => recorder.key_ready(step),
+            Keyboard::Replay(log) => log.key_ready(step),
+            Keyboard::Stream { .. } =>
+            {
+                self.fill();
+                matches!(self, Keyboard::Stream { pending: Some(_), .. })
+            }
+        }
+    }
+
+    /// Next input byte, or `None` when the stream has reached EOF.
+    pub fn read_byte(&mut self) -> Option<u8>
+    {
+        match self
+        {
+            Keyboard::Terminal =>
+            {
+                let mut buffer = [0u8; 1];
+                io::stdin().read_exact(&mut buffer).ok()?;
+                Some(buffer[0])
+            }
+            Keyboard::Script(script) => script.next_key(),
+            Keyboard::Queue(keys) => keys.pop_front(),
+            Keyboard::Record(recorder) => recorder.read_byte(),
+            Keyboard::Replay(log) => log.read_byte(),
+            Keyboard::Stream { .. } =>
+            {
+                self.fill();
+                match self
+                {
+                    Keyboard::Stream { pending, .. } => pending.take(),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Step of the recorded key poll a replay failed to match, see `InputLog::diverged`.
+    pub fn replay_diverged(&self) -> Option<u64>
+    {
+        match self
+        {
+            Keyboard::Replay(log) => log.diverged(),
+            _ => None,
+        }
+    }
+
+    fn fill(&mut self)
+    {
+        if let Keyboard::Stream { reader, pending, eof } = self
+        {
+            if pending.is_some() || *eof
+            {
+                return;
+            }
+            let mut buffer = [0u8; 1];
+            loop
+            {
+                match reader.read(&mut buffer)
+                {
+                    Ok(0) => *eof = true,
+                    Ok(_) => *pending = Some(buffer[0]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => *eof = true,
+                }
+                return;
+            }
+        }
+    }
+}
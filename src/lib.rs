@@ -0,0 +1,33 @@
+pub mod image;
+pub mod operations;
+pub mod vm;
+pub mod traps;
+pub mod input_buffering;
+pub mod hardware;
+pub mod keyboard;
+pub mod cli;
+pub mod input_script;
+pub mod display;
+pub mod diff;
+pub mod suite;
+pub mod disasm;
+pub mod trace;
+pub mod symbols;
+pub mod profile;
+pub mod coverage;
+pub mod timing;
+pub mod micro;
+pub mod tui;
+pub mod snapshot;
+pub mod replay;
+pub mod decode;
+pub mod bench;
+pub mod jit;
+pub mod hostfs;
+pub mod disk;
+pub mod boot;
+pub mod protection;
+#[cfg(test)]
+mod conformance;
+#[cfg(test)]
+mod properties;
@@ -1,43 +1,463 @@
-mod image;
-mod operations;
-mod vm;
-mod traps;
-mod input_buffering;
-mod hardware;
+use lc3box::{
+    bench, boot, cli, coverage, diff, disk, display, hardware, hostfs, image, input_buffering, input_script, jit, keyboard,
+    micro, profile, protection, replay, snapshot, suite, symbols, trace, tui, vm,
+};
 
 use std::env;
+use std::fs;
+use std::fs::File;
+use std::mem;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 use std::process::exit;
 
-use input_buffering::{disable_input_buffering, restore_input_buffering};
+use input_buffering::{disable_input_buffering, restore_input_buffering, is_interactive};
 use hardware::Registers;
-use operations::OPCODE_TABLE;
-use crate::vm::VM;
+use keyboard::Keyboard;
+use input_script::InputScript;
+use vm::VM;
+use cli::{BenchOptions, Command, CoverageOptions, MicroOptions, MkdiskOptions, Options, ProfileOptions, TestOptions, TuiOptions};
+use suite::Suite;
+use trace::Tracer;
+use profile::Profiler;
+use symbols::SymbolTable;
+use coverage::{Coverage, SourceMap};
+use micro::{CrossCheck, MicroMachine};
+use display::Display;
+use tui::Debugger;
+use snapshot::Snapshot;
+use replay::{InputLog, Recorder};
+use jit::Jit;
+use protection::Protection;
+use disk::Disk;
 
 fn main() {
-    let mut vm = VM::new();
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
         exit(0);
     }
+    match cli::parse(&args[1..]) {
+        Ok(Command::Run(options)) => {
+            if run(options, |_| {}).fault().is_some() {
+                exit(3);
+            }
+        }
+        Ok(Command::Test(options)) => test(options),
+        Ok(Command::Profile(options)) => profile(options),
+        Ok(Command::Coverage(options)) => coverage(options),
+        Ok(Command::Micro(options)) => micro(options),
+        Ok(Command::Tui(options)) => tui(options),
+        Ok(Command::Bench(options)) => bench(options),
+        Ok(Command::Mkdisk(options)) => mkdisk(options),
+        Err(message) => fatal(message),
+    }
+}
+
+/// Reports a usage or setup error and exits with status 2.
+fn fatal(message: impl std::fmt::Display) -> ! {
+    eprintln!("{message}");
+    exit(2);
+}
+
+/// Loads the images and points the PC at the conventional x3000 entry.
+fn load_images(vm: &mut VM, images: &[String]) {
+    for image in images {
+        if let Err(message) = image::read_image(image, vm) {
+            fatal(message);
+        }
+    }
+    vm.register_write(Registers::R_PC as usize, 0x3000);
+}
+
+/// Boots from the disk with `--boot`, otherwise loads the images, in user
+/// mode with `--user`. Then protects the ranges the options ask for.
+fn load_program(vm: &mut VM, options: &Options) {
+    if options.boot {
+        boot_from_disk(vm, options);
+    } else {
+        load_images(vm, &options.images);
+        for (path, protection) in &options.protected_images {
+            let (origin, words) = image::read_words(path).unwrap_or_else(|e| fatal(e));
+            if !words.is_empty() {
+                vm.protect(origin, origin.wrapping_add(words.len() as u16 - 1), *protection, path);
+            }
+        }
+        if options.user_mode {
+            vm.set_psr(vm::PSR_USER);
+        }
+    }
+    protect_ranges(vm, options);
+}
+
+/// `--read-only-range` and `--no-exec-range`, also applied to a resumed run.
+fn protect_ranges(vm: &mut VM, options: &Options) {
+    for &(first, last, protection) in &options.protected_ranges {
+        let flag = match protection {
+            Protection::ReadOnly => "--read-only-range",
+            Protection::NoExecute => "--no-exec-range",
+        };
+        vm.protect(first, last, protection, flag);
+    }
+}
+
+fn boot_from_disk(vm: &mut VM, options: &Options) {
+    let rom = match &options.rom {
+        Some(path) => match image::read_words(path) {
+            Ok((boot::ROM_START, words)) => words,
+            Ok((origin, _)) => fatal(format!("{path}: a boot ROM starts at x{:04X}, not x{origin:04X}", boot::ROM_START)),
+            Err(message) => fatal(message),
+        },
+        None => boot::BOOT_ROM.to_vec(),
+    };
+    if let Err(message) = boot::boot(vm, &rom) {
+        fatal(message);
+    }
+}
+
+/// Runs the program described by `options` until HALT. `prepare` gets the
+/// loaded VM right before the first instruction.
+fn run(options: Options, prepare: impl FnOnce(&mut VM)) -> VM {
+    run_with(options, prepare, VM::run_block)
+}
+
+/// Gives the program the host file traps when `--sandbox` names a directory
+/// and the disk when `--disk` names an image.
+fn attach_devices(vm: &mut VM, options: &Options) {
+    if let Some(root) = &options.sandbox
+        && let Err(message) = hostfs::install(vm, Path::new(root))
+    {
+        fatal(message);
+    }
+    if let Some(path) = &options.disk {
+        match Disk::open(Path::new(path)) {
+            Ok(disk) => vm.set_disk(disk),
+            Err(message) => fatal(message),
+        }
+    }
+}
+
+/// Like `run`, with `execute` advancing the VM by one instruction.
+fn run_with(options: Options, prepare: impl FnOnce(&mut VM), mut execute: impl FnMut(&mut VM)) -> VM {
+    let mut vm = VM::new();
+    vm.set_predecode(!options.no_predecode);
+    vm.set_extended_traps(options.extended_traps);
+    vm.set_access_control(options.access_control || options.strict);
+    attach_devices(&mut vm, &options);
+    if options.jit {
+        vm.set_jit(Jit::new(jit::DEFAULT_THRESHOLD));
+    }
+    let headless = options.headless || !is_interactive();
 
     print!("{}", vm.state_read());
-    for i in 1..args.len() {
-        image::read_image(&args[i], &mut vm);
+    let resumed = options.resume.as_ref().map(|path| {
+        let snapshot = Snapshot::load(path).unwrap_or_else(|e| fatal(e));
+        snapshot.restore(&mut vm).unwrap_or_else(|e| fatal(format!("{path}: {e}")));
+        protect_ranges(&mut vm, &options);
+        snapshot
+    });
+    if resumed.is_none() {
+        load_program(&mut vm, &options);
+    }
+    if let Some(path) = &options.output
+        && let Err(e) = vm.display().tee_to(path)
+    {
+        fatal(format!("{path}: {e}"));
+    }
+    let expected = options.expect.as_ref().map(|path| match fs::read(path) {
+        Ok(expected) => (path, expected),
+        Err(e) => fatal(format!("{path}: {e}")),
+    });
+    if expected.is_some() {
+        vm.display().capture();
+    }
+    if options.trace {
+        let out: Box<dyn Write> = match &options.trace_file {
+            Some(path) => match File::create(path) {
+                Ok(file) => Box::new(BufWriter::new(file)),
+                Err(e) => fatal(format!("{path}: {e}")),
+            },
+            None => Box::new(io::stderr()),
+        };
+        vm.set_tracer(Tracer::new(options.trace_format, options.trace_filter, out));
+    }
+    if let Some(path) = &options.replay_input {
+        match InputLog::load(path) {
+            Ok(log) => vm.set_keyboard(Keyboard::Replay(log)),
+            Err(message) => fatal(message),
+        }
+    } else if let Some(path) = &options.input_script {
+        match InputScript::load(path) {
+            Ok(script) => vm.set_keyboard(Keyboard::Script(script)),
+            Err(message) => fatal(message),
+        }
+    } else if headless {
+        vm.set_keyboard(Keyboard::stream(io::stdin()));
+    } else {
+        disable_input_buffering();
+    }
+    if let Some(snapshot) = &resumed {
+        let keyboard = mem::replace(vm.keyboard(), Keyboard::Terminal);
+        vm.set_keyboard(snapshot.keyboard(keyboard));
+    }
+    if let Some(path) = &options.record_input {
+        let log = File::create(path).unwrap_or_else(|e| fatal(format!("{path}: {e}")));
+        let keyboard = mem::replace(vm.keyboard(), Keyboard::Terminal);
+        vm.set_keyboard(Keyboard::Record(Recorder::new(keyboard, Box::new(log))));
     }
 
-    vm.register_write(Registers::R_PC as usize, 0x3000);
-    disable_input_buffering();
+    if let Some(timing) = options.timing {
+        vm.set_timing(timing);
+    }
+    prepare(&mut vm);
 
     // Main VM loop
     while vm.state_read() {
-        let instruction_register = vm.register_read(Registers::R_PC.into());
-        vm.register_write(Registers::R_PC.into(), instruction_register + 1); // PC incremented
-        let instruction: u16 = vm.memory_read(instruction_register);
-        let opcode = instruction >> 12;
-        OPCODE_TABLE[opcode as usize](instruction, &mut vm);
+        execute(&mut vm);
     }
 
     // Restore terminal state before exit
-    restore_input_buffering();
-    println!("\nVM exited cleanly.");
+    if !headless {
+        restore_input_buffering();
+    }
+    vm.display().flush();
+    if let Some(tracer) = vm.tracer() {
+        tracer.flush();
+    }
+    if let Some(path) = &options.snapshot_on_halt
+        && let Err(message) = Snapshot::capture(&mut vm).save(path)
+    {
+        fatal(message);
+    }
+    if let Keyboard::Replay(log) = vm.keyboard()
+        && log.remaining() > 0
+        && log.diverged().is_none()
+    {
+        eprintln!("warning: {} recorded input events were not replayed", log.remaining());
+    }
+    match vm.fault() {
+        Some(fault) => {
+            eprintln!("\nerror: {fault}");
+            if let Some(region) = vm.fault_region() {
+                eprintln!("note: protected by {region}");
+            }
+        }
+        None => println!("\nVM exited cleanly."),
+    }
+    if let Some(timing) = options.timing {
+        let cpi = vm.cycles() as f64 / vm.steps().max(1) as f64;
+        println!(
+            "{} instructions, {} cycles (CPI {cpi:.2}, memory latency {})",
+            vm.steps(),
+            vm.cycles(),
+            timing.memory_latency
+        );
+    }
+    if let Some(jit) = vm.jit() {
+        println!("{} blocks translated, {} dropped after stores into them", jit.translated, jit.invalidated);
+    }
+
+    if let Some((path, expected)) = expected {
+        let expected = String::from_utf8_lossy(&expected);
+        let actual = String::from_utf8_lossy(vm.display().captured()).into_owned();
+        if let Some(diff) = diff::unified_diff(&expected, &actual, path, "program output") {
+            eprint!("{diff}");
+            exit(1);
+        }
+    }
+    vm
+}
+
+fn test(options: TestOptions) {
+    let suite = match Suite::load(&options.suite) {
+        Ok(suite) => suite,
+        Err(message) => fatal(message),
+    };
+    let base = Path::new(&options.suite).parent().unwrap_or(Path::new("."));
+    let report = suite.run(base);
+    print!("{}", report.to_text());
+
+    let outputs = [(&options.json, report.to_json()), (&options.junit, report.to_junit())];
+    for (path, contents) in outputs {
+        if let Some(path) = path
+            && let Err(e) = fs::write(path, contents)
+        {
+            fatal(format!("{path}: {e}"));
+        }
+    }
+    if report.failed > 0 {
+        exit(1);
+    }
+}
+
+fn load_symbols(paths: &[String]) -> SymbolTable {
+    let mut symbols = SymbolTable::default();
+    for path in paths {
+        match fs::read_to_string(path) {
+            Ok(text) => symbols.extend(&text),
+            Err(e) => fatal(format!("{path}: {e}")),
+        }
+    }
+    symbols
+}
+
+fn profile(options: ProfileOptions) {
+    let symbols = load_symbols(&options.symbols);
+    let vm = run(options.run, |vm| {
+        let entry = vm.register_read(Registers::R_PC.into());
+        vm.set_profiler(Profiler::new(entry));
+    });
+    let Some(profiler) = vm.profiler() else { return };
+
+    println!("{}", profiler.report(&symbols, options.top, |address| vm.memory_peek(address)));
+    if let Some(path) = &options.folded
+        && let Err(e) = fs::write(path, profiler.folded(&symbols))
+    {
+        fatal(format!("{path}: {e}"));
+    }
+}
+
+fn coverage(options: CoverageOptions) {
+    let symbols = load_symbols(&options.symbols);
+    let map = options.source_map.as_ref().map(|path| SourceMap::load(path).unwrap_or_else(|e| fatal(e)));
+    // Without a source map every loaded word counts, data included
+    let mut addresses: Vec<u16> = match &map {
+        Some(map) => map.addresses(),
+        None => {
+            let mut scratch = VM::new();
+            let mut addresses = Vec::new();
+            for image in &options.run.images {
+                let (origin, words) = image::read_image(image, &mut scratch).unwrap_or_else(|e| fatal(e));
+                addresses.extend((0..words).map(|i| origin.wrapping_add(i as u16)));
+            }
+            addresses
+        }
+    };
+    addresses.sort_unstable();
+    addresses.dedup();
+
+    let vm = run(options.run, |vm| vm.set_coverage(Coverage::new()));
+    let Some(coverage) = vm.coverage() else { return };
+
+    let memory = |address| vm.memory_peek(address);
+    println!("{}", coverage.report(&addresses, &symbols, map.as_ref(), memory));
+    if let (Some(path), Some(map)) = (&options.lcov, &map)
+        && let Err(e) = fs::write(path, coverage.lcov(map, memory))
+    {
+        fatal(format!("{path}: {e}"));
+    }
+}
+
+fn micro(mut options: MicroOptions) {
+    let timing = options.run.timing.take().unwrap_or_default();
+    let mut machine = MicroMachine::new(timing);
+    let mut dump: Option<Box<dyn Write>> = match &options.dump_file {
+        Some(path) => match File::create(path) {
+            Ok(file) => Some(Box::new(BufWriter::new(file))),
+            Err(e) => fatal(format!("{path}: {e}")),
+        },
+        None if options.dump => Some(Box::new(io::stderr())),
+        None => None,
+    };
+    let mut check = options.check.then(CrossCheck::new);
+    let mut mismatches = 0;
+    let mut observe = |snapshot: &micro::Snapshot| {
+        if let Some(out) = &mut dump {
+            let _ = writeln!(out, "{snapshot}");
+        }
+    };
+    let vm = run_with(options.run, |_| {}, |vm| match &mut check {
+        Some(check) => {
+            if let Err(mismatch) = check.instruction(&mut machine, vm, &mut observe) {
+                eprintln!("mismatch at {mismatch}");
+                mismatches += 1;
+            }
+        }
+        None => machine.instruction(vm, &mut observe),
+    });
+    if let Some(out) = &mut dump {
+        let _ = out.flush();
+    }
+
+    let cpi = machine.cycles() as f64 / vm.steps().max(1) as f64;
+    println!(
+        "{} instructions, {} cycles (CPI {cpi:.2}, memory latency {})",
+        vm.steps(),
+        machine.cycles(),
+        machine.timing.memory_latency
+    );
+    if let Some(check) = &check {
+        println!("Cross-check: {} instructions compared, {} skipped (device I/O), {mismatches} mismatches", check.checked, check.skipped);
+        if mismatches > 0 {
+            exit(1);
+        }
+    }
+}
+
+fn tui(options: TuiOptions) {
+    if !is_interactive() {
+        fatal("tui needs an interactive terminal");
+    }
+    let symbols = load_symbols(&options.symbols);
+    let mut vm = VM::new();
+    vm.set_extended_traps(options.run.extended_traps);
+    vm.set_access_control(options.run.access_control || options.run.strict);
+    attach_devices(&mut vm, &options.run);
+    load_program(&mut vm, &options.run);
+    vm.set_display(Display::captured_only());
+    if let Some(path) = &options.run.output
+        && let Err(e) = vm.display().tee_to(path)
+    {
+        fatal(format!("{path}: {e}"));
+    }
+    match &options.run.input_script {
+        Some(path) => vm.set_keyboard(Keyboard::Script(InputScript::load(path).unwrap_or_else(|e| fatal(e)))),
+        None => vm.set_keyboard(Keyboard::Queue(Default::default())),
+    }
+
+    let mut debugger = Debugger::new(symbols, &mut vm);
+    for breakpoint in &options.breakpoints {
+        if let Err(message) = debugger.command(&mut vm, &format!("break {breakpoint}")) {
+            fatal(message);
+        }
+    }
+    if let Err(e) = debugger.interact(&mut vm) {
+        fatal(e);
+    }
+    vm.display().flush();
+}
+
+fn bench(options: BenchOptions) {
+    let programs = if options.images.is_empty() {
+        bench::builtin()
+    } else {
+        options.images.iter().map(|path| bench::Program::from_image(path).unwrap_or_else(|e| fatal(e))).collect()
+    };
+    let baseline = options.baseline.as_ref().map(|path| bench::Baseline::load(path).unwrap_or_else(|e| fatal(e)));
+    let rows = bench::run_suite(&programs, options.repeat, options.max_steps).unwrap_or_else(|e| fatal(e));
+    print!("{}", bench::report(&rows, baseline.as_ref()));
+    if let Some(path) = &options.save_baseline
+        && let Err(message) = bench::Baseline::from_rows(&rows).save(path)
+    {
+        fatal(message);
+    }
+    if let (Some(baseline), Some(max_percent)) = (&baseline, options.max_regression) {
+        let slower = bench::regressions(&rows, baseline, max_percent);
+        for message in &slower {
+            eprintln!("{message}");
+        }
+        if !slower.is_empty() {
+            exit(1);
+        }
+    }
+}
+
+fn mkdisk(options: MkdiskOptions) {
+    let images: Vec<(u16, Vec<u16>)> =
+        options.images.iter().map(|path| image::read_words(path).unwrap_or_else(|e| fatal(e))).collect();
+    let entry = options.entry.unwrap_or(images[0].0);
+    let bytes = boot::make_disk(&images, entry).unwrap_or_else(|e| fatal(e));
+    if let Err(e) = fs::write(&options.output, &bytes) {
+        fatal(format!("{}: {e}", options.output));
+    }
+    println!("{}: {} images, {} sectors, entry x{entry:04X}", options.output, images.len(), bytes.len() / 512);
 }
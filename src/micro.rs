@@ -0,0 +1,734 @@
+use std::fmt;
+use std::io;
+
+use crate::disasm::disassemble;
+use crate::display::Display;
+use crate::hardware::{CondtionalFlags, Registers};
+use crate::keyboard::Keyboard;
+use crate::operations::{sign_extension, OPCODE_TABLE};
+use crate::timing::{TimingModel, MEMORY_STATES};
+use crate::vm::{Fault, VM};
+
+/// Selects the next PC when LD.PC is asserted.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PcMux
+{
+    Increment,
+    Bus,
+    Adder,
+}
+
+/// Destination register of LD.REG.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DrMux
+{
+    Ir11,
+    R7,
+    Sp,
+}
+
+/// First register file read port, feeding the ALU and ADDR1MUX.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Sr1Mux
+{
+    Ir11,
+    Ir8,
+    Sp,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Addr1Mux
+{
+    Pc,
+    BaseR,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Addr2Mux
+{
+    Zero,
+    Offset6,
+    Offset9,
+    Offset11,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MarMux
+{
+    Zext7,
+    Adder,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Aluk
+{
+    Add,
+    And,
+    Not,
+    PassA,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SpMux
+{
+    Increment,
+}
+
+/// Control signals of one microinstruction. Muxes are `None` when their
+/// output is not used in that state.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Signals
+{
+    pub ld_mar: bool,
+    pub ld_mdr: bool,
+    pub ld_ir: bool,
+    pub ld_ben: bool,
+    pub ld_reg: bool,
+    pub ld_cc: bool,
+    pub ld_pc: bool,
+    pub ld_psr: bool,
+    pub gate_pc: bool,
+    pub gate_mdr: bool,
+    pub gate_alu: bool,
+    pub gate_marmux: bool,
+    pub gate_sp: bool,
+    pub pcmux: Option<PcMux>,
+    pub drmux: Option<DrMux>,
+    pub sr1mux: Option<Sr1Mux>,
+    pub addr1mux: Option<Addr1Mux>,
+    pub addr2mux: Option<Addr2Mux>,
+    pub marmux: Option<MarMux>,
+    pub aluk: Option<Aluk>,
+    pub spmux: Option<SpMux>,
+    pub mio_en: bool,
+    /// R.W: true for a memory write.
+    pub write: bool,
+}
+
+/// One entry of the control store: what the state does, in register
+/// transfer notation, and the signals that do it.
+pub struct Microinstruction
+{
+    pub rtl: &'static str,
+    pub signals: Signals,
+}
+
+const NONE: Signals = Signals {
+    ld_mar: false,
+    ld_mdr: false,
+    ld_ir: false,
+    ld_ben: false,
+    ld_reg: false,
+    ld_cc: false,
+    ld_pc: false,
+    ld_psr: false,
+    gate_pc: false,
+    gate_mdr: false,
+    gate_alu: false,
+    gate_marmux: false,
+    gate_sp: false,
+    pcmux: None,
+    drmux: None,
+    sr1mux: None,
+    addr1mux: None,
+    addr2mux: None,
+    marmux: None,
+    aluk: None,
+    spmux: None,
+    mio_en: false,
+    write: false,
+};
+
+const MEMORY_READ: Signals = Signals { ld_mdr: true, mio_en: true, ..NONE };
+
+/// MAR<-PC+offset9, shared by LD, ST, LDI and STI.
+const MAR_PC_OFFSET9: Signals = Signals {
+    ld_mar: true,
+    gate_marmux: true,
+    marmux: Some(MarMux::Adder),
+    addr1mux: Some(Addr1Mux::Pc),
+    addr2mux: Some(Addr2Mux::Offset9),
+    ..NONE
+};
+
+/// MAR<-BaseR+offset6, shared by LDR and STR.
+const MAR_BASE_OFFSET6: Signals = Signals {
+    ld_mar: true,
+    gate_marmux: true,
+    marmux: Some(MarMux::Adder),
+    addr1mux: Some(Addr1Mux::BaseR),
+    addr2mux: Some(Addr2Mux::Offset6),
+    sr1mux: Some(Sr1Mux::Ir8),
+    ..NONE
+};
+
+const ALU_TO_DR: Signals = Signals {
+    ld_reg: true,
+    ld_cc: true,
+    gate_alu: true,
+    drmux: Some(DrMux::Ir11),
+    sr1mux: Some(Sr1Mux::Ir8),
+    ..NONE
+};
+
+const PC_FROM_ADDER: Signals = Signals { ld_pc: true, pcmux: Some(PcMux::Adder), ..NONE };
+
+/// The microinstruction for `state`, following Patt & Patel, appendix C.
+/// States that only exist for interrupts and exceptions are `None`.
+pub fn control_store(state: u8) -> Option<Microinstruction>
+{
+    let (rtl, signals) = match state
+    {
+        18 => ("MAR<-PC, PC<-PC+1", Signals { ld_mar: true, ld_pc: true, gate_pc: true, pcmux: Some(PcMux::Increment), ..NONE }),
+        33 => ("MDR<-M[MAR]", MEMORY_READ),
+        35 => ("IR<-MDR", Signals { ld_ir: true, gate_mdr: true, ..NONE }),
+        32 => ("BEN<-IR[11]&N + IR[10]&Z + IR[9]&P, [IR[15:12]]", Signals { ld_ben: true, ..NONE }),
+        0 => ("[BEN]", NONE),
+        22 => ("PC<-PC+off9", Signals { addr1mux: Some(Addr1Mux::Pc), addr2mux: Some(Addr2Mux::Offset9), ..PC_FROM_ADDER }),
+        1 => ("DR<-SR1+OP2, setcc", Signals { aluk: Some(Aluk::Add), ..ALU_TO_DR }),
+        5 => ("DR<-SR1&OP2, setcc", Signals { aluk: Some(Aluk::And), ..ALU_TO_DR }),
+        9 => ("DR<-NOT(SR), setcc", Signals { aluk: Some(Aluk::Not), ..ALU_TO_DR }),
+        14 => (
+            "DR<-PC+off9, setcc",
+            Signals { ld_reg: true, ld_cc: true, drmux: Some(DrMux::Ir11), ..MAR_PC_OFFSET9 }.without_mar(),
+        ),
+        2 | 3 | 10 | 11 => ("MAR<-PC+off9", MAR_PC_OFFSET9),
+        6 | 7 => ("MAR<-B+off6", MAR_BASE_OFFSET6),
+        25 | 24 | 29 => ("MDR<-M[MAR]", MEMORY_READ),
+        27 => ("DR<-MDR, setcc", Signals { ld_reg: true, ld_cc: true, gate_mdr: true, drmux: Some(DrMux::Ir11), ..NONE }),
+        26 | 31 => ("MAR<-MDR", Signals { ld_mar: true, gate_mdr: true, ..NONE }),
+        23 => ("MDR<-SR", Signals { ld_mdr: true, gate_alu: true, aluk: Some(Aluk::PassA), sr1mux: Some(Sr1Mux::Ir11), ..NONE }),
+        16 => ("M[MAR]<-MDR", Signals { mio_en: true, write: true, ..NONE }),
+        12 => ("PC<-BaseR", Signals { addr1mux: Some(Addr1Mux::BaseR), addr2mux: Some(Addr2Mux::Zero), sr1mux: Some(Sr1Mux::Ir8), ..PC_FROM_ADDER }),
+        4 => ("R7<-PC, [IR[11]]", Signals { ld_reg: true, gate_pc: true, drmux: Some(DrMux::R7), ..NONE }),
+        21 => ("PC<-PC+off11", Signals { addr1mux: Some(Addr1Mux::Pc), addr2mux: Some(Addr2Mux::Offset11), ..PC_FROM_ADDER }),
+        20 => ("PC<-BaseR", Signals { addr1mux: Some(Addr1Mux::BaseR), addr2mux: Some(Addr2Mux::Zero), sr1mux: Some(Sr1Mux::Ir8), ..PC_FROM_ADDER }),
+        15 => ("MAR<-ZEXT[IR[7:0]]", Signals { ld_mar: true, gate_marmux: true, marmux: Some(MarMux::Zext7), ..NONE }),
+        28 => ("MDR<-M[MAR], R7<-PC", Signals { ld_reg: true, gate_pc: true, drmux: Some(DrMux::R7), ..MEMORY_READ }),
+        30 => ("PC<-MDR (native service routine)", Signals { ld_pc: true, gate_mdr: true, pcmux: Some(PcMux::Bus), ..NONE }),
+        8 => ("MAR<-R6, [PSR[15]]", Signals { ld_mar: true, gate_alu: true, aluk: Some(Aluk::PassA), sr1mux: Some(Sr1Mux::Sp), ..NONE }),
+        36 | 40 => ("MDR<-M[MAR]", MEMORY_READ),
+        38 => ("PC<-MDR", Signals { ld_pc: true, gate_mdr: true, pcmux: Some(PcMux::Bus), ..NONE }),
+        39 => (
+            "MAR, R6<-R6+1",
+            Signals { ld_mar: true, ld_reg: true, gate_sp: true, drmux: Some(DrMux::Sp), sr1mux: Some(Sr1Mux::Sp), spmux: Some(SpMux::Increment), ..NONE },
+        ),
+        42 => ("PSR<-MDR", Signals { ld_psr: true, ..NONE }),
+        34 => (
+            "R6<-R6+1, [PSR[15]]",
+            Signals { ld_reg: true, gate_sp: true, drmux: Some(DrMux::Sp), sr1mux: Some(Sr1Mux::Sp), spmux: Some(SpMux::Increment), ..NONE },
+        ),
+        51 => ("nothing", NONE),
+        59 => ("Saved.SSP<-R6, R6<-Saved.USP", Signals { ld_reg: true, drmux: Some(DrMux::Sp), sr1mux: Some(Sr1Mux::Sp), ..NONE }),
+        _ => return None,
+    };
+    Some(Microinstruction { rtl, signals })
+}
+
+impl Signals
+{
+    /// LEA reuses the address adder but routes its result to DR, not MAR.
+    const fn without_mar(self) -> Self
+    {
+        Self { ld_mar: false, ..self }
+    }
+}
+
+impl fmt::Display for Signals
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let flags = [
+            (self.ld_mar, "LD.MAR"),
+            (self.ld_mdr, "LD.MDR"),
+            (self.ld_ir, "LD.IR"),
+            (self.ld_ben, "LD.BEN"),
+            (self.ld_reg, "LD.REG"),
+            (self.ld_cc, "LD.CC"),
+            (self.ld_pc, "LD.PC"),
+            (self.ld_psr, "LD.PSR"),
+            (self.gate_pc, "GatePC"),
+            (self.gate_mdr, "GateMDR"),
+            (self.gate_alu, "GateALU"),
+            (self.gate_marmux, "GateMARMUX"),
+            (self.gate_sp, "GateSP"),
+            (self.mio_en, "MIO.EN"),
+        ];
+        let mut parts: Vec<String> = flags.iter().filter(|(on, _)| *on).map(|(_, name)| name.to_string()).collect();
+        if self.mio_en
+        {
+            parts.push(if self.write { "R.W=WR" } else { "R.W=RD" }.to_string());
+        }
+        let muxes = [
+            ("PCMUX", self.pcmux.map(|m| match m { PcMux::Increment => "PC+1", PcMux::Bus => "BUS", PcMux::Adder => "ADDER" })),
+            ("DRMUX", self.drmux.map(|m| match m { DrMux::Ir11 => "11.9", DrMux::R7 => "R7", DrMux::Sp => "SP" })),
+            ("SR1MUX", self.sr1mux.map(|m| match m { Sr1Mux::Ir11 => "11.9", Sr1Mux::Ir8 => "8.6", Sr1Mux::Sp => "SP" })),
+            ("ADDR1MUX", self.addr1mux.map(|m| match m { Addr1Mux::Pc => "PC", Addr1Mux::BaseR => "BaseR" })),
+            (
+                "ADDR2MUX",
+                self.addr2mux.map(|m| match m
+                {
+                    Addr2Mux::Zero => "ZERO",
+                    Addr2Mux::Offset6 => "offset6",
+                    Addr2Mux::Offset9 => "PCoffset9",
+                    Addr2Mux::Offset11 => "PCoffset11",
+                }),
+            ),
+            ("MARMUX", self.marmux.map(|m| match m { MarMux::Zext7 => "7.0", MarMux::Adder => "ADDER" })),
+            ("ALUK", self.aluk.map(|m| match m { Aluk::Add => "ADD", Aluk::And => "AND", Aluk::Not => "NOT", Aluk::PassA => "PASSA" })),
+            ("SPMUX", self.spmux.map(|SpMux::Increment| "SP+1")),
+        ];
+        parts.extend(muxes.iter().filter_map(|(name, value)| value.map(|value| format!("{name}={value}"))));
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// The datapath after one microstep.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Snapshot
+{
+    pub cycle: u64,
+    pub state: u8,
+    pub next_state: u8,
+    pub rtl: &'static str,
+    pub signals: Signals,
+    /// Value driven onto the bus, `None` when no gate was open.
+    pub bus: Option<u16>,
+    /// Memory's R signal; memory states repeat until it is asserted.
+    pub ready: bool,
+    pub mar: u16,
+    pub mdr: u16,
+    pub ir: u16,
+    pub ben: bool,
+    pub pc: u16,
+    pub registers: [u16; 8],
+    pub cond: u16,
+}
+
+impl fmt::Display for Snapshot
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let bus = self.bus.map_or("-----".to_string(), |bus| format!("x{bus:04X}"));
+        let cc = match self.cond
+        {
+            4 => 'n',
+            2 => 'z',
+            1 => 'p',
+            _ => '-',
+        };
+        writeln!(
+            f,
+            "{:>8}  state {:>2} -> {:>2}  {:<36} {}",
+            self.cycle, self.state, self.next_state, self.rtl, self.signals
+        )?;
+        write!(
+            f,
+            "          BUS={bus} MAR=x{:04X} MDR=x{:04X} IR=x{:04X} BEN={} PC=x{:04X} CC={cc} R={}",
+            self.mar,
+            self.mdr,
+            self.ir,
+            self.ben as u8,
+            self.pc,
+            self.ready as u8
+        )?;
+        for (index, value) in self.registers.iter().enumerate()
+        {
+            write!(f, " R{index}=x{value:04X}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Executes instructions one clock cycle at a time by walking the LC-3
+/// microsequencer states, using the VM for the register file and memory.
+///
+/// Privilege and the saved stack pointers are the VM's, and TRAP service
+/// routines are the VM's native ones: state 30 runs the routine instead of
+/// jumping to it.
+/// The reserved opcode and RTI in user mode raise the same faults as
+/// `OPCODE_TABLE` instead of taking the exception states.
+pub struct MicroMachine
+{
+    pub timing: TimingModel,
+    state: u8,
+    mar: u16,
+    mdr: u16,
+    ir: u16,
+    ben: bool,
+    waited: u64, // cycles spent in the current memory state
+    cycles: u64,
+}
+
+fn flags(value: u16) -> u16
+{
+    if value == 0
+    {
+        CondtionalFlags::FL_ZRO as u16
+    }
+    else if value >> 15 == 1
+    {
+        CondtionalFlags::FL_NEG as u16
+    }
+    else
+    {
+        CondtionalFlags::FL_POS as u16
+    }
+}
+
+impl MicroMachine
+{
+    pub fn new(timing: TimingModel) -> Self
+    {
+        Self { timing, state: 18, mar: 0, mdr: 0, ir: 0, ben: false, waited: 0, cycles: 0 }
+    }
+
+    pub fn cycles(&self) -> u64
+    {
+        self.cycles
+    }
+
+    /// Executes the current state for one cycle and latches its results.
+    pub fn microstep(&mut self, vm: &mut VM) -> Snapshot
+    {
+        let state = self.state;
+        let Some(Microinstruction { rtl, signals }) = control_store(state)
+        else
+        {
+            unreachable!("state {state} has no microinstruction");
+        };
+        let ir = self.ir;
+        let pc = vm.register_read(Registers::R_PC.into());
+        let cond = vm.register_read(Registers::R_COND.into());
+
+        // Combinational logic, all computed from the values latched last cycle
+        let sr1 = match signals.sr1mux
+        {
+            Some(Sr1Mux::Ir11) => (ir >> 9) & 7,
+            Some(Sr1Mux::Sp) => 6,
+            _ => (ir >> 6) & 7,
+        };
+        let a = vm.register_read(sr1.into());
+        let b = if ir & 0x20 != 0 { sign_extension(ir & 0x1F, 5) } else { vm.register_read((ir & 7).into()) };
+        let alu = match signals.aluk
+        {
+            Some(Aluk::Add) => a.wrapping_add(b),
+            Some(Aluk::And) => a & b,
+            Some(Aluk::Not) => !a,
+            _ => a,
+        };
+        let addr1 = if signals.addr1mux == Some(Addr1Mux::BaseR) { a } else { pc };
+        let addr2 = match signals.addr2mux
+        {
+            Some(Addr2Mux::Offset6) => sign_extension(ir & 0x3F, 6),
+            Some(Addr2Mux::Offset9) => sign_extension(ir & 0x1FF, 9),
+            Some(Addr2Mux::Offset11) => sign_extension(ir & 0x7FF, 11),
+            _ => 0,
+        };
+        let adder = addr1.wrapping_add(addr2);
+        let marmux = if signals.marmux == Some(MarMux::Zext7) { ir & 0xFF } else { adder };
+        let sp = a.wrapping_add(1); // SPMUX=SP+1, the only stack move RTI needs
+        let bus = if signals.gate_pc
+        {
+            Some(pc)
+        }
+        else if signals.gate_mdr
+        {
+            Some(self.mdr)
+        }
+        else if signals.gate_alu
+        {
+            Some(alu)
+        }
+        else if signals.gate_marmux
+        {
+            Some(marmux)
+        }
+        else if signals.gate_sp
+        {
+            Some(sp)
+        }
+        else
+        {
+            None
+        };
+        let value = bus.unwrap_or(0);
+
+        let mut ready = false;
+        if signals.mio_en
+        {
+            self.waited += 1;
+            ready = self.waited >= self.timing.state_cycles(state);
+            if ready
+            {
+                self.waited = 0;
+                if signals.write
+                {
+                    vm.memory_write(self.mar, self.mdr);
+                }
+                else
+                {
+                    self.mdr = vm.memory_read(self.mar);
+                }
+            }
+        }
+
+        // Latches
+        if signals.ld_mar
+        {
+            self.mar = value;
+        }
+        if signals.ld_mdr && !signals.mio_en
+        {
+            self.mdr = value;
+        }
+        if signals.ld_ir
+        {
+            self.ir = value;
+        }
+        if signals.ld_ben
+        {
+            self.ben = (ir >> 9) & cond & 7 != 0;
+        }
+        if signals.ld_reg
+        {
+            let dr = match signals.drmux
+            {
+                Some(DrMux::R7) => 7,
+                Some(DrMux::Sp) => 6,
+                _ => (ir >> 9) & 7,
+            };
+            if state == 59
+            {
+                let (_, usp) = vm.saved_stack_pointers();
+                vm.set_saved_stack_pointers(a, usp);
+                vm.register_write(dr.into(), usp);
+            }
+            else
+            {
+                vm.register_write(dr.into(), value);
+            }
+        }
+        if signals.ld_cc
+        {
+            vm.register_write(Registers::R_COND.into(), flags(value));
+        }
+        if signals.ld_psr
+        {
+            vm.set_psr(self.mdr);
+        }
+        if state == 30
+        {
+            OPCODE_TABLE[0xF](ir, vm);
+        }
+        else if signals.ld_pc
+        {
+            let next = match signals.pcmux
+            {
+                Some(PcMux::Increment) => pc.wrapping_add(1),
+                Some(PcMux::Bus) => value,
+                _ => adder,
+            };
+            vm.register_write(Registers::R_PC.into(), next);
+        }
+
+        // A fault stops the VM and ends the instruction, like `OP_RES` and `OP_RTI`
+        self.state = match self.next_state(state, ready, vm.user_mode(), pc.wrapping_sub(1))
+        {
+            Ok(next) => next,
+            Err(fault) =>
+            {
+                vm.raise(fault);
+                18
+            }
+        };
+        self.cycles += 1;
+        Snapshot {
+            cycle: self.cycles,
+            state,
+            next_state: self.state,
+            rtl,
+            signals,
+            bus,
+            ready,
+            mar: self.mar,
+            mdr: self.mdr,
+            ir: self.ir,
+            ben: self.ben,
+            pc: vm.register_read(Registers::R_PC.into()),
+            registers: std::array::from_fn(|r| vm.register_read(r)),
+            cond: vm.register_read(Registers::R_COND.into()),
+        }
+    }
+
+    /// `user_mode` is PSR[15], `pc` the address of the instruction being
+    /// executed for faults.
+    fn next_state(&self, state: u8, ready: bool, user_mode: bool, pc: u16) -> Result<u8, Fault>
+    {
+        if MEMORY_STATES.contains(&state) && !ready
+        {
+            return Ok(state);
+        }
+        Ok(match state
+        {
+            18 => 33,
+            33 => 35,
+            35 => 32,
+            32 if self.ir >> 12 == 0xD => return Err(Fault::IllegalOpcode { pc, instruction: self.ir }),
+            32 => (self.ir >> 12) as u8,
+            0 if self.ben => 22,
+            2 | 6 => 25,
+            3 | 7 => 23,
+            4 if self.ir & 0x0800 != 0 => 21,
+            4 => 20,
+            8 if user_mode => return Err(Fault::PrivilegeViolation { pc }),
+            8 => 36,
+            10 => 24,
+            11 => 29,
+            15 => 28,
+            24 => 26,
+            25 => 27,
+            26 => 25,
+            28 => 30,
+            29 => 31,
+            31 => 23,
+            23 => 16,
+            36 => 38,
+            38 => 39,
+            39 => 40,
+            40 => 42,
+            42 => 34,
+            34 if user_mode => 59,
+            34 => 51,
+            _ => 18,
+        })
+    }
+
+    /// Runs microsteps from the current state until the machine is back at
+    /// state 18, i.e. one whole instruction when started at a fetch.
+    pub fn instruction(&mut self, vm: &mut VM, mut observe: impl FnMut(&Snapshot))
+    {
+        loop
+        {
+            let snapshot = self.microstep(vm);
+            observe(&snapshot);
+            if snapshot.next_state == 18
+            {
+                break;
+            }
+        }
+        vm.count_step();
+    }
+}
+
+/// Runs every instruction through both the microcode and `OPCODE_TABLE`
+/// (via `VM::step` on a private reference VM) and compares the results.
+///
+/// Instructions that touch device registers or read the keyboard are not
+/// compared, since the reference VM has no devices attached.
+pub struct CrossCheck
+{
+    reference: Box<VM>,
+    /// The reference's memory matches the VM's, so only the words an
+    /// instruction writes need comparing and copying.
+    synced: bool,
+    pub checked: u64,
+    pub skipped: u64,
+}
+
+impl Default for CrossCheck
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl CrossCheck
+{
+    pub fn new() -> Self
+    {
+        let mut reference = Box::new(VM::new());
+        reference.set_keyboard(Keyboard::stream(io::empty()));
+        reference.set_display(Display::captured_only());
+        Self { reference, synced: false, checked: 0, skipped: 0 }
+    }
+
+    /// Executes one instruction with `micro` and reports where the
+    /// architectural state differs from the ISA-level implementation.
+    pub fn instruction(&mut self, micro: &mut MicroMachine, vm: &mut VM, mut observe: impl FnMut(&Snapshot)) -> Result<(), String>
+    {
+        if !self.synced
+        {
+            self.reference.set_arch_state(&vm.arch_state());
+            self.synced = true;
+        }
+        let registers = vm.registers();
+        let pc = registers[Registers::R_PC as usize];
+        let psr = vm.psr();
+        let (ssp, usp) = vm.saved_stack_pointers();
+        // Everything the reference may write; the microcode's writes are added as they happen
+        let mut touched: Vec<u16> = vm.store_address(pc).into_iter().collect();
+        let mut devices = false;
+        micro.instruction(vm, |snapshot| {
+            devices |= snapshot.signals.mio_en && snapshot.mar >= 0xFE00 && snapshot.state != 33;
+            if snapshot.signals.mio_en && snapshot.signals.write && snapshot.ready
+            {
+                touched.push(snapshot.mar);
+            }
+            observe(snapshot);
+        });
+        let inst = micro.ir;
+        if devices || (inst >> 12 == 0xF && matches!(inst & 0xFF, 0x20 | 0x23))
+        {
+            self.skipped += 1;
+            self.synced = false;
+            return Ok(());
+        }
+        self.checked += 1;
+        self.reference.set_registers(&registers);
+        self.reference.set_psr(psr);
+        self.reference.set_saved_stack_pointers(ssp, usp);
+        self.reference.step();
+        let mut differences = differences(&self.reference, vm, &touched);
+        if self.reference.fault() != vm.fault()
+        {
+            let fault = |fault: Option<Fault>| fault.map_or("none".to_string(), |fault| fault.to_string());
+            differences.push(format!("fault {}/{}", fault(self.reference.fault()), fault(vm.fault())));
+        }
+        if differences.is_empty()
+        {
+            return Ok(());
+        }
+        self.synced = false;
+        Err(format!("x{pc:04X}  {}: {}", disassemble(pc, inst), differences.join(", ")))
+    }
+}
+
+/// Human-readable list of what differs in the registers and the `touched`
+/// words, as `name OPCODE_TABLE/microcode`.
+fn differences(expected: &VM, actual: &VM, touched: &[u16]) -> Vec<String>
+{
+    const NAMES: [&str; 10] = ["R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "PC", "COND"];
+    let mut out: Vec<String> = NAMES
+        .iter()
+        .zip(expected.registers().iter().zip(&actual.registers()))
+        .filter(|(_, (e, a))| e != a)
+        .map(|(name, (e, a))| format!("{name} x{e:04X}/x{a:04X}"))
+        .collect();
+    let mut touched = touched.to_vec();
+    touched.sort_unstable();
+    touched.dedup();
+    for address in touched
+    {
+        let (e, a) = (expected.memory_peek(address), actual.memory_peek(address));
+        if e != a
+        {
+            out.push(format!("M[x{address:04X}] x{e:04X}/x{a:04X}"));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,174 @@
+use super::*;
+use crate::vm::PSR_USER;
+
+fn program_vm(words: &[u16]) -> VM {
+    let mut vm = VM::new();
+    vm.set_keyboard(Keyboard::stream(io::empty()));
+    vm.set_display(Display::captured_only());
+    for (i, &word) in words.iter().enumerate() {
+        vm.memory_write(0x3000 + i as u16, word);
+    }
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    vm
+}
+
+/// Exercises every opcode except RTI and the reserved one.
+const PROGRAM: [u16; 24] = [
+    0x5020, // AND R0, R0, #0
+    0x1025, // ADD R0, R0, #5
+    0x927F, // NOT R1, R1
+    0xE20F, // LEA R1, x3013
+    0x2410, // LD R2, x3015
+    0x6640, // LDR R3, R1, #0
+    0xA80F, // LDI R4, x3016
+    0x340C, // ST R2, x3014
+    0x7441, // STR R2, R1, #1
+    0xB00D, // STI R0, x3017
+    0x0401, // BRz x300C (not taken)
+    0x0201, // BRp x300D (taken)
+    0x0000, // NOP, skipped
+    0x4803, // JSR x3011
+    0x4140, // JSRR R5
+    0xF021, // OUT
+    0xF025, // HALT
+    0xC1C0, // RET
+    0x0000, // NOP
+    0x1234, // x3013
+    0x0000, // x3014
+    0x0042, // x3015
+    0x3015, // x3016, pointer for LDI
+    0x3020, // x3017, pointer for STI
+];
+
+#[test]
+fn test_fetch_walks_states_and_latches_registers() {
+    let mut vm = program_vm(&[0x1025]);
+    let mut machine = MicroMachine::new(TimingModel { memory_latency: 2 });
+    let mut snapshots = Vec::new();
+    machine.instruction(&mut vm, |snapshot| snapshots.push(snapshot.clone()));
+
+    let states: Vec<u8> = snapshots.iter().map(|s| s.state).collect();
+    assert_eq!(states, [18, 33, 33, 35, 32, 1]);
+    assert_eq!((snapshots[0].mar, snapshots[0].pc, snapshots[0].bus), (0x3000, 0x3001, Some(0x3000)));
+    assert!(!snapshots[1].ready && snapshots[2].ready);
+    assert_eq!(snapshots[2].mdr, 0x1025);
+    assert_eq!(snapshots[3].ir, 0x1025);
+    assert_eq!(snapshots[5].registers[0], 5);
+    assert_eq!(snapshots[5].cond, CondtionalFlags::FL_POS as u16);
+    assert_eq!(machine.cycles(), 6);
+    assert_eq!(vm.steps(), 1);
+}
+
+#[test]
+fn test_control_store_signals() {
+    let fetch = control_store(18).unwrap();
+    assert_eq!(fetch.rtl, "MAR<-PC, PC<-PC+1");
+    assert_eq!(fetch.signals.to_string(), "LD.MAR LD.PC GatePC PCMUX=PC+1");
+    assert_eq!(control_store(16).unwrap().signals.to_string(), "MIO.EN R.W=WR");
+    assert_eq!(
+        control_store(1).unwrap().signals.to_string(),
+        "LD.REG LD.CC GateALU DRMUX=11.9 SR1MUX=8.6 ALUK=ADD"
+    );
+    assert!(control_store(49).is_none()); // interrupt states are not modelled
+}
+
+#[test]
+fn test_cross_check_agrees_with_opcode_table() {
+    let mut vm = program_vm(&PROGRAM);
+    vm.register_write(5, 0x3011);
+    let mut machine = MicroMachine::new(TimingModel::default());
+    let mut check = CrossCheck::new();
+    while vm.state_read() {
+        check.instruction(&mut machine, &mut vm, |_| {}).unwrap();
+    }
+    assert_eq!((check.checked, check.skipped), (18, 0));
+    assert_eq!(vm.memory_peek(0x3014), 0x42);
+    assert_eq!(vm.memory_peek(0x3020), 5);
+}
+
+#[test]
+fn test_cycles_match_timing_model() {
+    let timing = TimingModel { memory_latency: 3 };
+    let mut vm = program_vm(&PROGRAM);
+    vm.register_write(5, 0x3011);
+    let mut machine = MicroMachine::new(timing);
+    while vm.state_read() {
+        let before = machine.cycles();
+        let cond = vm.register_read(Registers::R_COND.into());
+        let pc = vm.register_read(Registers::R_PC.into());
+        let inst = vm.memory_peek(pc);
+        machine.instruction(&mut vm, |_| {});
+        let ben = (inst >> 9) & cond & 7 != 0;
+        assert_eq!(machine.cycles() - before, timing.instruction_cycles(inst, ben, false), "x{inst:04X}");
+    }
+}
+
+#[test]
+fn test_rti_pops_pc_and_psr() {
+    let mut vm = program_vm(&[0x8000]);
+    vm.register_write(6, 0x2FFE);
+    vm.memory_write(0x2FFE, 0x3100); // PC
+    vm.memory_write(0x2FFF, 0x0004); // PSR: supervisor, N
+    let mut machine = MicroMachine::new(TimingModel { memory_latency: 1 });
+    let mut states = Vec::new();
+    machine.instruction(&mut vm, |snapshot| states.push(snapshot.state));
+
+    assert_eq!(&states[4..], TimingModel::execute_states(0x8000, false, false));
+    assert_eq!(vm.register_read(Registers::R_PC.into()), 0x3100);
+    assert_eq!(vm.register_read(6), 0x3000);
+    assert_eq!(vm.register_read(Registers::R_COND.into()), 4);
+}
+
+#[test]
+fn test_keyboard_traps_are_not_compared() {
+    let mut vm = program_vm(&[0xF020, 0xF025]);
+    let mut machine = MicroMachine::new(TimingModel::default());
+    let mut check = CrossCheck::new();
+    while vm.state_read() {
+        check.instruction(&mut machine, &mut vm, |_| {}).unwrap();
+    }
+    assert_eq!((check.checked, check.skipped), (1, 1));
+}
+
+#[test]
+fn test_reserved_opcode_faults() {
+    let mut vm = program_vm(&[0x1021, 0xD000, 0xF025]);
+    let mut machine = MicroMachine::new(TimingModel::default());
+    let mut check = CrossCheck::new();
+    while vm.state_read() {
+        check.instruction(&mut machine, &mut vm, |_| {}).unwrap();
+    }
+    assert_eq!(vm.fault(), Some(Fault::IllegalOpcode { pc: 0x3001, instruction: 0xD000 }));
+    assert_eq!((vm.steps(), check.checked), (2, 2));
+}
+
+#[test]
+fn test_rti_in_user_mode_faults() {
+    // The first RTI drops to user mode at x3001, where the second one faults
+    let mut vm = program_vm(&[0x8000, 0x8000]);
+    vm.register_write(6, 0x2FFE);
+    vm.memory_write(0x2FFE, 0x3001); // PC
+    vm.memory_write(0x2FFF, PSR_USER | 2); // PSR: user, Z
+    let mut machine = MicroMachine::new(TimingModel::default());
+    let mut check = CrossCheck::new();
+    while vm.state_read() {
+        check.instruction(&mut machine, &mut vm, |_| {}).unwrap();
+    }
+    assert_eq!(vm.fault(), Some(Fault::PrivilegeViolation { pc: 0x3001 }));
+    assert_eq!(check.checked, 2);
+}
+
+#[test]
+fn test_rti_to_user_mode_switches_the_vm_to_the_user_stack() {
+    let mut vm = program_vm(&[0x8000, 0xF025]);
+    vm.register_write(6, 0x2FFE);
+    vm.memory_write(0x2FFE, 0x3001); // PC
+    vm.memory_write(0x2FFF, PSR_USER | 1 << 8 | 4); // PSR: user, priority 1, N
+    vm.set_saved_stack_pointers(0x3000, 0x7000);
+    let mut machine = MicroMachine::new(TimingModel::default());
+    let mut check = CrossCheck::new();
+    check.instruction(&mut machine, &mut vm, |_| {}).unwrap();
+    assert_eq!(vm.psr(), PSR_USER | 1 << 8 | 4);
+    assert_eq!(vm.register_read(6), 0x7000);
+    assert_eq!(vm.saved_stack_pointers(), (0x3000, 0x7000));
+}
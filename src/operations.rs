@@ -1,13 +1,12 @@
 
 use crate::hardware;
-use crate::vm::VM;
-use crate::traps::Traps;
+use crate::vm::{Fault, VM};
+use crate::traps::{TrapBinding, Traps};
 use hardware::Registers;
 use hardware::CondtionalFlags;
-use std::io;
-use std::io::Read;
+use crate::keyboard::EOF_CHAR;
 
-fn sign_extension(val: u16, bit_count: u8) -> u16
+pub fn sign_extension(val: u16, bit_count: u8) -> u16
 {
     let shift = 16 - bit_count;
     (((val << shift) as i16) >> shift) as u16
@@ -72,21 +71,19 @@ fn OP_ST(inst:u16,vm:&mut VM)
 fn OP_JSR(inst:u16,vm:&mut VM)
 {
     let program_counter = vm.register_read(Registers::R_PC.into());
-    vm.register_write(Registers::R_R7.into(), program_counter);
 
-    if ((inst >> 11) & 1) == 1 {
+    // The target is computed before R7 is written, so JSRR R7 jumps to the old R7
+    let target = if ((inst >> 11) & 1) == 1 {
         // JSR: PC-relative (11-bit offset)
         let offset = sign_extension(inst, 11) as i32;
-        vm.register_write(
-            Registers::R_PC.into(),
-            (program_counter as i32 + offset) as u16,
-        );
+        (program_counter as i32 + offset) as u16
     } else {
         // JSRR: register-based
         let base_r = (inst >> 6) & 0x7;
-        let target = vm.register_read(base_r as usize);
-        vm.register_write(Registers::R_PC.into(), target);
-    }
+        vm.register_read(base_r as usize)
+    };
+    vm.register_write(Registers::R_PC.into(), target);
+    vm.register_write(Registers::R_R7.into(), program_counter);
 }
 
 
@@ -139,7 +136,12 @@ fn OP_STR(inst:u16,vm:&mut VM)
 
 fn OP_RTI(inst:u16,vm:&mut VM)
 {
-    
+    if vm.user_mode()
+    {
+        let pc = vm.register_read(Registers::R_PC.into()).wrapping_sub(1);
+        return vm.raise(Fault::PrivilegeViolation { pc });
+    }
+    vm.return_from_interrupt();
 }
 
 
@@ -184,7 +186,8 @@ fn OP_JMP(inst:u16,vm:&mut VM)
 
 fn OP_RES(inst:u16,vm:&mut VM)
 {
-    panic!("Illegal Opcode");
+    let pc = vm.register_read(Registers::R_PC.into()).wrapping_sub(1);
+    vm.raise(Fault::IllegalOpcode { pc, instruction: inst });
 }
 
 
@@ -201,71 +204,127 @@ fn OP_LEA(inst:u16,vm:&mut VM)
 
 fn OP_TRAP(inst:u16,vm:&mut VM)
 {
+    if let Some(TrapBinding::Routine(_)) = vm.trap_binding(inst as u8)
+    {
+        return vm.enter_trap(inst as u8);
+    }
     let pc = vm.register_read(Registers::R_PC.into());
     vm.register_write(Registers::R_R7.into(), pc);
-    let instr = (inst & 0xFF).into(); 
+    if let Some(handler) = vm.trap_handler(inst as u8)
+    {
+        handler(vm);
+        return;
+    }
+    let instr = match Traps::from(inst & 0xFF)
+    {
+        trap if trap.is_extended() && !vm.extended_traps() => Traps::TRAP_INVALID,
+        trap => trap,
+    };
     match (instr)
     {
         Traps::TRAP_GETC => 
         {
-            let mut buffer = [0u8; 1];
-            // read one byte from stdin (line-buffered: requires Enter)
-            io::stdin().read_exact(&mut buffer).unwrap();
-            vm.register_write(Registers::R_R0.into(), buffer[0] as u16);
+            let character = vm.keyboard_read().map_or(EOF_CHAR, |byte| byte as u16);
+            vm.register_write(Registers::R_R0.into(), character);
 
             vm.update_flags(Registers::R_R0.into());
         }
         Traps::TRAP_OUT => 
         {
-            let character = vm.register_read(Registers::R_R0.into());
-            print!("{character}");
+            let character = vm.register_read(Registers::R_R0.into()) as u8;
+            vm.display().write(&[character]);
         }
         Traps::TRAP_PUTS => 
         {
-            let mut base_address = vm.register_read(Registers::R_R0.into());
+            let start = vm.register_read(Registers::R_R0.into());
+            let mut base_address = start;
             loop 
             {
-                let chr = vm.memory_read(base_address) as u8;
+                // One character per word in bits [7:0], ending at x0000.
+                let chr = vm.memory_read(base_address);
                 if chr == 0  {break;}
-                print!("{}",chr as char);
-                base_address+=1;
+                vm.display().write(&[chr as u8]);
+                base_address = base_address.wrapping_add(1);
+                if base_address == start {vm.raise(Fault::UnterminatedString { address: start }); break;}
             }
         }
         Traps::TRAP_IN => 
         {
-            println!("Enter a single character: ");
-            let mut buffer = [0u8; 1];
-            // read one byte from stdin (line-buffered: requires Enter)
-            io::stdin().read_exact(&mut buffer).unwrap();
-            vm.register_write(Registers::R_R0.into(), buffer[0] as u16);
+            vm.display().write(b"Enter a single character: \n");
+            let key = vm.keyboard_read();
+            vm.register_write(Registers::R_R0.into(), key.map_or(EOF_CHAR, |byte| byte as u16));
             vm.update_flags(Registers::R_R0.into());
-            print!("{}",buffer[0]);
+            if let Some(byte) = key {vm.display().write(&[byte]);}
         }
         Traps::TRAP_PUTSP => 
         {
-            let base_address = vm.register_read(Registers::R_R0.into());
+            let start = vm.register_read(Registers::R_R0.into());
+            let mut base_address = start;
             loop 
             {
+                // Two characters per word, bits [7:0] first; a string of odd
+                // length ends with x00 in bits [15:8] of its last word.
                 let chrs = vm.memory_read(base_address);
+                if chrs == 0  {break;}
                 let ch1 = (chrs & 255) as u8;
-                if ch1 == 0  {break;}
-                print!("{}",ch1 as char);
+                vm.display().write(&[ch1]);
                 let ch2 = (chrs>>8) as u8;
-                if ch1 == 0  {break;}
-                print!("{}",ch1 as char);
+                if ch2 == 0  {break;}
+                vm.display().write(&[ch2]);
+                base_address = base_address.wrapping_add(1);
+                if base_address == start {vm.raise(Fault::UnterminatedString { address: start }); break;}
             }
         }
         Traps::TRAP_HALT => 
         {
-            println!("VM HAlted");
+            vm.display().status("VM HAlted");
             vm.state_change();
         }
-        Traps::TRAP_INVALID  => {panic!("asd");}
+        Traps::TRAP_PUTD => 
+        {
+            let value = vm.register_read(Registers::R_R0.into()) as i16;
+            vm.display().write(value.to_string().as_bytes());
+        }
+        Traps::TRAP_PUTX => 
+        {
+            let value = vm.register_read(Registers::R_R0.into());
+            vm.display().write(format!("x{value:04X}").as_bytes());
+        }
+        Traps::TRAP_GETD => 
+        {
+            let value = read_decimal(vm);
+            vm.register_write(Registers::R_R0.into(), value);
+            vm.update_flags(Registers::R_R0.into());
+        }
+        Traps::TRAP_NEWLN => 
+        {
+            vm.display().write(b"\n");
+        }
+        Traps::TRAP_INVALID  => {vm.raise(Fault::UnknownTrap { pc: pc.wrapping_sub(1), vector: inst as u8 });}
     }
 }
 
 
 
+/// Reads a line for GETD, echoing it, and returns it as a 16-bit number.
+/// Backspace deletes the last character. Anything but an optional sign and
+/// digits in -32768..=32767 reads as 0.
+fn read_decimal(vm:&mut VM) -> u16
+{
+    let mut line = String::new();
+    while let Some(byte) = vm.keyboard_read()
+    {
+        match byte
+        {
+            b'\n' | b'\r' => {vm.display().write(b"\n"); break;}
+            0x08 | 0x7F => {if line.pop().is_some() {vm.display().write(b"\x08 \x08");}}
+            _ => {line.push(byte as char); vm.display().write(&[byte]);}
+        }
+    }
+    line.trim().parse::<i16>().unwrap_or(0) as u16
+}
+
+
 pub const OPCODE_TABLE:[fn(inst:u16,vm:&mut VM);16] = 
 [
     OP_BR, /* branch */
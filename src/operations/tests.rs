@@ -1,4 +1,7 @@
 
+use crate::keyboard::Keyboard;
+use crate::input_script::InputScript;
+use crate::display::Display;
 use super::*;
 fn as_i16(v: u16) -> i16 
 {
@@ -932,4 +935,697 @@ fn test_str_overwrites_existing_value() {
 }
 
 
-// ---------------- TRAP OPERATION ----------------
\ No newline at end of file
+// ---------------- RTI OPERATION ----------------
+
+#[test]
+fn test_rti_returns_to_supervisor_mode() {
+    let mut vm = VM::new();
+    vm.register_write(6, 0x2FFE);
+    vm.memory_write(0x2FFE, 0x3456); // PC
+    vm.memory_write(0x2FFF, 0x0304); // PSR: priority 3, N
+    OP_RTI(0x8000, &mut vm);
+    assert_eq!(vm.register_read(Registers::R_PC.into()), 0x3456);
+    assert_eq!(vm.register_read(6), 0x3000);
+    assert_eq!(vm.psr(), 0x0304);
+    assert!(vm.state_read());
+}
+
+#[test]
+fn test_rti_to_user_mode_switches_stacks() {
+    let mut vm = VM::new();
+    vm.memory_write(0x0180, 0x1000); // handler of vector x80
+    vm.set_psr(crate::vm::PSR_USER | CondtionalFlags::FL_POS as u16);
+    vm.register_write(6, 0xFD00); // user stack
+    vm.register_write(Registers::R_PC.into(), 0x4000);
+    assert!(vm.interrupt(0x80, 4));
+    assert!(!vm.user_mode());
+    assert_eq!(vm.register_read(6), 0x2FFE);
+    assert_eq!(vm.register_read(Registers::R_PC.into()), 0x1000);
+
+    OP_RTI(0x8000, &mut vm);
+    assert!(vm.user_mode());
+    assert_eq!(vm.register_read(6), 0xFD00);
+    assert_eq!(vm.register_read(Registers::R_PC.into()), 0x4000);
+    assert_eq!(vm.psr(), crate::vm::PSR_USER | CondtionalFlags::FL_POS as u16);
+}
+
+#[test]
+fn test_rti_in_user_mode_is_a_privilege_violation() {
+    let mut vm = VM::new();
+    vm.set_psr(crate::vm::PSR_USER);
+    vm.register_write(Registers::R_PC.into(), 0x3001);
+    OP_RTI(0x8000, &mut vm);
+    assert_eq!(vm.fault(), Some(Fault::PrivilegeViolation { pc: 0x3000 }));
+    assert!(!vm.state_read());
+}
+
+// ---------------- TRAP OPERATION ----------------
+
+/// A VM that captures trap output and reads keys from `input`.
+fn trap_vm(input: &'static [u8]) -> VM {
+    let mut vm = VM::new();
+    vm.set_display(Display::captured_only());
+    vm.set_keyboard(Keyboard::stream(input));
+    vm
+}
+
+/// Runs `inst` at x3000 through `step`, the way a program reaches it.
+fn run_trap(vm: &mut VM, inst: u16) {
+    vm.memory_write(0x3000, inst);
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    vm.step();
+}
+
+fn write_words(vm: &mut VM, address: u16, words: &[u16]) {
+    for (i, &word) in words.iter().enumerate() {
+        vm.memory_write(address + i as u16, word);
+    }
+}
+
+#[test]
+fn test_trap_saves_return_address_in_r7() {
+    for vector in 0x20..=0x24 {
+        let mut vm = trap_vm(b"k");
+        vm.register_write(0, 0x4000); // empty string for PUTS/PUTSP
+        vm.register_write(7, 0x1234);
+        run_trap(&mut vm, 0xF000 | vector);
+
+        assert_eq!(vm.register_read(7), 0x3001, "TRAP x{vector:02X}");
+        assert_eq!(vm.register_read(Registers::R_PC.into()), 0x3001, "TRAP x{vector:02X}");
+        assert!(vm.state_read(), "TRAP x{vector:02X}");
+    }
+}
+
+#[test]
+fn test_halt_stops_without_output() {
+    let mut vm = trap_vm(b"");
+    run_trap(&mut vm, 0xF025); // HALT
+
+    assert!(!vm.state_read());
+    assert_eq!(vm.fault(), None);
+    assert_eq!(vm.register_read(7), 0x3001);
+    // The halt notice is a status line, not program output
+    assert_eq!(vm.display().captured(), b"");
+}
+
+#[test]
+fn test_getc_does_not_echo_and_clears_high_byte() {
+    let mut vm = trap_vm(b"A");
+    vm.register_write(0, 0xFFFF);
+    run_trap(&mut vm, 0xF020); // GETC
+
+    assert_eq!(vm.register_read(0), 0x0041);
+    assert_eq!(vm.register_read(Registers::R_COND.into()), CondtionalFlags::FL_POS as u16);
+    assert_eq!(vm.display().captured(), b"");
+}
+
+#[test]
+fn test_getc_nul_sets_zero_flag() {
+    let mut vm = trap_vm(b"\0");
+    run_trap(&mut vm, 0xF020); // GETC
+
+    assert_eq!(vm.register_read(0), 0);
+    assert_eq!(vm.register_read(Registers::R_COND.into()), CondtionalFlags::FL_ZRO as u16);
+}
+
+#[test]
+fn test_out_prints_low_byte_as_character() {
+    let mut vm = trap_vm(b"");
+    vm.register_write(0, b'A' as u16);
+    OP_TRAP(0xF021, &mut vm); // OUT
+    vm.register_write(0, 0x1F0A); // bits [15:8] are ignored
+    OP_TRAP(0xF021, &mut vm); // OUT
+
+    assert_eq!(vm.display().captured(), b"A\n");
+    assert_eq!(vm.register_read(0), 0x1F0A);
+}
+
+#[test]
+fn test_out_leaves_flags_alone() {
+    let mut vm = trap_vm(b"");
+    vm.register_write(0, 0);
+    vm.register_write(Registers::R_COND.into(), CondtionalFlags::FL_NEG as u16);
+    run_trap(&mut vm, 0xF021); // OUT
+
+    assert_eq!(vm.register_read(Registers::R_COND.into()), CondtionalFlags::FL_NEG as u16);
+    assert_eq!(vm.display().captured(), b"\0");
+}
+
+#[test]
+fn test_puts_prints_one_character_per_word() {
+    let mut vm = trap_vm(b"");
+    // Bits [15:8] are not part of the character
+    write_words(&mut vm, 0x4000, &[0x0048, 0xFF69, 0x0021, 0x0000, 0x0058]);
+    vm.register_write(0, 0x4000);
+    run_trap(&mut vm, 0xF022); // PUTS
+
+    assert_eq!(vm.display().captured(), b"Hi!");
+    assert_eq!(vm.register_read(0), 0x4000);
+}
+
+#[test]
+fn test_puts_ends_only_at_x0000() {
+    let mut vm = trap_vm(b"");
+    write_words(&mut vm, 0x4000, &[0x0061, 0x0100, 0x0062, 0x0000]);
+    vm.register_write(0, 0x4000);
+    run_trap(&mut vm, 0xF022); // PUTS
+
+    assert_eq!(vm.display().captured(), b"a\0b");
+}
+
+#[test]
+fn test_puts_empty_string() {
+    let mut vm = trap_vm(b"");
+    vm.register_write(0, 0x4000);
+    run_trap(&mut vm, 0xF022); // PUTS
+
+    assert_eq!(vm.display().captured(), b"");
+    assert!(vm.state_read());
+}
+
+#[test]
+fn test_in_prompts_and_echoes_the_character() {
+    let mut vm = trap_vm(b"xy");
+    run_trap(&mut vm, 0xF023); // IN
+
+    assert_eq!(vm.display().captured(), b"Enter a single character: \nx");
+    assert_eq!(vm.register_read(0), b'x' as u16);
+    assert_eq!(vm.register_read(Registers::R_COND.into()), CondtionalFlags::FL_POS as u16);
+}
+
+#[test]
+fn test_in_at_eof_echoes_nothing() {
+    let mut vm = trap_vm(b"");
+    run_trap(&mut vm, 0xF023); // IN
+
+    assert_eq!(vm.display().captured(), b"Enter a single character: \n");
+    assert_eq!(vm.register_read(0), EOF_CHAR);
+    assert_eq!(vm.register_read(Registers::R_COND.into()), CondtionalFlags::FL_NEG as u16);
+}
+
+#[test]
+fn test_putsp_prints_low_byte_first() {
+    let mut vm = trap_vm(b"");
+    // "Hello": He, ll, o with x00 in the high byte of the last word
+    write_words(&mut vm, 0x4000, &[0x6548, 0x6C6C, 0x006F, 0x0000]);
+    vm.register_write(0, 0x4000);
+    run_trap(&mut vm, 0xF024); // PUTSP
+
+    assert_eq!(vm.display().captured(), b"Hello");
+    assert_eq!(vm.register_read(0), 0x4000);
+}
+
+#[test]
+fn test_putsp_even_length_ends_at_x0000() {
+    let mut vm = trap_vm(b"");
+    write_words(&mut vm, 0x4000, &[0x6948, 0x2121, 0x0000, 0x7A7A]);
+    vm.register_write(0, 0x4000);
+    run_trap(&mut vm, 0xF024); // PUTSP
+
+    assert_eq!(vm.display().captured(), b"Hi!!");
+}
+
+#[test]
+fn test_putsp_high_byte_zero_ends_string() {
+    let mut vm = trap_vm(b"");
+    write_words(&mut vm, 0x4000, &[0x0041, 0x4342]);
+    vm.register_write(0, 0x4000);
+    run_trap(&mut vm, 0xF024); // PUTSP
+
+    assert_eq!(vm.display().captured(), b"A");
+}
+
+#[test]
+fn test_puts_faults_without_terminator() {
+    // Every word is nonzero, KBSR included while a key is waiting
+    let mut vm = trap_vm(b"k");
+    for address in 0..=0xFFFF {
+        vm.memory_write(address, 0x2121);
+    }
+    vm.register_write(0, 0x4000);
+    OP_TRAP(0xF022, &mut vm); // PUTS
+
+    assert_eq!(vm.fault(), Some(Fault::UnterminatedString { address: 0x4000 }));
+    assert!(!vm.state_read());
+}
+
+/// Like `trap_vm`, with TRAP x26-x29 serviced.
+fn extended_vm(input: &'static [u8]) -> VM {
+    let mut vm = trap_vm(input);
+    vm.set_extended_traps(true);
+    vm
+}
+
+#[test]
+fn test_extended_traps_are_unknown_by_default() {
+    for vector in 0x26..=0x29 {
+        let mut vm = trap_vm(b"1\n");
+        run_trap(&mut vm, 0xF000 | vector);
+
+        assert_eq!(vm.fault(), Some(Fault::UnknownTrap { pc: 0x3000, vector: vector as u8 }));
+        assert_eq!(vm.display().captured(), b"");
+    }
+}
+
+#[test]
+fn test_extended_traps_save_return_address_in_r7() {
+    for vector in 0x26..=0x29 {
+        let mut vm = extended_vm(b"1\n");
+        run_trap(&mut vm, 0xF000 | vector);
+
+        assert_eq!(vm.register_read(7), 0x3001, "TRAP x{vector:02X}");
+        assert!(vm.state_read(), "TRAP x{vector:02X}");
+    }
+}
+
+#[test]
+fn test_putd_prints_r0_as_signed_decimal() {
+    let mut vm = extended_vm(b"");
+    for value in [0, 42, 0x7FFF, 0x8000, 0xFFFF] {
+        vm.register_write(0, value);
+        OP_TRAP(0xF026, &mut vm); // PUTD
+        OP_TRAP(0xF029, &mut vm); // NEWLN
+    }
+
+    assert_eq!(vm.display().captured(), b"0\n42\n32767\n-32768\n-1\n");
+}
+
+#[test]
+fn test_putx_prints_r0_as_hex() {
+    let mut vm = extended_vm(b"");
+    vm.register_write(0, 0x00AF);
+    OP_TRAP(0xF027, &mut vm); // PUTX
+    vm.register_write(0, 0xBEEF);
+    OP_TRAP(0xF027, &mut vm); // PUTX
+
+    assert_eq!(vm.display().captured(), b"x00AFxBEEF");
+    assert_eq!(vm.register_read(0), 0xBEEF);
+}
+
+#[test]
+fn test_getd_reads_and_echoes_a_line() {
+    let mut vm = extended_vm(b"-123\n77\r");
+    OP_TRAP(0xF028, &mut vm); // GETD
+    assert_eq!(vm.register_read(0), -123i16 as u16);
+    assert_eq!(vm.register_read(Registers::R_COND.into()), CondtionalFlags::FL_NEG as u16);
+    OP_TRAP(0xF028, &mut vm); // GETD
+    assert_eq!(vm.register_read(0), 77);
+    assert_eq!(vm.register_read(Registers::R_COND.into()), CondtionalFlags::FL_POS as u16);
+
+    assert_eq!(vm.display().captured(), b"-123\n77\n");
+}
+
+#[test]
+fn test_getd_backspace_erases() {
+    let mut vm = extended_vm(b"12\x7f3\n");
+    OP_TRAP(0xF028, &mut vm); // GETD
+
+    assert_eq!(vm.register_read(0), 13);
+    assert_eq!(vm.display().captured(), b"12\x08 \x083\n");
+}
+
+#[test]
+fn test_getd_rejects_non_numbers() {
+    for input in [&b"abc\n"[..], b"40000\n", b"\n", b""] {
+        let mut vm = extended_vm(input);
+        vm.register_write(0, 0x1234);
+        OP_TRAP(0xF028, &mut vm); // GETD
+
+        assert_eq!(vm.register_read(0), 0, "{input:?}");
+        assert_eq!(vm.register_read(Registers::R_COND.into()), CondtionalFlags::FL_ZRO as u16);
+    }
+}
+
+#[test]
+fn test_getd_line_may_end_at_eof() {
+    let mut vm = extended_vm(b" 7 ");
+    OP_TRAP(0xF028, &mut vm); // GETD
+
+    assert_eq!(vm.register_read(0), 7);
+    assert_eq!(vm.display().captured(), b" 7 ");
+}
+
+#[test]
+fn test_registered_trap_runs_closure() {
+    let mut vm = trap_vm(b"");
+    vm.register_trap(0x30, |vm| {
+        let doubled = vm.register_read(0).wrapping_mul(2);
+        vm.register_write(0, doubled);
+    });
+    vm.register_write(0, 21);
+    run_trap(&mut vm, 0xF030);
+
+    assert_eq!(vm.register_read(0), 42);
+    assert_eq!(vm.register_read(7), 0x3001);
+    assert_eq!(vm.register_read(Registers::R_PC.into()), 0x3001);
+    assert!(vm.state_read());
+}
+
+#[test]
+fn test_registered_trap_overrides_builtin() {
+    let mut vm = trap_vm(b"");
+    assert!(vm.register_trap(0x21, |vm| vm.display().write(b"[out]")).is_none());
+    vm.register_write(0, b'A' as u16);
+    OP_TRAP(0xF021, &mut vm); // OUT, overridden
+    assert!(vm.unregister_trap(0x21).is_some());
+    OP_TRAP(0xF021, &mut vm); // OUT, built in again
+
+    assert_eq!(vm.display().captured(), b"[out]A");
+}
+
+#[test]
+fn test_register_trap_returns_replaced_handler() {
+    let mut vm = trap_vm(b"");
+    vm.register_trap(0x40, |vm| vm.register_write(1, 1));
+    let replaced = vm.register_trap(0x40, |vm| vm.register_write(1, 2)).unwrap();
+    OP_TRAP(0xF040, &mut vm);
+    assert_eq!(vm.register_read(1), 2);
+    replaced(&mut vm);
+    assert_eq!(vm.register_read(1), 1);
+    assert!(vm.unregister_trap(0x41).is_none());
+}
+
+#[test]
+fn test_trap_handler_state_survives_jit() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // x3000: TRAP x30 ; ADD R1, R1, #-1 ; BRp x3000 ; HALT
+    let calls = Rc::new(Cell::new(0));
+    let counter = calls.clone();
+    let mut vm = trap_vm(b"");
+    vm.set_jit(crate::jit::Jit::new(2));
+    vm.register_trap(0x30, move |_| counter.set(counter.get() + 1));
+    write_words(&mut vm, 0x3000, &[0xF030, 0x127F, 0x03FD, 0xF025]);
+    vm.register_write(1, 100);
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    while vm.state_read() {
+        vm.run_block();
+    }
+
+    assert_eq!(calls.get(), 100);
+    assert_eq!(vm.fault(), None);
+}
+
+#[test]
+fn test_handler_can_halt_and_fault() {
+    let mut vm = trap_vm(b"");
+    vm.register_trap(0x25, |vm| vm.state_change());
+    run_trap(&mut vm, 0xF025);
+    assert!(!vm.state_read());
+
+    let mut vm = trap_vm(b"");
+    vm.register_trap(0x31, |vm| vm.raise(Fault::UnknownTrap { pc: 0, vector: 0x31 }));
+    run_trap(&mut vm, 0xF031);
+    assert_eq!(vm.fault(), Some(Fault::UnknownTrap { pc: 0, vector: 0x31 }));
+}
+
+#[test]
+fn test_bound_traps_lists_builtins_and_handlers() {
+    use crate::traps::TrapBinding::{BuiltIn, Handler};
+
+    let mut vm = trap_vm(b"");
+    let builtin: Vec<_> = (0x20..=0x25).map(|vector| (vector, BuiltIn)).collect();
+    assert_eq!(vm.bound_traps(), builtin);
+    assert_eq!(vm.trap_binding(0x26), None);
+
+    vm.set_extended_traps(true);
+    assert_eq!(vm.bound_traps().len(), 10);
+    assert_eq!(vm.trap_binding(0x29), Some(BuiltIn));
+
+    vm.register_trap(0x21, |_| {});
+    vm.register_trap(0x00, |_| {});
+    vm.register_trap(0xFF, |_| {});
+    let bound = vm.bound_traps();
+    assert_eq!(bound.len(), 12);
+    assert_eq!(bound[0], (0x00, Handler));
+    assert_eq!(bound[2], (0x21, Handler));
+    assert_eq!(bound[11], (0xFF, Handler));
+}
+
+#[test]
+fn test_vectored_trap_enters_routine_in_memory() {
+    use crate::traps::TrapBinding::{BuiltIn, Routine};
+
+    let mut vm = trap_vm(b"");
+    vm.memory_write(0x0021, 0x0400);
+    assert_eq!(vm.trap_binding(0x21), Some(BuiltIn));
+    vm.set_vectored_traps(true);
+    assert_eq!(vm.trap_binding(0x21), Some(Routine(0x0400)));
+    // Vectors left at zero keep the built-in routine
+    assert_eq!(vm.trap_binding(0x22), Some(BuiltIn));
+
+    vm.register_write(6, 0x2000);
+    vm.register_write(7, 0x1111);
+    vm.register_write(Registers::R_COND.into(), CondtionalFlags::FL_POS as u16);
+    run_trap(&mut vm, 0xF021);
+    assert_eq!(vm.register_read(Registers::R_PC.into()), 0x0400);
+    assert_eq!(vm.register_read(7), 0x1111);
+    assert_eq!(vm.register_read(6), 0x1FFE);
+    assert_eq!(vm.memory_peek(0x1FFE), 0x3001);
+    assert_eq!(vm.memory_peek(0x1FFF), CondtionalFlags::FL_POS as u16);
+    assert!(vm.display().captured().is_empty());
+
+    OP_RTI(0x8000, &mut vm);
+    assert_eq!(vm.register_read(Registers::R_PC.into()), 0x3001);
+    assert_eq!(vm.register_read(6), 0x2000);
+}
+
+// ---------------- ACCESS CONTROL ----------------
+
+/// A VM in user mode with access control on, about to run `words` at x3000.
+fn user_vm(words: &[u16]) -> VM {
+    let mut vm = trap_vm(b"");
+    vm.set_access_control(true);
+    write_words(&mut vm, 0x3000, words);
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    vm.set_psr(crate::vm::PSR_USER | CondtionalFlags::FL_ZRO as u16);
+    vm
+}
+
+fn violation(words: &[u16], registers: &[(usize, u16)]) -> Option<Fault> {
+    let mut vm = user_vm(words);
+    for &(register, value) in registers {
+        vm.register_write(register, value);
+    }
+    vm.step();
+    vm.fault()
+}
+
+#[test]
+fn test_user_mode_may_not_touch_system_or_device_space() {
+    let acv = |instruction, address| Some(Fault::AccessViolation { pc: 0x3000, instruction, address });
+    assert_eq!(violation(&[0x2000 | 0x1FF], &[]), None); // LD x3100
+    assert_eq!(violation(&[0x2000 | 0x100], &[]), acv(0x2100, 0x2F01)); // LD x2F01
+    assert_eq!(violation(&[0x7040], &[(1, 0xFE06)]), acv(0x7040, 0xFE06)); // STR R0, R1, #0
+    assert_eq!(violation(&[0x6040], &[(1, 0x2FFF)]), acv(0x6040, 0x2FFF)); // LDR R0, R1, #0
+    assert_eq!(violation(&[0x6040], &[(1, 0xFDFF)]), None);
+    // LDI and STI through a pointer into protected memory
+    assert_eq!(violation(&[0xA001, 0, 0xFE00], &[]), acv(0xA001, 0xFE00));
+    assert_eq!(violation(&[0xB001, 0, 0x0000], &[]), acv(0xB001, 0x0000));
+    assert_eq!(violation(&[0xA001, 0, 0x4000], &[]), None);
+}
+
+#[test]
+fn test_acv_leaves_the_machine_untouched() {
+    let mut vm = user_vm(&[0x7040]); // STR R0, R1, #0
+    vm.register_write(0, 0x1234);
+    vm.register_write(1, 0x0200);
+    vm.step();
+    assert!(!vm.state_read());
+    assert_eq!(vm.memory_peek(0x0200), 0);
+    assert_eq!(vm.register_read(Registers::R_PC.into()), 0x3000);
+    let message = vm.fault().unwrap().to_string();
+    assert_eq!(message, "access control violation at x3000 (STR R0, R1, #0): user mode may not access x0200");
+}
+
+#[test]
+fn test_user_mode_may_not_fetch_from_system_space() {
+    let mut vm = user_vm(&[0xC040]); // JMP R1
+    vm.register_write(1, 0x0200);
+    vm.step();
+    assert_eq!(vm.register_read(Registers::R_PC.into()), 0x0200);
+    vm.step();
+    assert_eq!(vm.fault(), Some(Fault::AccessViolation { pc: 0x0200, instruction: 0, address: 0x0200 }));
+    assert!(vm.fault().unwrap().to_string().contains("fetched x0000 from x0200"));
+}
+
+#[test]
+fn test_acv_enters_the_exception_handler() {
+    let mut vm = user_vm(&[0x2000 | 0x100]); // LD R0, x2F01
+    vm.memory_write(0x0102, 0x1000);
+    vm.memory_write(0x1000, 0x1261); // ADD R1, R1, #1
+    vm.register_write(6, 0xFD00);
+    vm.step();
+    assert_eq!(vm.fault(), None);
+    assert!(!vm.user_mode());
+    assert_eq!(vm.register_read(1), 1);
+    assert_eq!(vm.register_read(Registers::R_PC.into()), 0x1001);
+    // The pushed PC is the violating instruction's, on the supervisor stack
+    assert_eq!(vm.register_read(6), 0x2FFE);
+    assert_eq!(vm.memory_peek(0x2FFE), 0x3000);
+    assert_eq!(vm.memory_peek(0x2FFF), crate::vm::PSR_USER | CondtionalFlags::FL_ZRO as u16);
+}
+
+#[test]
+fn test_access_control_spares_supervisor_mode_and_traps() {
+    // Built-in routines may read the string wherever it is
+    let mut vm = user_vm(&[0xF022]); // PUTS
+    vm.register_write(0, 0x0200);
+    vm.memory_write(0x0200, b'k' as u16);
+    vm.step();
+    assert_eq!(vm.fault(), None);
+    assert_eq!(vm.display().captured(), b"k");
+
+    let mut vm = user_vm(&[0x6040]);
+    vm.register_write(1, 0xFE04);
+    vm.set_psr(0);
+    vm.step();
+    assert_eq!(vm.register_read(0), 1 << 15);
+
+    let mut vm = user_vm(&[0x6040]);
+    vm.set_access_control(false);
+    vm.register_write(1, 0xFE04);
+    vm.step();
+    assert_eq!(vm.fault(), None);
+}
+
+// ---------------- HEADLESS KEYBOARD ----------------
+
+#[test]
+fn test_kbsr_ready_with_piped_byte() {
+    let mut vm = VM::new();
+    vm.set_keyboard(Keyboard::stream(&b"a"[..]));
+
+    // Polling KBSR latches the next byte into KBDR
+    assert_eq!(vm.memory_read(0xFE00), 1 << 15);
+    assert_eq!(vm.memory_read(0xFE02), b'a' as u16);
+}
+
+#[test]
+fn test_kbsr_not_ready_at_eof() {
+    let mut vm = VM::new();
+    vm.set_keyboard(Keyboard::stream(&b""[..]));
+
+    assert_eq!(vm.memory_read(0xFE00), 0);
+    assert_eq!(vm.memory_read(0xFE00), 0);
+}
+
+#[test]
+fn test_getc_reads_piped_bytes_in_order() {
+    let mut vm = VM::new();
+    vm.set_keyboard(Keyboard::stream(&b"hi"[..]));
+
+    OP_TRAP(0xF020, &mut vm); // GETC
+    assert_eq!(vm.register_read(0), b'h' as u16);
+    OP_TRAP(0xF020, &mut vm); // GETC
+    assert_eq!(vm.register_read(0), b'i' as u16);
+}
+
+#[test]
+fn test_getc_at_eof_returns_xffff() {
+    let mut vm = VM::new();
+    vm.set_keyboard(Keyboard::stream(&b""[..]));
+
+    OP_TRAP(0xF020, &mut vm); // GETC
+    assert_eq!(vm.register_read(0), EOF_CHAR);
+    assert_eq!(
+        vm.register_read(Registers::R_COND.into()),
+        CondtionalFlags::FL_NEG as u16
+    );
+}
+
+// ---------------- SCRIPTED KEYBOARD ----------------
+
+fn scripted_vm(script: &str) -> VM {
+    let mut vm = VM::new();
+    vm.set_keyboard(Keyboard::Script(InputScript::parse(script).unwrap()));
+    vm
+}
+
+#[test]
+fn test_script_key_available_at_step() {
+    let mut vm = scripted_vm("3 \"k\"\n");
+    // x3000: LDI R1, KBSR ; BRzp x3000 ; HALT ; .FILL xFE00
+    vm.memory_write(0x3000, 0b1010_001_000000010);
+    vm.memory_write(0x3001, 0b0000_011_111111110);
+    vm.memory_write(0x3002, 0xF025);
+    vm.memory_write(0x3003, 0xFE00);
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+
+    while vm.state_read() {
+        vm.step();
+    }
+    // Polls at steps 0 and 2 miss, the poll at step 4 sees the key,
+    // then the branch falls through and HALT is the 7th instruction
+    assert_eq!(vm.steps(), 7);
+    assert_eq!(vm.memory_read(0xFE02), b'k' as u16);
+}
+
+#[test]
+fn test_script_relative_delays_and_named_keys() {
+    let script = InputScript::parse("# comment\n10 x41\n+5 \"b\\n\"  # trailing\n+0 enter\n").unwrap();
+    assert!(!script.key_ready(9));
+    assert!(script.key_ready(10));
+
+    // Empty memory decodes as BR with no condition bits, i.e. a NOP
+    let mut vm = scripted_vm("10 x41\n+5 \"b\\n\"\n+0 enter\n");
+    for step in 0..18 {
+        let ready = vm.memory_read(0xFE00) != 0;
+        assert_eq!(ready, step == 10 || (15..18).contains(&step), "step {step}");
+        vm.step();
+    }
+    assert_eq!(vm.memory_read(0xFE00), 0);
+}
+
+#[test]
+fn test_script_getc_does_not_wait_for_step() {
+    let mut vm = scripted_vm("1000 \"z\"\n");
+    OP_TRAP(0xF020, &mut vm); // GETC
+    assert_eq!(vm.register_read(0), b'z' as u16);
+    OP_TRAP(0xF020, &mut vm); // GETC
+    assert_eq!(vm.register_read(0), EOF_CHAR);
+}
+
+#[test]
+fn test_script_rejects_bad_lines() {
+    assert!(InputScript::parse("abc \"x\"\n").is_err());
+    assert!(InputScript::parse("10 \"x\"\n5 \"y\"\n").is_err());
+    assert!(InputScript::parse("10 \"unterminated\n").is_err());
+    assert!(InputScript::parse("10 shift\n").is_err());
+}
+
+// ---------------- DISPLAY ----------------
+
+#[test]
+fn test_dsr_always_ready() {
+    let mut vm = VM::new();
+    assert_eq!(vm.memory_read(0xFE04), 1 << 15);
+}
+
+#[test]
+fn test_ddr_write_reaches_display() {
+    let mut vm = VM::new();
+    vm.set_display(Display::captured_only());
+
+    // STI R0 to DDR, as a polling output loop would
+    vm.register_write(0, b'Q' as u16);
+    vm.register_write(Registers::R_PC.into(), 0x3001);
+    vm.memory_write(0x3001, 0xFE06);
+    OP_STI(0b1011_000_000000000, &mut vm);
+
+    assert_eq!(vm.display().captured(), b"Q");
+}
+
+#[test]
+fn test_puts_output_is_captured() {
+    let mut vm = VM::new();
+    vm.set_display(Display::captured_only());
+    for (i, c) in b"ok\n".iter().enumerate() {
+        vm.memory_write(0x4000 + i as u16, *c as u16);
+    }
+    vm.register_write(0, 0x4000);
+
+    OP_TRAP(0xF022, &mut vm); // PUTS
+    assert_eq!(vm.display().captured(), b"ok\n");
+}
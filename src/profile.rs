@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use crate::disasm::{disassemble, OPCODE_NAMES};
+use crate::hardware::MEMORY_MAX;
+use crate::symbols::SymbolTable;
+
+/// One node of the calling-context tree: a subroutine reached through a
+/// particular chain of JSR/JSRR calls.
+struct Frame
+{
+    entry: u16,
+    parent: usize,
+    cycles: u64, // spent in this frame itself, not in its callees
+}
+
+/// Execution counts per address and opcode, plus cycles attributed to
+/// subroutines by following JSR/JSRR and `RET` (`JMP R7`).
+pub struct Profiler
+{
+    per_address: Vec<u64>,
+    per_opcode: [u64; 16],
+    frames: Vec<Frame>,
+    children: HashMap<(usize, u16), usize>,
+    calls: HashMap<u16, u64>,
+    current: usize,
+}
+
+impl Profiler
+{
+    /// `entry` names the root frame, normally where execution starts.
+    pub fn new(entry: u16) -> Self
+    {
+        Self
+        {
+            per_address: vec![0; MEMORY_MAX],
+            per_opcode: [0; 16],
+            frames: vec![Frame { entry, parent: 0, cycles: 0 }],
+            children: HashMap::new(),
+            calls: HashMap::new(),
+            current: 0,
+        }
+    }
+
+    /// Accounts for `inst` executed at `pc`; `next_pc` is the PC after it ran.
+    pub fn record(&mut self, pc: u16, inst: u16, next_pc: u16, cycles: u64)
+    {
+        self.per_address[pc as usize] += 1;
+        self.per_opcode[(inst >> 12) as usize] += 1;
+        self.frames[self.current].cycles += cycles;
+
+        let opcode = inst >> 12;
+        if opcode == 0x4
+        {
+            *self.calls.entry(next_pc).or_default() += 1;
+            let parent = self.current;
+            let next_id = self.frames.len();
+            self.current = *self.children.entry((parent, next_pc)).or_insert(next_id);
+            if self.current == next_id
+            {
+                self.frames.push(Frame { entry: next_pc, parent, cycles: 0 });
+            }
+        }
+        else if opcode == 0xC && (inst >> 6) & 7 == 7 && self.current != 0
+        {
+            self.current = self.frames[self.current].parent;
+        }
+    }
+
+    pub fn total(&self) -> u64
+    {
+        self.per_opcode.iter().sum()
+    }
+
+    fn path(&self, mut frame: usize) -> Vec<u16>
+    {
+        let mut path = vec![self.frames[frame].entry];
+        while frame != 0
+        {
+            frame = self.frames[frame].parent;
+            path.push(self.frames[frame].entry);
+        }
+        path.reverse();
+        path
+    }
+
+    /// Per subroutine: (entry, calls, self cycles, inclusive cycles), hottest first.
+    pub fn subroutines(&self) -> Vec<(u16, u64, u64, u64)>
+    {
+        let mut totals: HashMap<u16, (u64, u64)> = HashMap::new();
+        for (id, frame) in self.frames.iter().enumerate()
+        {
+            totals.entry(frame.entry).or_default().0 += frame.cycles;
+            // Recursive frames count once towards each distinct subroutine on the path
+            let mut seen = self.path(id);
+            seen.sort_unstable();
+            seen.dedup();
+            for entry in seen
+            {
+                totals.entry(entry).or_default().1 += frame.cycles;
+            }
+        }
+        let mut rows: Vec<_> = totals
+            .into_iter()
+            .map(|(entry, (own, inclusive))| (entry, self.calls.get(&entry).copied().unwrap_or(0), own, inclusive))
+            .collect();
+        rows.sort_by(|a, b| b.3.cmp(&a.3).then(b.2.cmp(&a.2)).then(a.0.cmp(&b.0)));
+        rows
+    }
+
+    /// Hottest addresses as (address, count), most executed first.
+    pub fn hot_spots(&self) -> Vec<(u16, u64)>
+    {
+        let mut rows: Vec<(u16, u64)> = self
+            .per_address
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(address, &count)| (address as u16, count))
+            .collect();
+        rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        rows
+    }
+
+    /// Stacks in the folded format read by `flamegraph.pl` and inferno:
+    /// `main;outer;inner 1234` per line.
+    pub fn folded(&self, symbols: &SymbolTable) -> String
+    {
+        let mut lines: Vec<String> = self
+            .frames
+            .iter()
+            .enumerate()
+            .filter(|(_, frame)| frame.cycles > 0)
+            .map(|(id, frame)| {
+                let names: Vec<String> = self.path(id).into_iter().map(|entry| symbols.describe(entry)).collect();
+                format!("{} {}", names.join(";"), frame.cycles)
+            })
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+
+    /// Human-readable hot-spot report. `memory` supplies the words to disassemble.
+    pub fn report(&self, symbols: &SymbolTable, top: usize, memory: impl Fn(u16) -> u16) -> String
+    {
+        let total = self.total().max(1);
+        let percent = |count: u64| count as f64 * 100.0 / total as f64;
+        let mut out = format!("{} instructions executed\n\nHot spots:\n", self.total());
+        out += &format!("{:>10} {:>6}  {:<6} {:<16} {}\n", "count", "%", "addr", "location", "instruction");
+        for (address, count) in self.hot_spots().into_iter().take(top)
+        {
+            out += &format!(
+                "{count:>10} {:>5.1}%  x{address:04X}  {:<16} {}\n",
+                percent(count),
+                symbols.describe(address),
+                disassemble(address, memory(address))
+            );
+        }
+
+        out += &format!("\nSubroutines:\n{:>10} {:>10} {:>6} {:>10} {:>6}  {}\n", "calls", "self", "%", "total", "%", "entry");
+        for (entry, calls, own, inclusive) in self.subroutines().into_iter().take(top)
+        {
+            out += &format!(
+                "{calls:>10} {own:>10} {:>5.1}% {inclusive:>10} {:>5.1}%  {}\n",
+                percent(own),
+                percent(inclusive),
+                symbols.describe(entry)
+            );
+        }
+
+        out += "\nOpcodes:\n";
+        let mut opcodes: Vec<(usize, u64)> = self.per_opcode.iter().copied().enumerate().filter(|&(_, c)| c > 0).collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (opcode, count) in opcodes
+        {
+            out += &format!("{count:>10} {:>5.1}%  {}\n", percent(count), OPCODE_NAMES[opcode]);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,68 @@
+use super::*;
+use crate::hardware::Registers;
+use crate::vm::VM;
+
+// MAIN: JSR SUB ; JSR SUB ; HALT
+// SUB:  ADD R0, R0, #1 ; RET
+const PROGRAM: [u16; 5] = [0x4802, 0x4801, 0xF025, 0x1021, 0xC1C0];
+
+fn symbols() -> SymbolTable {
+    let mut symbols = SymbolTable::default();
+    symbols.extend("MAIN x3000\nSUB x3003\n");
+    symbols
+}
+
+fn profiled_run() -> VM {
+    let mut vm = VM::new();
+    for (i, word) in PROGRAM.iter().enumerate() {
+        vm.memory_write(0x3000 + i as u16, *word);
+    }
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    vm.set_profiler(Profiler::new(0x3000));
+    while vm.state_read() {
+        vm.step();
+    }
+    vm
+}
+
+#[test]
+fn test_counts_per_address_and_opcode() {
+    let vm = profiled_run();
+    let profiler = vm.profiler().unwrap();
+    assert_eq!(profiler.total(), 7);
+    assert_eq!(profiler.hot_spots(), vec![(0x3003, 2), (0x3004, 2), (0x3000, 1), (0x3001, 1), (0x3002, 1)]);
+    assert_eq!(profiler.per_opcode[0x4], 2);
+    assert_eq!(profiler.per_opcode[0xC], 2);
+}
+
+#[test]
+fn test_cycles_attributed_to_subroutines() {
+    let vm = profiled_run();
+    let profiler = vm.profiler().unwrap();
+    // (entry, calls, self, inclusive)
+    assert_eq!(profiler.subroutines(), vec![(0x3000, 0, 3, 7), (0x3003, 2, 4, 4)]);
+    assert_eq!(profiler.folded(&symbols()), "MAIN 3\nMAIN;SUB 4\n");
+}
+
+#[test]
+fn test_recursion_counts_once_per_subroutine() {
+    let mut profiler = Profiler::new(0x3000);
+    profiler.record(0x3000, 0x4800, 0x3100, 1); // JSR F
+    profiler.record(0x3100, 0x4800, 0x3100, 1); // F: JSR F
+    profiler.record(0x3100, 0x1021, 0x3101, 1); // ADD
+    profiler.record(0x3101, 0xC1C0, 0x3101, 1); // RET
+    profiler.record(0x3101, 0xC1C0, 0x3001, 1); // RET
+    profiler.record(0x3001, 0xC1C0, 0x3001, 1); // RET with nothing to return from
+    assert_eq!(profiler.subroutines(), vec![(0x3000, 0, 2, 6), (0x3100, 2, 4, 4)]);
+    assert_eq!(profiler.folded(&SymbolTable::default()), "x3000 2\nx3000;x3100 2\nx3000;x3100;x3100 2\n");
+}
+
+#[test]
+fn test_report_uses_labels() {
+    let vm = profiled_run();
+    let report = vm.profiler().unwrap().report(&symbols(), 3, |address| vm.memory_peek(address));
+    assert!(report.starts_with("7 instructions executed\n"));
+    assert!(report.contains("x3003  SUB              ADD R0, R0, #1\n"));
+    assert!(report.contains("MAIN\n"));
+    assert!(report.contains("  JSR\n"));
+}
@@ -0,0 +1,303 @@
+use std::io;
+
+use proptest::prelude::*;
+
+use crate::display::Display;
+use crate::hardware::{CondtionalFlags, Registers};
+use crate::image::load_image;
+use crate::jit::{Jit, DEFAULT_THRESHOLD};
+use crate::keyboard::Keyboard;
+use crate::operations::sign_extension;
+use crate::vm::{Fault, VM};
+
+/// Instructions an arbitrary image may run before it counts as still running.
+const STEP_LIMIT: u64 = 5_000;
+
+const PC: usize = Registers::R_PC as usize;
+const COND: usize = Registers::R_COND as usize;
+
+fn quiet_vm(predecode: bool) -> VM
+{
+    let mut vm = VM::new();
+    vm.set_predecode(predecode);
+    vm.set_display(Display::captured_only());
+    vm.set_keyboard(Keyboard::stream(io::empty()));
+    vm
+}
+
+/// A VM about to execute `instruction` at `pc` with `registers` in R0-R7.
+fn vm_at(pc: u16, instruction: u16, registers: [u16; 8]) -> VM
+{
+    let mut vm = quiet_vm(true);
+    for (i, &value) in registers.iter().enumerate()
+    {
+        vm.register_write(i, value);
+    }
+    vm.register_write(PC, pc);
+    vm.register_write(COND, CondtionalFlags::FL_ZRO as u16);
+    vm.memory_write(pc, instruction);
+    vm
+}
+
+/// The flags `value` should leave behind, exactly one of N, Z and P.
+fn cond_of(value: u16) -> u16
+{
+    if value == 0
+    {
+        CondtionalFlags::FL_ZRO as u16
+    }
+    else if value & 0x8000 != 0
+    {
+        CondtionalFlags::FL_NEG as u16
+    }
+    else
+    {
+        CondtionalFlags::FL_POS as u16
+    }
+}
+
+/// PCs near both ends of memory, where PC+1 and PC-relative targets wrap.
+/// The device page is left out so the instruction itself is plain memory.
+fn edge_pc() -> impl Strategy<Value = u16>
+{
+    prop_oneof![0x0000u16..=0x0100, 0xFF00u16..=0xFFFF]
+}
+
+fn register() -> impl Strategy<Value = u16>
+{
+    0u16..8
+}
+
+proptest! {
+    #[test]
+    fn test_sign_extension_keeps_field_and_copies_sign(value in any::<u16>(), width in 1u8..=16) {
+        let extended = sign_extension(value, width);
+        let mask = ((1u32 << width) - 1) as u16;
+        prop_assert_eq!(extended & mask, value & mask);
+        let sign = value >> (width - 1) & 1;
+        prop_assert_eq!(extended & !mask, if sign == 1 { !mask } else { 0 });
+        // Bits above the field make no difference.
+        prop_assert_eq!(sign_extension(value & mask, width), extended);
+        let limit = 1i32 << (width - 1);
+        prop_assert!((-limit..limit).contains(&(extended as i16 as i32)));
+    }
+
+    #[test]
+    fn test_add_register(registers in any::<[u16; 8]>(), dr in register(), sr1 in register(), sr2 in register(), pc in 0u16..0xFE00) {
+        let mut vm = vm_at(pc, 0x1000 | dr << 9 | sr1 << 6 | sr2, registers);
+        vm.step();
+        let expected = registers[sr1 as usize].wrapping_add(registers[sr2 as usize]);
+        prop_assert_eq!(vm.register_read(dr as usize), expected);
+        prop_assert_eq!(vm.register_read(COND), cond_of(expected));
+        prop_assert_eq!(vm.register_read(PC), pc + 1);
+    }
+
+    #[test]
+    fn test_add_immediate(registers in any::<[u16; 8]>(), dr in register(), sr1 in register(), imm in 0u16..32) {
+        let mut vm = vm_at(0x3000, 0x1020 | dr << 9 | sr1 << 6 | imm, registers);
+        vm.step();
+        let expected = registers[sr1 as usize].wrapping_add(sign_extension(imm, 5));
+        prop_assert_eq!(vm.register_read(dr as usize), expected);
+        prop_assert_eq!(vm.register_read(COND), cond_of(expected));
+    }
+
+    #[test]
+    fn test_and_register(registers in any::<[u16; 8]>(), dr in register(), sr1 in register(), sr2 in register()) {
+        let mut vm = vm_at(0x3000, 0x5000 | dr << 9 | sr1 << 6 | sr2, registers);
+        vm.step();
+        let expected = registers[sr1 as usize] & registers[sr2 as usize];
+        prop_assert_eq!(vm.register_read(dr as usize), expected);
+        prop_assert_eq!(vm.register_read(COND), cond_of(expected));
+    }
+
+    #[test]
+    fn test_and_immediate(registers in any::<[u16; 8]>(), dr in register(), sr1 in register(), imm in 0u16..32) {
+        let mut vm = vm_at(0x3000, 0x5020 | dr << 9 | sr1 << 6 | imm, registers);
+        vm.step();
+        let expected = registers[sr1 as usize] & sign_extension(imm, 5);
+        prop_assert_eq!(vm.register_read(dr as usize), expected);
+        prop_assert_eq!(vm.register_read(COND), cond_of(expected));
+    }
+
+    #[test]
+    fn test_not(registers in any::<[u16; 8]>(), dr in register(), sr in register()) {
+        let mut vm = vm_at(0x3000, 0x903F | dr << 9 | sr << 6, registers);
+        vm.step();
+        let expected = !registers[sr as usize];
+        prop_assert_eq!(vm.register_read(dr as usize), expected);
+        prop_assert_eq!(vm.register_read(COND), cond_of(expected));
+        // NOT twice is the identity.
+        let mut vm = vm_at(0x3000, 0x903F | dr << 9 | dr << 6, registers);
+        vm.memory_write(0x3001, 0x903F | dr << 9 | dr << 6);
+        vm.step();
+        vm.step();
+        prop_assert_eq!(vm.register_read(dr as usize), registers[dr as usize]);
+    }
+
+    /// Every instruction that sets the condition codes leaves exactly the
+    /// flag matching the value it wrote to DR.
+    #[test]
+    fn test_flags_follow_destination(registers in any::<[u16; 8]>(), opcode in prop::sample::select(vec![0x1u16, 0x2, 0x5, 0x6, 0x9, 0xA, 0xE]), operands in 0u16..0x1000) {
+        let instruction = opcode << 12 | operands;
+        let mut vm = vm_at(0x3000, instruction, registers);
+        vm.step();
+        let dr = (instruction >> 9 & 7) as usize;
+        let cond = vm.register_read(COND);
+        prop_assert_eq!(cond.count_ones(), 1);
+        prop_assert_eq!(cond, cond_of(vm.register_read(dr)));
+    }
+
+    #[test]
+    fn test_br_wraps_around_memory(pc in edge_pc(), offset in 0u16..0x200, nzp in 1u16..8) {
+        let mut vm = vm_at(pc, nzp << 9 | offset, [0; 8]);
+        let cond = vm.register_read(COND);
+        vm.step();
+        let next = pc.wrapping_add(1);
+        let expected = if nzp & cond != 0 { next.wrapping_add(sign_extension(offset, 9)) } else { next };
+        prop_assert_eq!(vm.register_read(PC), expected);
+    }
+
+    #[test]
+    fn test_lea_wraps_around_memory(pc in edge_pc(), dr in register(), offset in 0u16..0x200) {
+        let mut vm = vm_at(pc, 0xE000 | dr << 9 | offset, [0; 8]);
+        vm.step();
+        prop_assert_eq!(vm.register_read(dr as usize), pc.wrapping_add(1).wrapping_add(sign_extension(offset, 9)));
+    }
+
+    #[test]
+    fn test_jsr_wraps_around_memory(pc in edge_pc(), offset in 0u16..0x800) {
+        let mut vm = vm_at(pc, 0x4800 | offset, [0; 8]);
+        vm.step();
+        prop_assert_eq!(vm.register_read(PC), pc.wrapping_add(1).wrapping_add(sign_extension(offset, 11)));
+        prop_assert_eq!(vm.register_read(7), pc.wrapping_add(1));
+    }
+
+    #[test]
+    fn test_ld_and_st_wrap_around_memory(pc in edge_pc(), dr in register(), offset in 0u16..0x200, value in any::<u16>()) {
+        let address = pc.wrapping_add(1).wrapping_add(sign_extension(offset, 9));
+        // Device registers and the instruction itself do not hold what was stored.
+        prop_assume!(address != pc && !(0xFE00..0xFE08).contains(&address));
+        let mut registers = [0; 8];
+        registers[dr as usize] = value;
+        let mut vm = vm_at(pc, 0x3000 | dr << 9 | offset, registers);
+        vm.step();
+        prop_assert_eq!(vm.memory_peek(address), value);
+        vm.register_write(dr as usize, !value);
+        vm.register_write(PC, pc);
+        vm.memory_write(pc, 0x2000 | dr << 9 | offset);
+        vm.step();
+        prop_assert_eq!(vm.register_read(dr as usize), value);
+    }
+
+    #[test]
+    fn test_ldr_and_str_wrap_around_memory(base in any::<u16>(), sr in 1u16..8, offset in 0u16..0x40, value in any::<u16>()) {
+        let address = base.wrapping_add(sign_extension(offset, 6));
+        prop_assume!(address != 0x3000 && !(0xFE00..0xFE08).contains(&address));
+        let mut registers = [0; 8];
+        registers[0] = base;
+        registers[sr as usize] = value;
+        let mut vm = vm_at(0x3000, 0x7000 | sr << 9 | offset, registers);
+        vm.step();
+        prop_assert_eq!(vm.memory_peek(address), value);
+        vm.register_write(PC, 0x3000);
+        vm.memory_write(0x3000, 0x6000 | sr << 9 | offset);
+        vm.register_write(sr as usize, !value);
+        vm.step();
+        prop_assert_eq!(vm.register_read(sr as usize), value);
+    }
+
+    #[test]
+    fn test_ldi_and_sti_wrap_around_memory(pc in edge_pc(), dr in register(), offset in 0u16..0x200, target in any::<u16>(), value in any::<u16>()) {
+        let pointer = pc.wrapping_add(1).wrapping_add(sign_extension(offset, 9));
+        prop_assume!(pointer != pc && !(0xFE00..0xFE08).contains(&pointer));
+        prop_assume!(target != pc && target != pointer && !(0xFE00..0xFE08).contains(&target));
+        let mut registers = [0; 8];
+        registers[dr as usize] = value;
+        let mut vm = vm_at(pc, 0xB000 | dr << 9 | offset, registers);
+        vm.memory_write(pointer, target);
+        vm.step();
+        prop_assert_eq!(vm.memory_peek(target), value);
+        prop_assert_eq!(vm.memory_peek(pointer), target);
+        vm.register_write(dr as usize, !value);
+        vm.register_write(PC, pc);
+        vm.memory_write(pc, 0xA000 | dr << 9 | offset);
+        vm.step();
+        prop_assert_eq!(vm.register_read(dr as usize), value);
+        prop_assert_eq!(vm.register_read(COND), cond_of(value));
+    }
+
+    #[test]
+    fn test_jmp_goes_to_base_register(registers in any::<[u16; 8]>(), base in register(), pc in 0u16..0xFE00) {
+        let mut vm = vm_at(pc, 0xC000 | base << 6, registers);
+        vm.step();
+        prop_assert_eq!(vm.register_read(PC), registers[base as usize]);
+        prop_assert_eq!(vm.register_read(COND), CondtionalFlags::FL_ZRO as u16);
+    }
+
+    /// JSRR reads the base register before writing R7, so JSRR R7 jumps to
+    /// the old R7.
+    #[test]
+    fn test_jsrr_links_and_goes_to_base_register(registers in any::<[u16; 8]>(), base in register(), pc in 0u16..0xFE00) {
+        let mut vm = vm_at(pc, 0x4000 | base << 6, registers);
+        vm.step();
+        prop_assert_eq!(vm.register_read(PC), registers[base as usize]);
+        prop_assert_eq!(vm.register_read(7), pc + 1);
+    }
+
+    /// Any single instruction either completes, halts or stops with the
+    /// fault that belongs to it, never panics. RTI is fine in the supervisor
+    /// mode the VM starts in.
+    #[test]
+    fn test_any_instruction_stops_only_with_a_fault(instruction in any::<u16>(), registers in any::<[u16; 8]>(), pc in 0u16..0xFE00) {
+        let mut vm = vm_at(pc, instruction, registers);
+        vm.step();
+        let expected = match instruction >> 12 {
+            0xD => Some(Fault::IllegalOpcode { pc, instruction }),
+            0xF if !(0x20..=0x25).contains(&(instruction & 0xFF)) => Some(Fault::UnknownTrap { pc, vector: instruction as u8 }),
+            _ => None,
+        };
+        match vm.fault() {
+            Some(Fault::UnterminatedString { .. }) => prop_assert!(matches!(instruction & 0xF0FF, 0xF022 | 0xF024)),
+            fault => prop_assert_eq!(fault, expected),
+        }
+        let halted = instruction & 0xF0FF == 0xF025;
+        prop_assert_eq!(vm.state_read(), !halted && vm.fault().is_none());
+    }
+
+    /// Arbitrary bytes loaded as an image and run from their origin end in
+    /// HALT, a fault or the step limit, with or without the JIT.
+    #[test]
+    fn test_arbitrary_images_never_panic(bytes in prop::collection::vec(any::<u8>(), 0..600), predecode in any::<bool>(), jit in any::<bool>()) {
+        let mut vm = quiet_vm(predecode);
+        if jit {
+            vm.set_jit(Jit::new(DEFAULT_THRESHOLD));
+        }
+        let origin = match load_image(&bytes, &mut vm) {
+            Ok((origin, _)) => origin,
+            Err(_) => {
+                prop_assert!(bytes.len() < 2);
+                return Ok(());
+            }
+        };
+        vm.register_write(PC, origin);
+        while vm.state_read() && vm.steps() < STEP_LIMIT {
+            vm.run_block();
+        }
+        if !vm.state_read() && vm.fault().is_none() {
+            let pc = vm.register_read(PC);
+            prop_assert_eq!(vm.memory_peek(pc.wrapping_sub(1)) & 0xF0FF, 0xF025);
+        }
+    }
+}
+
+#[test]
+fn test_full_memory_image_loads_every_word()
+{
+    let words: Vec<u16> = (0..=u16::MAX).map(|i| i ^ 0x5A5A).collect();
+    let mut bytes = vec![0x30, 0x00];
+    bytes.extend(words.iter().flat_map(|word| word.to_be_bytes()));
+    let mut vm = quiet_vm(true);
+    assert_eq!(load_image(&bytes, &mut vm), Ok((0x3000, words.len())));
+    assert_eq!(vm.memory_peek(0x2FFF), 0xFFFF ^ 0x5A5A);
+    assert_eq!(vm.memory_peek(0x3000), 0x5A5A);
+}
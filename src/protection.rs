@@ -0,0 +1,59 @@
+use std::fmt;
+
+/// What a protected region forbids.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Protection
+{
+    /// ST, STI and STR may not write here, e.g. code or the trap vector table.
+    ReadOnly,
+    /// The PC may not reach here, e.g. data from .BLKW, .FILL or .STRINGZ.
+    NoExecute,
+}
+
+impl fmt::Display for Protection
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            Protection::ReadOnly => write!(f, "read-only"),
+            Protection::NoExecute => write!(f, "not executable"),
+        }
+    }
+}
+
+/// Addresses `first` to `last` with `protection`, named after the image or
+/// option that set it up for diagnostics.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Region
+{
+    pub first: u16,
+    pub last: u16,
+    pub protection: Protection,
+    pub name: String,
+}
+
+impl Region
+{
+    pub fn contains(&self, address: u16) -> bool
+    {
+        (self.first..=self.last).contains(&address)
+    }
+}
+
+impl fmt::Display for Region
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{} x{:04X}-x{:04X}, {}", self.name, self.first, self.last, self.protection)
+    }
+}
+
+/// The region of `regions` with `protection` that holds `address`.
+pub fn find(regions: &[Region], address: u16, protection: Protection) -> Option<&Region>
+{
+    regions.iter().find(|region| region.protection == protection && region.contains(address))
+}
+
+#[cfg(test)]
+mod tests;
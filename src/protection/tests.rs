@@ -0,0 +1,118 @@
+use super::*;
+
+use crate::display::Display;
+use crate::hardware::Registers;
+use crate::vm::{Fault, VM};
+
+/// Code at x3000 followed by data, with the data protected from execution
+/// and the code from writes.
+fn protected_vm(code: &[u16], data: &[u16]) -> VM {
+    let mut vm = VM::new();
+    vm.set_display(Display::captured_only());
+    for (i, &word) in code.iter().chain(data).enumerate() {
+        vm.memory_write(0x3000 + i as u16, word);
+    }
+    let data_start = 0x3000 + code.len() as u16;
+    vm.protect(0x3000, data_start - 1, Protection::ReadOnly, "code");
+    if !data.is_empty() {
+        vm.protect(data_start, data_start + data.len() as u16 - 1, Protection::NoExecute, "data");
+    }
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    vm
+}
+
+fn run(vm: &mut VM) {
+    while vm.state_read() && vm.steps() < 1000 {
+        vm.step();
+    }
+}
+
+#[test]
+fn test_find() {
+    let regions = [
+        Region { first: 0x3000, last: 0x30FF, protection: Protection::ReadOnly, name: "a".to_string() },
+        Region { first: 0x3080, last: 0x3080, protection: Protection::NoExecute, name: "b".to_string() },
+    ];
+    assert_eq!(find(&regions, 0x3080, Protection::ReadOnly).unwrap().name, "a");
+    assert_eq!(find(&regions, 0x3080, Protection::NoExecute).unwrap().name, "b");
+    assert!(find(&regions, 0x3081, Protection::NoExecute).is_none());
+    assert!(find(&regions, 0x3100, Protection::ReadOnly).is_none());
+    assert_eq!(regions[0].to_string(), "a x3000-x30FF, read-only");
+}
+
+#[test]
+fn test_stores_into_read_only_memory_are_caught() {
+    // ST R0, x3000 overwrites its own code
+    let mut vm = protected_vm(&[0x31FF, 0xF025], &[]);
+    run(&mut vm);
+    assert_eq!(vm.fault(), Some(Fault::WriteProtected { pc: 0x3000, instruction: 0x31FF, address: 0x3000 }));
+    assert_eq!(vm.memory_peek(0x3000), 0x31FF);
+    assert_eq!(vm.fault().unwrap().to_string(), "write to read-only memory at x3000 (ST R0, x3000): x3000 is read-only");
+    assert_eq!(vm.fault_region().unwrap().name, "code");
+
+    // STR R0, R1, #0 and STI R0, x3002 through a pointer into the code
+    let mut vm = protected_vm(&[0x7040, 0xF025], &[]);
+    vm.register_write(1, 0x3001);
+    run(&mut vm);
+    assert_eq!(vm.fault(), Some(Fault::WriteProtected { pc: 0x3000, instruction: 0x7040, address: 0x3001 }));
+    let mut vm = protected_vm(&[0xB001, 0xF025], &[0x3001]);
+    run(&mut vm);
+    assert_eq!(vm.fault(), Some(Fault::WriteProtected { pc: 0x3000, instruction: 0xB001, address: 0x3001 }));
+}
+
+#[test]
+fn test_stores_into_data_are_fine() {
+    // STI R0, x3003 through a pointer into the data, then LD R1, x3003
+    let mut vm = protected_vm(&[0xB002, 0x2201, 0xF025], &[0x3004, 0x0000]);
+    vm.register_write(0, 0x1234);
+    run(&mut vm);
+    assert_eq!(vm.fault(), None);
+    assert_eq!(vm.memory_peek(0x3004), 0x1234);
+    assert_eq!(vm.register_read(1), 0x3004);
+}
+
+#[test]
+fn test_running_into_data_is_caught() {
+    // Falls through into its data
+    let mut vm = protected_vm(&[0x1021], &[0x0041]);
+    run(&mut vm);
+    assert_eq!(vm.fault(), Some(Fault::ExecuteProtected { pc: 0x3001, from: Some(0x3000) }));
+    assert_eq!(vm.fault().unwrap().to_string(), "execution reached non-executable x3001 from x3000");
+    assert_eq!(vm.fault_region().unwrap().to_string(), "data x3001-x3001, not executable");
+
+    // JMP R1 into the data
+    let mut vm = protected_vm(&[0xC040, 0xF025], &[0, 0]);
+    vm.register_write(1, 0x3003);
+    run(&mut vm);
+    assert_eq!(vm.fault(), Some(Fault::ExecuteProtected { pc: 0x3003, from: Some(0x3000) }));
+    assert_eq!(vm.steps(), 1);
+
+    let mut vm = protected_vm(&[0xF025], &[0]);
+    vm.register_write(Registers::R_PC.into(), 0x3001);
+    run(&mut vm);
+    assert_eq!(vm.fault(), Some(Fault::ExecuteProtected { pc: 0x3001, from: None }));
+}
+
+#[test]
+fn test_trap_vector_table_can_be_read_only() {
+    let mut vm = protected_vm(&[0xF020, 0xF025], &[]);
+    vm.set_keyboard(crate::keyboard::Keyboard::stream(&b"k"[..]));
+    vm.register_write(Registers::R_R7.into(), 0);
+    vm.protect(0x0000, 0x00FF, Protection::ReadOnly, "vectors");
+    run(&mut vm);
+    assert_eq!(vm.fault(), None);
+    assert_eq!(vm.register_read(0), b'k' as u16);
+    assert_eq!(vm.regions().len(), 2);
+}
+
+#[test]
+fn test_range_wrapping_past_xffff_is_split() {
+    let mut vm = VM::new();
+    vm.protect(0xFFFE, 0x0001, Protection::ReadOnly, "wraps");
+    let spans: Vec<(u16, u16)> = vm.regions().iter().map(|region| (region.first, region.last)).collect();
+    assert_eq!(spans, [(0xFFFE, 0xFFFF), (0x0000, 0x0001)]);
+    for address in [0xFFFE, 0xFFFF, 0x0000, 0x0001] {
+        assert!(find(vm.regions(), address, Protection::ReadOnly).is_some());
+    }
+    assert!(find(vm.regions(), 0x0002, Protection::ReadOnly).is_none());
+}
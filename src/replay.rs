@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+
+use crate::keyboard::Keyboard;
+
+/// One answer the keyboard gave the program.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputEvent
+{
+    /// KBSR reported a key at this step. Polls that found no key are not
+    /// logged: replay answers "not ready" whenever no event matches.
+    Ready(u64),
+    /// A byte handed to KBDR or GETC/IN, `None` for end of input.
+    Byte(Option<u8>),
+}
+
+/// Recorded session, one event per line:
+///
+/// ```text
+/// # lc3box input log v1
+/// ready 1520
+/// byte x41
+/// byte eof
+/// ```
+#[derive(Default)]
+pub struct InputLog
+{
+    events: VecDeque<InputEvent>,
+    /// Step of the recorded poll this run failed to match. Once set the log
+    /// answers "no key" and EOF; the VM stops with `Fault::ReplayDiverged`.
+    diverged: Option<u64>,
+}
+
+pub const HEADER: &str = "# lc3box input log v1";
+
+fn format_event(event: InputEvent) -> String
+{
+    match event
+    {
+        InputEvent::Ready(step) => format!("ready {step}"),
+        InputEvent::Byte(Some(byte)) => format!("byte x{byte:02X}"),
+        InputEvent::Byte(None) => "byte eof".to_string(),
+    }
+}
+
+impl InputLog
+{
+    pub fn load(path: &str) -> Result<Self, String>
+    {
+        let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        Self::parse(&text).map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String>
+    {
+        let mut events = VecDeque::new();
+        for (number, line) in text.lines().enumerate()
+        {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty()
+            {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {message}", number + 1);
+            let event = match line.split_once(' ')
+            {
+                Some(("ready", step)) => InputEvent::Ready(step.trim().parse().map_err(|_| error("invalid step"))?),
+                Some(("byte", "eof")) => InputEvent::Byte(None),
+                Some(("byte", byte)) =>
+                {
+                    let digits = byte.trim().strip_prefix('x').ok_or_else(|| error("expected a byte like x41"))?;
+                    InputEvent::Byte(Some(u8::from_str_radix(digits, 16).map_err(|_| error("invalid byte"))?))
+                }
+                _ => return Err(error("expected `ready <step>` or `byte <xHH|eof>`")),
+            };
+            events.push_back(event);
+        }
+        Ok(Self { events, diverged: None })
+    }
+
+    /// Events not replayed yet.
+    pub fn remaining(&self) -> usize
+    {
+        self.events.len()
+    }
+
+    /// Step of the recorded key poll this run went past without making, or
+    /// read a byte before.
+    pub fn diverged(&self) -> Option<u64>
+    {
+        self.diverged
+    }
+
+    /// Whether the recorded KBSR saw a key at `step`.
+    pub fn key_ready(&mut self, step: u64) -> bool
+    {
+        if self.diverged.is_some()
+        {
+            return false;
+        }
+        match self.events.front()
+        {
+            Some(&InputEvent::Ready(at)) if at == step =>
+            {
+                self.events.pop_front();
+                true
+            }
+            Some(&InputEvent::Ready(at)) if at < step =>
+            {
+                self.diverged = Some(at);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    pub fn read_byte(&mut self) -> Option<u8>
+    {
+        if self.diverged.is_some()
+        {
+            return None;
+        }
+        match self.events.front()
+        {
+            Some(&InputEvent::Byte(byte)) =>
+            {
+                self.events.pop_front();
+                byte
+            }
+            Some(&InputEvent::Ready(at)) =>
+            {
+                self.diverged = Some(at);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+/// Wraps the real keyboard and logs every answer it gives.
+pub struct Recorder
+{
+    inner: Box<Keyboard>,
+    out: Box<dyn Write>,
+}
+
+impl Recorder
+{
+    pub fn new(inner: Keyboard, mut out: Box<dyn Write>) -> Self
+    {
+        let _ = writeln!(out, "{HEADER}");
+        Self { inner: Box::new(inner), out }
+    }
+
+    pub fn inner(&mut self) -> &mut Keyboard
+    {
+        &mut self.inner
+    }
+
+    fn log(&mut self, event: InputEvent)
+    {
+        let _ = writeln!(self.out, "{}", format_event(event));
+        let _ = self.out.flush();
+    }
+
+    pub fn key_ready(&mut self, step: u64) -> bool
+    {
+        let ready = self.inner.key_ready(step);
+        if ready
+        {
+            self.log(InputEvent::Ready(step));
+        }
+        ready
+    }
+
+    pub fn read_byte(&mut self) -> Option<u8>
+    {
+        let byte = self.inner.read_byte();
+        self.log(InputEvent::Byte(byte));
+        byte
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,112 @@
+use super::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::display::Display;
+use crate::hardware::Registers;
+use crate::input_script::InputScript;
+use crate::vm::{Fault, VM};
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Polls KBSR until a key arrives, reads it from KBDR, then reads one more with GETC.
+const PROGRAM: [u16; 7] = [
+    0xA004, // LDI R0, KBSR
+    0x07FE, // BRzp x3000
+    0xA203, // LDI R1, KBDR
+    0xF020, // GETC
+    0xF025, // HALT
+    0xFE00,
+    0xFE02,
+];
+
+fn run(keyboard: Keyboard) -> VM {
+    let mut vm = VM::new();
+    vm.set_display(Display::captured_only());
+    vm.set_keyboard(keyboard);
+    for (i, &word) in PROGRAM.iter().enumerate() {
+        vm.memory_write(0x3000 + i as u16, word);
+    }
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    while vm.state_read() {
+        vm.step();
+    }
+    vm
+}
+
+#[test]
+fn test_replay_reproduces_recorded_session() {
+    let log = SharedBuffer::default();
+    let script = InputScript::parse("7 \"a\"\n9 \"b\"").unwrap();
+    let recorded = run(Keyboard::Record(Recorder::new(Keyboard::Script(script), Box::new(log.clone()))));
+    let text = String::from_utf8(log.0.borrow().clone()).unwrap();
+    assert_eq!(text, "# lc3box input log v1\nready 8\nbyte x61\nbyte x62\n");
+
+    let mut replayed = run(Keyboard::Replay(InputLog::parse(&text).unwrap()));
+    assert!(replayed.arch_state() == recorded.arch_state());
+    assert_eq!(replayed.steps(), recorded.steps());
+    assert_eq!((replayed.register_read(1), replayed.register_read(0)), (b'a' as u16, b'b' as u16));
+}
+
+#[test]
+fn test_end_of_input_is_recorded() {
+    let log = SharedBuffer::default();
+    let script = InputScript::parse("0 \"a\"").unwrap();
+    run(Keyboard::Record(Recorder::new(Keyboard::Script(script), Box::new(log.clone()))));
+    let text = String::from_utf8(log.0.borrow().clone()).unwrap();
+    assert!(text.ends_with("byte eof\n"));
+
+    let mut replay = InputLog::parse(&text).unwrap();
+    assert!(replay.key_ready(0));
+    assert_eq!((replay.read_byte(), replay.read_byte(), replay.remaining()), (Some(b'a'), None, 0));
+}
+
+#[test]
+fn test_replay_only_answers_at_the_recorded_step() {
+    let mut log = InputLog::parse("ready 5\nbyte x41").unwrap();
+    assert!(!log.key_ready(4));
+    assert!(log.key_ready(5));
+    assert_eq!(log.read_byte(), Some(b'A'));
+    assert!(!log.key_ready(6));
+}
+
+#[test]
+fn test_divergence_is_reported() {
+    let mut log = InputLog::parse("ready 5\nbyte x41").unwrap();
+    assert!(!log.key_ready(9));
+    assert_eq!(log.diverged(), Some(5));
+    // Nothing more is handed out once the run has left the recording
+    assert!(!log.key_ready(5));
+    assert_eq!(log.read_byte(), None);
+
+    let mut log = InputLog::parse("ready 5\nbyte x41").unwrap();
+    assert_eq!(log.read_byte(), None);
+    assert_eq!((log.diverged(), log.remaining()), (Some(5), 2));
+}
+
+#[test]
+fn test_divergence_stops_the_vm_with_a_fault() {
+    // The program polls KBSR at steps 0, 2, 4, ... so it never polls at step 1
+    let vm = run(Keyboard::Replay(InputLog::parse("ready 1\nbyte x61").unwrap()));
+    assert_eq!(vm.fault(), Some(Fault::ReplayDiverged { step: 2, recorded: 1 }));
+    assert_eq!(vm.fault().unwrap().to_string(), "input replay diverged at step 2: the recording polled the keyboard at step 1");
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!(InputLog::parse("ready soon").err().unwrap(), "line 1: invalid step");
+    assert_eq!(InputLog::parse("\nbyte 41").err().unwrap(), "line 2: expected a byte like x41");
+    assert!(InputLog::parse("key x41").is_err());
+}
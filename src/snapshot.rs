@@ -0,0 +1,382 @@
+use std::fs;
+use std::io::{self, Cursor, Read};
+
+use crate::disk::DiskState;
+use crate::hardware::{Registers, MEMORY_MAX};
+use crate::input_script::InputScript;
+use crate::keyboard::Keyboard;
+use crate::protection::{Protection, Region};
+use crate::vm::{ArchState, VM};
+
+/// Checkpoint of a VM that can be written to disk and resumed later.
+///
+/// File format, version 4. All integers are little-endian.
+///
+/// | offset | size       | field                                               |
+/// |--------|------------|-----------------------------------------------------|
+/// | 0      | 8          | magic `LC3SNAP\0`                                   |
+/// | 8      | 2          | format version                                      |
+/// | 10     | 2          | flags: bit 0 halted, bit 1 input is scripted, bit 2 input reached EOF, bit 3 disk attached, bit 4 disk busy, bit 5 disk interrupt requested, bit 6 vectored traps, bit 7 ROM present |
+/// | 12     | 20         | R0-R7, PC, PSR                                      |
+/// | 32     | 4          | saved supervisor and user stack pointers            |
+/// | 36     | 8          | instructions executed                               |
+/// | 44     | 8          | cycles                                              |
+/// | 52     | 12         | disk status, sector, address, command (2 each) and instructions left (4) |
+/// | 64     | 4          | first and last address of the ROM                   |
+/// | 68     | 4          | number of buffered keys, n                          |
+/// | 72     | 9 n        | buffered keys: step (8), byte (1)                   |
+/// | 72+9n  | 2          | number of protected regions, r                      |
+/// | 74+9n  | r regions  | first (2), last (2), 0 read-only or 1 no-execute (1), name length (2), UTF-8 name |
+/// | ...    | 131072     | memory x0000-xFFFF, including the device registers  |
+/// | end-4  | 4          | CRC-32 (IEEE) of everything before it               |
+///
+/// The PSR holds the privilege in bit 15, the priority in bits 10:8 and the
+/// condition codes in bits 2:0. The disk fields are zero without a disk; the
+/// disk image itself is not part of a snapshot, so a run that had one must be
+/// resumed with the same image attached. The ROM and vectored traps keep a
+/// booted machine booted, see `boot::boot`. Version 1 had no stack pointers or
+/// disk state, version 2 no ROM or vectored traps, version 3 no protected
+/// regions.
+///
+/// Buffered keys are input the VM has accepted but the program has not read
+/// yet: the rest of an input script, a byte read ahead from stdin, or keys
+/// typed into the TUI.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Snapshot
+{
+    pub halted: bool,
+    pub registers: [u16; Registers::R_COUNT as usize],
+    /// (supervisor, user) stack pointers saved on the last mode switch.
+    pub saved_stack_pointers: (u16, u16),
+    pub steps: u64,
+    pub cycles: u64,
+    pub scripted: bool,
+    pub eof: bool,
+    pub keys: Vec<(u64, u8)>,
+    pub disk: Option<DiskState>,
+    /// TRAPs go through the vector table, see `VM::set_vectored_traps`.
+    pub vectored_traps: bool,
+    /// First and last address of the read-only ROM.
+    pub rom: Option<(u16, u16)>,
+    /// See `VM::protect`.
+    pub regions: Vec<Region>,
+    pub memory: Box<[u16]>,
+}
+
+pub const MAGIC: &[u8; 8] = b"LC3SNAP\0";
+pub const VERSION: u16 = 4;
+
+const HALTED: u16 = 1;
+const SCRIPTED: u16 = 1 << 1;
+const EOF: u16 = 1 << 2;
+const DISK: u16 = 1 << 3;
+const DISK_BUSY: u16 = 1 << 4;
+const DISK_REQUESTED: u16 = 1 << 5;
+const VECTORED_TRAPS: u16 = 1 << 6;
+const ROM: u16 = 1 << 7;
+
+/// CRC-32 with the IEEE polynomial, as used by zip and PNG.
+fn crc32(bytes: &[u8]) -> u32
+{
+    let mut crc = !0u32;
+    for &byte in bytes
+    {
+        crc ^= byte as u32;
+        for _ in 0..8
+        {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Little-endian reader over the snapshot body.
+struct Fields<'a>
+{
+    bytes: &'a [u8],
+}
+
+impl Fields<'_>
+{
+    fn take(&mut self, count: usize) -> Result<&[u8], String>
+    {
+        if self.bytes.len() < count
+        {
+            return Err("snapshot is truncated".to_string());
+        }
+        let (field, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(field)
+    }
+
+    fn u8(&mut self) -> Result<u8, String>
+    {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String>
+    {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String>
+    {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String>
+    {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+/// (scripted, reached EOF, keys) of the input the keyboard holds.
+fn buffered_input(keyboard: &mut Keyboard) -> (bool, bool, Vec<(u64, u8)>)
+{
+    match keyboard
+    {
+        Keyboard::Terminal | Keyboard::Replay(_) => (false, false, Vec::new()),
+        Keyboard::Stream { pending, eof, .. } => (false, *eof, pending.iter().map(|&byte| (0, byte)).collect()),
+        Keyboard::Script(script) => (true, false, script.remaining().collect()),
+        Keyboard::Queue(keys) => (false, false, keys.iter().map(|&byte| (0, byte)).collect()),
+        Keyboard::Record(recorder) => buffered_input(recorder.inner()),
+    }
+}
+
+impl Snapshot
+{
+    pub fn capture(vm: &mut VM) -> Self
+    {
+        let ArchState { mut registers, memory } = vm.arch_state();
+        registers[Registers::R_COND as usize] = vm.psr();
+        let (scripted, eof, keys) = buffered_input(vm.keyboard());
+        let disk = vm.disk().map(|disk| disk.state());
+        Self
+        {
+            halted: !vm.state_read(),
+            registers,
+            saved_stack_pointers: vm.saved_stack_pointers(),
+            steps: vm.steps(),
+            cycles: vm.cycles(),
+            scripted,
+            eof,
+            keys,
+            disk,
+            vectored_traps: vm.vectored_traps(),
+            rom: vm.rom(),
+            regions: vm.regions().to_vec(),
+            memory,
+        }
+    }
+
+    /// Puts registers, memory, counters, the disk registers, the ROM, the
+    /// trap mode and the protected regions back. The VM is left running even if the snapshot was taken
+    /// at HALT, so execution continues after it. A snapshot taken with a disk
+    /// needs one attached.
+    pub fn restore(&self, vm: &mut VM) -> Result<(), String>
+    {
+        if let Some(state) = self.disk
+        {
+            let disk = vm.disk().ok_or("the snapshot was taken with a disk attached, resume it with the same --disk")?;
+            disk.set_state(state);
+        }
+        vm.set_arch_state(&ArchState { registers: self.registers, memory: self.memory.clone() });
+        vm.set_psr(self.registers[Registers::R_COND as usize]);
+        let (ssp, usp) = self.saved_stack_pointers;
+        vm.set_saved_stack_pointers(ssp, usp);
+        vm.set_counters(self.steps, self.cycles);
+        vm.set_vectored_traps(self.vectored_traps);
+        if let Some((first, last)) = self.rom
+        {
+            vm.set_rom(first, &self.memory[first as usize..=last as usize]);
+        }
+        for region in &self.regions
+        {
+            vm.protect(region.first, region.last, region.protection, &region.name);
+        }
+        if !vm.state_read()
+        {
+            vm.state_change();
+        }
+        Ok(())
+    }
+
+    /// Keyboard that delivers the buffered keys before `fallback`, the input
+    /// source chosen for the resumed run. A scripted snapshot continues its
+    /// script unless a new script was given.
+    pub fn keyboard(&self, fallback: Keyboard) -> Keyboard
+    {
+        let bytes: Vec<u8> = self.keys.iter().map(|&(_, byte)| byte).collect();
+        match fallback
+        {
+            Keyboard::Script(script) => Keyboard::Script(script),
+            _ if self.scripted => Keyboard::Script(InputScript::from_keys(self.keys.iter().copied())),
+            Keyboard::Stream { .. } if self.eof => Keyboard::stream(Cursor::new(bytes)),
+            Keyboard::Stream { .. } if !bytes.is_empty() => Keyboard::stream(Cursor::new(bytes).chain(io::stdin())),
+            Keyboard::Queue(mut keys) =>
+            {
+                keys.extend(bytes);
+                Keyboard::Queue(keys)
+            }
+            fallback => fallback,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8>
+    {
+        let mut out = MAGIC.to_vec();
+        out.extend(VERSION.to_le_bytes());
+        let disk = self.disk.unwrap_or_default();
+        let flags = (self.halted as u16 * HALTED)
+            | (self.scripted as u16 * SCRIPTED)
+            | (self.eof as u16 * EOF)
+            | (self.disk.is_some() as u16 * DISK)
+            | (disk.busy.is_some() as u16 * DISK_BUSY)
+            | (disk.requested as u16 * DISK_REQUESTED)
+            | (self.vectored_traps as u16 * VECTORED_TRAPS)
+            | (self.rom.is_some() as u16 * ROM);
+        out.extend(flags.to_le_bytes());
+        for register in self.registers
+        {
+            out.extend(register.to_le_bytes());
+        }
+        let (ssp, usp) = self.saved_stack_pointers;
+        for word in [ssp, usp]
+        {
+            out.extend(word.to_le_bytes());
+        }
+        out.extend(self.steps.to_le_bytes());
+        out.extend(self.cycles.to_le_bytes());
+        let (command, left) = disk.busy.unwrap_or((0, 0));
+        for word in [disk.status, disk.sector, disk.address, command]
+        {
+            out.extend(word.to_le_bytes());
+        }
+        out.extend(left.to_le_bytes());
+        let (first, last) = self.rom.unwrap_or((0, 0));
+        for word in [first, last]
+        {
+            out.extend(word.to_le_bytes());
+        }
+        out.extend((self.keys.len() as u32).to_le_bytes());
+        for &(step, byte) in &self.keys
+        {
+            out.extend(step.to_le_bytes());
+            out.push(byte);
+        }
+        out.extend((self.regions.len() as u16).to_le_bytes());
+        for region in &self.regions
+        {
+            out.extend(region.first.to_le_bytes());
+            out.extend(region.last.to_le_bytes());
+            out.push((region.protection == Protection::NoExecute) as u8);
+            out.extend((region.name.len() as u16).to_le_bytes());
+            out.extend(region.name.as_bytes());
+        }
+        for word in self.memory.iter()
+        {
+            out.extend(word.to_le_bytes());
+        }
+        out.extend(crc32(&out).to_le_bytes());
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String>
+    {
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC
+        {
+            return Err("not an LC-3 snapshot".to_string());
+        }
+        let (body, checksum) = bytes.split_at(bytes.len().saturating_sub(4).max(MAGIC.len()));
+        let mut fields = Fields { bytes: &body[MAGIC.len()..] };
+        let version = fields.u16()?;
+        if version != VERSION
+        {
+            return Err(format!("unsupported snapshot version {version}, expected {VERSION}"));
+        }
+        if checksum.len() != 4 || crc32(body) != u32::from_le_bytes(checksum.try_into().unwrap())
+        {
+            return Err("snapshot checksum mismatch, the file is damaged".to_string());
+        }
+
+        let flags = fields.u16()?;
+        let mut registers = [0; Registers::R_COUNT as usize];
+        for register in &mut registers
+        {
+            *register = fields.u16()?;
+        }
+        let saved_stack_pointers = (fields.u16()?, fields.u16()?);
+        let steps = fields.u64()?;
+        let cycles = fields.u64()?;
+        let (status, sector, address, command, left) = (fields.u16()?, fields.u16()?, fields.u16()?, fields.u16()?, fields.u32()?);
+        let disk = (flags & DISK != 0).then_some(DiskState
+        {
+            status,
+            sector,
+            address,
+            busy: (flags & DISK_BUSY != 0).then_some((command, left)),
+            requested: flags & DISK_REQUESTED != 0,
+        });
+        let (first, last) = (fields.u16()?, fields.u16()?);
+        let rom = (flags & ROM != 0).then_some((first, last));
+        if rom.is_some_and(|(first, last)| first > last)
+        {
+            return Err("snapshot ROM range is empty".to_string());
+        }
+        let count = fields.u32()?;
+        let mut keys = Vec::new();
+        for _ in 0..count
+        {
+            keys.push((fields.u64()?, fields.u8()?));
+        }
+        let mut regions = Vec::new();
+        for _ in 0..fields.u16()?
+        {
+            let (first, last) = (fields.u16()?, fields.u16()?);
+            let protection = match fields.u8()?
+            {
+                0 => Protection::ReadOnly,
+                1 => Protection::NoExecute,
+                other => return Err(format!("unknown protection {other} in snapshot")),
+            };
+            let length = fields.u16()? as usize;
+            let name = String::from_utf8(fields.take(length)?.to_vec()).map_err(|_| "snapshot region name is not UTF-8".to_string())?;
+            regions.push(Region { first, last, protection, name });
+        }
+        let memory: Box<[u16]> = (0..MEMORY_MAX).map(|_| fields.u16()).collect::<Result<_, _>>()?;
+        if !fields.bytes.is_empty()
+        {
+            return Err("unexpected data after the memory image".to_string());
+        }
+        Ok(Self
+        {
+            halted: flags & HALTED != 0,
+            registers,
+            saved_stack_pointers,
+            steps,
+            cycles,
+            scripted: flags & SCRIPTED != 0,
+            eof: flags & EOF != 0,
+            keys,
+            disk,
+            vectored_traps: flags & VECTORED_TRAPS != 0,
+            rom,
+            regions,
+            memory,
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String>
+    {
+        fs::write(path, self.to_bytes()).map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn load(path: &str) -> Result<Self, String>
+    {
+        let bytes = fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+        Self::from_bytes(&bytes).map_err(|e| format!("{path}: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests;
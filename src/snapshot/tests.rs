@@ -0,0 +1,195 @@
+use super::*;
+use std::collections::VecDeque;
+
+use std::io::Cursor;
+
+use crate::disk::{Disk, COMMAND_READ, DISK_ADDRESS, DISK_CR, DISK_IE, DISK_SECTOR, DISK_SR, DISK_VECTOR};
+use crate::display::Display;
+use crate::vm::{INTERRUPT_TABLE, PSR_USER};
+
+/// Reads two keys with GETC and adds them into R2.
+const PROGRAM: [u16; 5] = [
+    0xF020, // GETC
+    0x1420, // ADD R2, R0, #0
+    0xF020, // GETC
+    0x1480, // ADD R2, R2, R0
+    0xF025, // HALT
+];
+
+fn program_vm(script: &str) -> VM {
+    let mut vm = VM::new();
+    vm.set_display(Display::captured_only());
+    vm.set_keyboard(Keyboard::Script(InputScript::parse(script).unwrap()));
+    for (i, &word) in PROGRAM.iter().enumerate() {
+        vm.memory_write(0x3000 + i as u16, word);
+    }
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    vm
+}
+
+#[test]
+fn test_round_trip() {
+    let mut vm = program_vm("0 \"ab\"\n+10 \"c\"");
+    vm.step();
+    let snapshot = Snapshot::capture(&mut vm);
+    assert!(snapshot.scripted);
+    assert_eq!(snapshot.keys, [(0, b'b'), (10, b'c')]);
+    assert_eq!(snapshot.steps, 1);
+
+    let bytes = snapshot.to_bytes();
+    assert_eq!(&bytes[..8], MAGIC);
+    assert_eq!(bytes.len(), 72 + 9 * 2 + 2 + 2 * MEMORY_MAX + 4);
+    assert_eq!(Snapshot::from_bytes(&bytes).unwrap(), snapshot);
+}
+
+#[test]
+fn test_resumed_run_matches_uninterrupted_run() {
+    let mut straight = program_vm("0 \"ab\"");
+    while straight.state_read() {
+        straight.step();
+    }
+
+    let mut first = program_vm("0 \"ab\"");
+    first.step();
+    first.step();
+    let snapshot = Snapshot::from_bytes(&Snapshot::capture(&mut first).to_bytes()).unwrap();
+
+    let mut resumed = VM::new();
+    resumed.set_display(Display::captured_only());
+    snapshot.restore(&mut resumed).unwrap();
+    resumed.set_keyboard(snapshot.keyboard(Keyboard::Terminal));
+    while resumed.state_read() {
+        resumed.step();
+    }
+    assert!(resumed.arch_state() == straight.arch_state());
+    assert_eq!(resumed.register_read(2), (b'a' + b'b') as u16);
+    assert_eq!(resumed.steps(), straight.steps());
+}
+
+#[test]
+fn test_snapshot_at_halt_resumes_running() {
+    let mut vm = program_vm("0 \"ab\"");
+    while vm.state_read() {
+        vm.step();
+    }
+    let snapshot = Snapshot::capture(&mut vm);
+    assert!(snapshot.halted);
+
+    let mut resumed = VM::new();
+    snapshot.restore(&mut resumed).unwrap();
+    assert!(resumed.state_read());
+    assert_eq!(resumed.register_read(Registers::R_PC.into()), 0x3005);
+}
+
+#[test]
+fn test_buffered_keys_come_before_the_new_input() {
+    let mut vm = program_vm("");
+    vm.set_keyboard(Keyboard::Queue(VecDeque::from(*b"xy")));
+    let snapshot = Snapshot::capture(&mut vm);
+    assert!(!snapshot.scripted);
+
+    let mut keyboard = snapshot.keyboard(Keyboard::Queue(VecDeque::new()));
+    assert_eq!((keyboard.read_byte(), keyboard.read_byte(), keyboard.read_byte()), (Some(b'x'), Some(b'y'), None));
+    let mut keyboard = Snapshot { eof: true, ..snapshot }.keyboard(Keyboard::stream(io::empty()));
+    assert_eq!((keyboard.read_byte(), keyboard.read_byte(), keyboard.read_byte()), (Some(b'x'), Some(b'y'), None));
+}
+
+#[test]
+fn test_rejects_damaged_files() {
+    let mut vm = program_vm("");
+    let mut bytes = Snapshot::capture(&mut vm).to_bytes();
+
+    assert_eq!(Snapshot::from_bytes(b"hello").unwrap_err(), "not an LC-3 snapshot");
+    assert_eq!(Snapshot::from_bytes(&bytes[..100]).unwrap_err(), "snapshot checksum mismatch, the file is damaged");
+
+    bytes[0x3000] ^= 1;
+    assert_eq!(Snapshot::from_bytes(&bytes).unwrap_err(), "snapshot checksum mismatch, the file is damaged");
+    bytes[0x3000] ^= 1;
+
+    bytes[8] = 3;
+    assert_eq!(Snapshot::from_bytes(&bytes).unwrap_err(), "unsupported snapshot version 3, expected 4");
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn test_psr_is_kept() {
+    let mut vm = program_vm("");
+    vm.set_psr(PSR_USER | 2 << 8 | 4);
+    let snapshot = Snapshot::from_bytes(&Snapshot::capture(&mut vm).to_bytes()).unwrap();
+    let mut resumed = program_vm("");
+    snapshot.restore(&mut resumed).unwrap();
+    assert_eq!(resumed.psr(), PSR_USER | 2 << 8 | 4);
+    assert!(resumed.user_mode());
+}
+
+/// A user program spinning at x3000 while a disk read is in flight; the
+/// completion interrupt runs HALT at x4000 on the supervisor stack at x2F00.
+fn user_mode_vm() -> VM {
+    let mut vm = VM::new();
+    vm.set_display(Display::captured_only());
+    let mut disk = Disk::new(Box::new(Cursor::new(vec![0x12; 1024]))).unwrap();
+    disk.set_latency(5);
+    vm.set_disk(disk);
+    vm.memory_write(0x3000, 0x0FFF); // BRnzp #-1
+    vm.memory_write(INTERRUPT_TABLE + DISK_VECTOR as u16, 0x4000);
+    vm.memory_write(0x4000, 0xF025); // HALT
+    for (register, value) in [(DISK_SR, DISK_IE), (DISK_SECTOR, 1), (DISK_ADDRESS, 0x5000), (DISK_CR, COMMAND_READ)] {
+        vm.memory_write(register, value);
+    }
+    vm.set_saved_stack_pointers(0x2F00, 0);
+    vm.set_psr(PSR_USER | 2);
+    vm.register_write(6, 0x7000);
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    vm
+}
+
+#[test]
+fn test_user_mode_and_disk_transfer_are_kept() {
+    let mut straight = user_mode_vm();
+    while straight.state_read() {
+        straight.step();
+    }
+    assert_eq!((straight.register_read(6), straight.memory_peek(0x2EFF)), (0x2EFE, PSR_USER | 2));
+    assert_eq!(straight.memory_peek(0x50FF), 0x1212);
+
+    let mut first = user_mode_vm();
+    first.step();
+    first.step();
+    let snapshot = Snapshot::from_bytes(&Snapshot::capture(&mut first).to_bytes()).unwrap();
+    assert_eq!(snapshot.saved_stack_pointers, (0x2F00, 0));
+    assert!(snapshot.disk.unwrap().busy.is_some());
+
+    let mut resumed = VM::new();
+    resumed.set_display(Display::captured_only());
+    resumed.set_disk(Disk::new(Box::new(Cursor::new(vec![0x12; 1024]))).unwrap());
+    snapshot.restore(&mut resumed).unwrap();
+    while resumed.state_read() {
+        resumed.step();
+    }
+    assert!(resumed.arch_state() == straight.arch_state());
+    assert_eq!(resumed.steps(), straight.steps());
+
+    let mut without_disk = VM::new();
+    assert!(snapshot.restore(&mut without_disk).unwrap_err().contains("--disk"));
+}
+
+#[test]
+fn test_protected_regions_are_kept() {
+    let mut vm = program_vm("");
+    vm.protect(0x3000, 0x3004, Protection::ReadOnly, "program.obj");
+    vm.protect(0x4000, 0x40FF, Protection::NoExecute, "--no-exec-range");
+    let snapshot = Snapshot::from_bytes(&Snapshot::capture(&mut vm).to_bytes()).unwrap();
+    let mut resumed = VM::new();
+    snapshot.restore(&mut resumed).unwrap();
+    assert_eq!(resumed.regions(), vm.regions());
+
+    // ST R0, x3000 into the read-only program
+    resumed.memory_write(0x3005, 0x31FA);
+    resumed.register_write(Registers::R_PC.into(), 0x3005);
+    resumed.step();
+    assert_eq!(resumed.fault_region().unwrap().name, "program.obj");
+}
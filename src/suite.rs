@@ -0,0 +1,426 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cli::parse_number;
+use crate::diff::unified_diff;
+use crate::display::Display;
+use crate::hardware::{CondtionalFlags, Registers};
+use crate::image::read_image;
+use crate::keyboard::Keyboard;
+use crate::vm::VM;
+
+/// A declarative autograder suite, usually loaded from TOML:
+///
+/// ```toml
+/// name = "lab 3"
+///
+/// [[case]]
+/// name = "sums the array"
+/// images = ["sum.obj"]
+/// weight = 2
+/// max_steps = 10000
+/// stdin = "5\n"
+/// registers = { R1 = "x4000" }
+/// memory = { x4000 = [1, 2, 3, 0] }
+///
+/// [case.expect]
+/// registers = { R0 = 6 }
+/// cond = "p"
+/// memory = { x5000 = "#6" }
+/// output = "Sum: 6\n"
+/// ```
+///
+/// Image paths are relative to the suite file. Numbers are either integers or
+/// strings in LC-3 notation (`x3000`, `#-5`).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Suite
+{
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(rename = "case", default)]
+    pub cases: Vec<Case>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Case
+{
+    pub name: String,
+    #[serde(default)]
+    pub images: Vec<String>,
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+    #[serde(default = "default_max_steps")]
+    pub max_steps: u64,
+    #[serde(default)]
+    pub stdin: String,
+    #[serde(default)]
+    pub pc: Option<Word>,
+    #[serde(default)]
+    pub registers: BTreeMap<String, Word>,
+    #[serde(default)]
+    pub memory: BTreeMap<String, Words>,
+    #[serde(default)]
+    pub expect: Expect,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expect
+{
+    #[serde(default = "default_halted")]
+    pub halted: bool,
+    #[serde(default)]
+    pub registers: BTreeMap<String, Word>,
+    #[serde(default)]
+    pub cond: Option<String>,
+    #[serde(default)]
+    pub memory: BTreeMap<String, Words>,
+    #[serde(default)]
+    pub output: Option<String>,
+}
+
+impl Default for Expect
+{
+    fn default() -> Self
+    {
+        Self { halted: true, registers: BTreeMap::new(), cond: None, memory: BTreeMap::new(), output: None }
+    }
+}
+
+fn default_weight() -> f64
+{
+    1.0
+}
+
+fn default_max_steps() -> u64
+{
+    1_000_000
+}
+
+fn default_halted() -> bool
+{
+    true
+}
+
+/// A 16-bit value written as an integer or in LC-3 notation.
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Word
+{
+    Number(i64),
+    Text(String),
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Words
+{
+    One(Word),
+    Many(Vec<Word>),
+}
+
+impl Word
+{
+    pub fn value(&self) -> Result<u16, String>
+    {
+        match self
+        {
+            Word::Number(n) => parse_number(&n.to_string()),
+            Word::Text(text) => parse_number(text),
+        }
+    }
+}
+
+impl Words
+{
+    fn values(&self) -> Result<Vec<u16>, String>
+    {
+        match self
+        {
+            Words::One(word) => Ok(vec![word.value()?]),
+            Words::Many(words) => words.iter().map(Word::value).collect(),
+        }
+    }
+}
+
+fn register_index(name: &str) -> Result<usize, String>
+{
+    match name.to_ascii_uppercase().as_str()
+    {
+        "PC" => Ok(Registers::R_PC.into()),
+        upper => match upper.strip_prefix('R').and_then(|n| n.parse::<usize>().ok())
+        {
+            Some(n) if n < 8 => Ok(n),
+            _ => Err(format!("unknown register `{name}`")),
+        },
+    }
+}
+
+fn cond_flag(cond: &str) -> Result<u16, String>
+{
+    match cond.to_ascii_lowercase().as_str()
+    {
+        "n" => Ok(CondtionalFlags::FL_NEG as u16),
+        "z" => Ok(CondtionalFlags::FL_ZRO as u16),
+        "p" => Ok(CondtionalFlags::FL_POS as u16),
+        _ => Err(format!("condition code must be n, z or p, got `{cond}`")),
+    }
+}
+
+fn cond_name(flags: u16) -> &'static str
+{
+    match flags
+    {
+        f if f == CondtionalFlags::FL_NEG as u16 => "n",
+        f if f == CondtionalFlags::FL_ZRO as u16 => "z",
+        f if f == CondtionalFlags::FL_POS as u16 => "p",
+        _ => "none",
+    }
+}
+
+#[derive(Serialize)]
+pub struct CaseResult
+{
+    pub name: String,
+    pub passed: bool,
+    pub weight: f64,
+    pub score: f64,
+    pub steps: u64,
+    pub failures: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct Report
+{
+    pub suite: String,
+    pub passed: usize,
+    pub failed: usize,
+    pub score: f64,
+    pub max_score: f64,
+    pub cases: Vec<CaseResult>,
+}
+
+impl Suite
+{
+    pub fn load(path: &str) -> Result<Self, String>
+    {
+        let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        toml::from_str(&text).map_err(|e| format!("{path}: {e}"))
+    }
+
+    /// Runs every case on a fresh VM. `base` is the directory image paths are relative to.
+    pub fn run(&self, base: &Path) -> Report
+    {
+        let cases: Vec<CaseResult> = self.cases.iter().map(|case| case.run(base)).collect();
+
+        let passed = cases.iter().filter(|case| case.passed).count();
+        Report
+        {
+            suite: self.name.clone().unwrap_or_else(|| "suite".to_string()),
+            passed,
+            failed: cases.len() - passed,
+            score: cases.iter().map(|case| case.score).sum(),
+            max_score: cases.iter().map(|case| case.weight).sum(),
+            cases,
+        }
+    }
+}
+
+impl Case
+{
+    pub fn run(&self, base: &Path) -> CaseResult
+    {
+        let mut vm = VM::new();
+        let failures = match self.setup(&mut vm, base)
+        {
+            Err(message) => vec![format!("setup: {message}")],
+            Ok(()) =>
+            {
+                while vm.state_read() && vm.steps() < self.max_steps
+                {
+                    vm.step();
+                }
+                match vm.fault()
+                {
+                    None => self.check(&mut vm),
+                    Some(fault) => vec![format!("VM stopped after {} steps: {fault}", vm.steps())],
+                }
+            }
+        };
+        let passed = failures.is_empty();
+        CaseResult
+        {
+            name: self.name.clone(),
+            passed,
+            weight: self.weight,
+            score: if passed { self.weight } else { 0.0 },
+            steps: vm.steps(),
+            failures,
+        }
+    }
+
+    fn setup(&self, vm: &mut VM, base: &Path) -> Result<(), String>
+    {
+        vm.set_display(Display::captured_only());
+        vm.set_keyboard(Keyboard::stream(Cursor::new(self.stdin.clone().into_bytes())));
+        for image in &self.images
+        {
+            read_image(&base.join(image).to_string_lossy(), vm)?;
+        }
+        for (address, words) in &self.memory
+        {
+            let address = parse_number(address)?;
+            for (i, value) in words.values()?.into_iter().enumerate()
+            {
+                vm.memory_write(address.wrapping_add(i as u16), value);
+            }
+        }
+        for (name, value) in &self.registers
+        {
+            vm.register_write(register_index(name)?, value.value()?);
+        }
+        let pc = self.pc.as_ref().map_or(Ok(0x3000), Word::value)?;
+        vm.register_write(Registers::R_PC.into(), pc);
+        Ok(())
+    }
+
+    fn check(&self, vm: &mut VM) -> Vec<String>
+    {
+        let mut failures = Vec::new();
+        let expect = &self.expect;
+        if expect.halted && vm.state_read()
+        {
+            failures.push(format!("did not halt within {} steps", self.max_steps));
+        }
+        for (name, expected) in &expect.registers
+        {
+            match register_index(name).and_then(|index| Ok((index, expected.value()?)))
+            {
+                Ok((index, expected)) =>
+                {
+                    let actual = vm.register_read(index);
+                    if actual != expected
+                    {
+                        failures.push(format!("{name}: expected x{expected:04X}, got x{actual:04X}"));
+                    }
+                }
+                Err(message) => failures.push(message),
+            }
+        }
+        if let Some(cond) = &expect.cond
+        {
+            let actual = vm.register_read(Registers::R_COND.into());
+            match cond_flag(cond)
+            {
+                Ok(expected) if expected != actual =>
+                {
+                    failures.push(format!("condition code: expected {}, got {}", cond.to_ascii_lowercase(), cond_name(actual)));
+                }
+                Ok(_) => {}
+                Err(message) => failures.push(message),
+            }
+        }
+        for (address, words) in &expect.memory
+        {
+            let (address, expected) = match parse_number(address).and_then(|a| Ok((a, words.values()?)))
+            {
+                Ok(block) => block,
+                Err(message) =>
+                {
+                    failures.push(message);
+                    continue;
+                }
+            };
+            for (i, expected) in expected.into_iter().enumerate()
+            {
+                let at = address.wrapping_add(i as u16);
+                let actual = vm.memory_peek(at);
+                if actual != expected
+                {
+                    failures.push(format!("memory x{at:04X}: expected x{expected:04X}, got x{actual:04X}"));
+                }
+            }
+        }
+        if let Some(expected) = &expect.output
+        {
+            let actual = String::from_utf8_lossy(vm.display().captured()).into_owned();
+            if let Some(diff) = unified_diff(expected, &actual, "expected output", "program output")
+            {
+                failures.push(format!("output differs:\n{diff}"));
+            }
+        }
+        failures
+    }
+}
+
+impl Report
+{
+    pub fn to_text(&self) -> String
+    {
+        let mut out = String::new();
+        for case in &self.cases
+        {
+            let status = if case.passed { "PASS" } else { "FAIL" };
+            out += &format!("{status} {} ({}/{}, {} steps)\n", case.name, case.score, case.weight, case.steps);
+            for failure in &case.failures
+            {
+                for line in failure.lines()
+                {
+                    out += &format!("    {line}\n");
+                }
+            }
+        }
+        out += &format!(
+            "\n{}: {} passed, {} failed, score {}/{}\n",
+            self.suite, self.passed, self.failed, self.score, self.max_score
+        );
+        out
+    }
+
+    pub fn to_json(&self) -> String
+    {
+        serde_json::to_string_pretty(self).expect("report is always serializable")
+    }
+
+    pub fn to_junit(&self) -> String
+    {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out += &format!(
+            "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">\n",
+            xml_escape(&self.suite),
+            self.cases.len(),
+            self.failed
+        );
+        for case in &self.cases
+        {
+            out += &format!("  <testcase name=\"{}\" classname=\"{}\"", xml_escape(&case.name), xml_escape(&self.suite));
+            if case.passed
+            {
+                out += "/>\n";
+                continue;
+            }
+            let message = case.failures.first().and_then(|f| f.lines().next()).unwrap_or("");
+            out += &format!(
+                ">\n    <failure message=\"{}\">{}</failure>\n  </testcase>\n",
+                xml_escape(message),
+                xml_escape(&case.failures.join("\n"))
+            );
+        }
+        out += "</testsuite>\n";
+        out
+    }
+}
+
+fn xml_escape(text: &str) -> String
+{
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,169 @@
+use super::*;
+
+fn suite(text: &str) -> Suite {
+    toml::from_str(text).unwrap()
+}
+
+// x3000: ADD R0, R1, R2 ; HALT
+const ADD_PROGRAM: &str = r#"memory = { x3000 = ["x1042", "xF025"] }"#;
+
+#[test]
+fn test_passing_case() {
+    let suite = suite(&format!(
+        r##"
+        [[case]]
+        name = "adds"
+        {ADD_PROGRAM}
+        registers = {{ R1 = 2, R2 = "#-5" }}
+        expect = {{ registers = {{ R0 = -3 }}, cond = "n" }}
+        "##
+    ));
+    let report = suite.run(Path::new("."));
+    assert_eq!(report.passed, 1);
+    assert_eq!(report.cases[0].steps, 2);
+    assert!(report.cases[0].failures.is_empty());
+}
+
+#[test]
+fn test_failing_assertions_are_all_reported() {
+    let suite = suite(&format!(
+        r##"
+        [[case]]
+        name = "adds"
+        weight = 3
+        {ADD_PROGRAM}
+        registers = {{ R1 = 2, R2 = 2 }}
+        expect = {{ registers = {{ R0 = 5 }}, cond = "z", memory = {{ x4000 = 1 }} }}
+        "##
+    ));
+    let report = suite.run(Path::new("."));
+    let case = &report.cases[0];
+    assert!(!case.passed);
+    assert_eq!(case.score, 0.0);
+    assert_eq!(report.max_score, 3.0);
+    assert_eq!(
+        case.failures,
+        vec![
+            "R0: expected x0005, got x0004",
+            "condition code: expected z, got p",
+            "memory x4000: expected x0001, got x0000",
+        ]
+    );
+}
+
+#[test]
+fn test_stdin_and_output() {
+    // x3000: GETC ; PUTS from x3003 ; HALT ; "ok"
+    let suite = suite(
+        r##"
+        [[case]]
+        name = "echo"
+        stdin = "A"
+        memory = { x3000 = ["xF020", "xE002", "xF022", "xF025", "#111", "#107", 0] }
+        expect = { registers = { R0 = "x3004" }, output = "ok" }
+
+        [[case]]
+        name = "wrong output"
+        memory = { x3000 = ["xE002", "xF022", "xF025", "#111", 0] }
+        expect = { output = "ok" }
+        "##,
+    );
+    let report = suite.run(Path::new("."));
+    assert!(report.cases[0].passed, "{:?}", report.cases[0].failures);
+    assert!(report.cases[1].failures[0].starts_with("output differs:"));
+}
+
+#[test]
+fn test_memory_assertions_leave_devices_alone() {
+    // A key is waiting, but the program never polls for it
+    let suite = suite(
+        r##"
+        [[case]]
+        name = "untouched keyboard"
+        stdin = "A"
+        memory = { x3000 = "xF025" }
+        expect = { memory = { xFE00 = [0, 0] } }
+        "##,
+    );
+    let report = suite.run(Path::new("."));
+    assert!(report.cases[0].passed, "{:?}", report.cases[0].failures);
+}
+
+#[test]
+fn test_step_limit_and_vm_errors() {
+    let suite = suite(
+        r##"
+        [[case]]
+        name = "spins"
+        max_steps = 50
+        memory = { x3000 = "x0FFF" }
+
+        [[case]]
+        name = "illegal opcode"
+        memory = { x3000 = "xD000" }
+
+        [[case]]
+        name = "missing image"
+        images = ["does-not-exist.obj"]
+        "##,
+    );
+    let report = suite.run(Path::new("."));
+    assert_eq!(report.failed, 3);
+    assert_eq!(report.cases[0].failures, vec!["did not halt within 50 steps"]);
+    assert_eq!(report.cases[1].failures, vec!["VM stopped after 1 steps: illegal opcode xD000 at x3000"]);
+    assert!(report.cases[2].failures[0].starts_with("setup: "));
+}
+
+#[test]
+fn test_images_are_relative_to_suite() {
+    let dir = std::env::temp_dir().join(format!("lc3box-suite-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // .ORIG x3000 ; AND R3, R3, #0 ; HALT
+    fs::write(dir.join("prog.obj"), [0x30, 0x00, 0x56, 0xE0, 0xF0, 0x25]).unwrap();
+    let suite = suite(
+        r##"
+        [[case]]
+        name = "clears R3"
+        images = ["prog.obj"]
+        registers = { R3 = 9 }
+        expect = { registers = { R3 = 0 }, cond = "z" }
+        "##,
+    );
+    let report = suite.run(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(report.cases[0].passed, "{:?}", report.cases[0].failures);
+}
+
+#[test]
+fn test_unknown_fields_are_rejected() {
+    let parsed: Result<Suite, _> = toml::from_str("[[case]]\nname = \"x\"\nmax_step = 5\n");
+    assert!(parsed.is_err());
+}
+
+#[test]
+fn test_machine_readable_reports() {
+    let suite = suite(
+        r##"
+        name = "lab <1>"
+
+        [[case]]
+        name = "ok"
+        memory = { x3000 = "xF025" }
+
+        [[case]]
+        name = "bad"
+        memory = { x3000 = "xF025" }
+        expect = { registers = { R0 = 1 } }
+        "##,
+    );
+    let report = suite.run(Path::new("."));
+
+    let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+    assert_eq!(json["passed"], 1);
+    assert_eq!(json["cases"][1]["failures"][0], "R0: expected x0001, got x0000");
+
+    let junit = report.to_junit();
+    assert!(junit.contains("<testsuite name=\"lab &lt;1&gt;\" tests=\"2\" failures=\"1\">"));
+    assert!(junit.contains("<testcase name=\"ok\" classname=\"lab &lt;1&gt;\"/>"));
+    assert!(junit.contains("<failure message=\"R0: expected x0001, got x0000\">"));
+}
@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+
+/// Labels from an assembler symbol table, as written by `lc3as`:
+///
+/// ```text
+/// // Symbol table
+/// // Scope level 0:
+/// //    Symbol Name       Page Address
+/// //    ----------------  ------------
+/// //    MAIN              3000
+/// ```
+///
+/// Plain `LABEL ADDRESS` lines are accepted as well; addresses are hex with an
+/// optional `x` prefix.
+#[derive(Default)]
+pub struct SymbolTable
+{
+    labels: BTreeMap<u16, String>,
+}
+
+impl SymbolTable
+{
+    /// Adds every `LABEL ADDRESS` pair found in `text`; headers and other lines are skipped.
+    pub fn extend(&mut self, text: &str)
+    {
+        for line in text.lines()
+        {
+            let line = line.trim_start().trim_start_matches('/');
+            let mut fields = line.split_whitespace();
+            let (Some(name), Some(address), None) = (fields.next(), fields.next(), fields.next())
+            else
+            {
+                continue;
+            };
+            let digits = address.trim_start_matches(['x', 'X']);
+            if let Ok(address) = u16::from_str_radix(digits, 16)
+            {
+                self.labels.entry(address).or_insert_with(|| name.to_string());
+            }
+        }
+    }
+
+    pub fn label(&self, address: u16) -> Option<&str>
+    {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    /// Address of a label, ignoring case.
+    pub fn address(&self, name: &str) -> Option<u16>
+    {
+        self.labels.iter().find(|(_, label)| label.eq_ignore_ascii_case(name)).map(|(&address, _)| address)
+    }
+
+    /// `LABEL`, `LABEL+n` relative to the closest label at or below `address`,
+    /// or the bare address when nothing precedes it.
+    pub fn describe(&self, address: u16) -> String
+    {
+        match self.labels.range(..=address).next_back()
+        {
+            Some((&at, label)) if at == address => label.clone(),
+            Some((&at, label)) => format!("{label}+{}", address - at),
+            None => format!("x{address:04X}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
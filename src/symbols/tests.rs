@@ -0,0 +1,40 @@
+use super::*;
+
+const LC3AS_OUTPUT: &str = "// Symbol table
+// Scope level 0:
+//\tSymbol Name       Page Address
+//\t----------------  ------------
+//\tMAIN              3000
+//\tLOOP              3004
+//\tSUM               3010
+";
+
+fn table(text: &str) -> SymbolTable {
+    let mut table = SymbolTable::default();
+    table.extend(text);
+    table
+}
+
+#[test]
+fn test_parses_lc3as_symbol_table() {
+    let table = table(LC3AS_OUTPUT);
+    assert_eq!(table.label(0x3000), Some("MAIN"));
+    assert_eq!(table.label(0x3010), Some("SUM"));
+    assert_eq!(table.label(0x3001), None);
+    assert_eq!(table.address("loop"), Some(0x3004));
+}
+
+#[test]
+fn test_plain_pairs_with_x_prefix() {
+    let table = table("START x3000\nDATA x4000\nnot a symbol line\n");
+    assert_eq!(table.label(0x4000), Some("DATA"));
+    assert_eq!(table.address("START"), Some(0x3000));
+}
+
+#[test]
+fn test_describe_uses_nearest_label() {
+    let table = table(LC3AS_OUTPUT);
+    assert_eq!(table.describe(0x3004), "LOOP");
+    assert_eq!(table.describe(0x3006), "LOOP+2");
+    assert_eq!(table.describe(0x2FFF), "x2FFF");
+}
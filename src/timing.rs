@@ -0,0 +1,88 @@
+/// Cycle counts derived from the LC-3 microsequencer (Patt & Patel,
+/// appendix C). Every state takes one cycle, except the states that wait for
+/// memory to assert R, which take `memory_latency` cycles each.
+///
+/// TRAP service routines are implemented natively by the VM, so only the
+/// TRAP microcode itself (states 15, 28, 30) is counted, not the routine.
+#[derive(Clone, Copy)]
+pub struct TimingModel
+{
+    pub memory_latency: u64,
+}
+
+/// Instruction fetch and decode: MAR<-PC, MDR<-M[MAR], IR<-MDR, decode.
+pub const FETCH: [u8; 4] = [18, 33, 35, 32];
+
+/// Interrupt and exception entry: push PSR and PC on the supervisor stack and
+/// load the handler from the vector table. State 45 swaps to the supervisor
+/// stack when the interrupted program ran in user mode.
+pub const INTERRUPT_SUPERVISOR: [u8; 9] = [49, 37, 41, 43, 47, 48, 50, 52, 54];
+pub const INTERRUPT_USER: [u8; 10] = [49, 45, 37, 41, 43, 47, 48, 50, 52, 54];
+
+/// States that access memory and repeat until it is ready.
+pub const MEMORY_STATES: [u8; 11] = [16, 24, 25, 28, 29, 33, 36, 40, 41, 48, 52];
+
+impl Default for TimingModel
+{
+    /// The textbook exercises assume a memory access takes five cycles.
+    fn default() -> Self
+    {
+        Self { memory_latency: 5 }
+    }
+}
+
+impl TimingModel
+{
+    /// States visited after decode for `inst`. `ben` is the branch enable
+    /// computed in state 32, `user_mode` is PSR[15] when RTI reaches state 34.
+    pub fn execute_states(inst: u16, ben: bool, user_mode: bool) -> &'static [u8]
+    {
+        match inst >> 12
+        {
+            0x0 if ben => &[0, 22],
+            0x0 => &[0],
+            0x1 => &[1],
+            0x2 => &[2, 25, 27],
+            0x3 => &[3, 23, 16],
+            0x4 if inst & 0x0800 != 0 => &[4, 21],
+            0x4 => &[4, 20],
+            0x5 => &[5],
+            0x6 => &[6, 25, 27],
+            0x7 => &[7, 23, 16],
+            0x8 if user_mode => &[8, 36, 38, 39, 40, 42, 34, 59],
+            0x8 => &[8, 36, 38, 39, 40, 42, 34, 51],
+            0x9 => &[9],
+            0xA => &[10, 24, 26, 25, 27],
+            0xB => &[11, 29, 31, 23, 16],
+            0xC => &[12],
+            0xD => &[13],
+            0xE => &[14],
+            _ => &[15, 28, 30],
+        }
+    }
+
+    pub fn state_cycles(&self, state: u8) -> u64
+    {
+        if MEMORY_STATES.contains(&state) { self.memory_latency } else { 1 }
+    }
+
+    fn sum(&self, states: &[u8]) -> u64
+    {
+        states.iter().map(|&state| self.state_cycles(state)).sum()
+    }
+
+    /// Cycles from fetch to the return to state 18 for one instruction.
+    pub fn instruction_cycles(&self, inst: u16, ben: bool, user_mode: bool) -> u64
+    {
+        self.sum(&FETCH) + self.sum(Self::execute_states(inst, ben, user_mode))
+    }
+
+    /// Cycles spent entering an interrupt or exception handler.
+    pub fn interrupt_cycles(&self, user_mode: bool) -> u64
+    {
+        if user_mode { self.sum(&INTERRUPT_USER) } else { self.sum(&INTERRUPT_SUPERVISOR) }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,67 @@
+use super::*;
+
+#[test]
+fn test_operate_instructions() {
+    let timing = TimingModel::default();
+    // 18, 33 (5 cycles), 35, 32, then a single execute state
+    assert_eq!(timing.instruction_cycles(0x1042, false, false), 9); // ADD
+    assert_eq!(timing.instruction_cycles(0x5020, false, false), 9); // AND
+    assert_eq!(timing.instruction_cycles(0x927F, false, false), 9); // NOT
+    assert_eq!(timing.instruction_cycles(0xE002, false, false), 9); // LEA
+}
+
+#[test]
+fn test_branch_depends_on_ben() {
+    let timing = TimingModel::default();
+    assert_eq!(TimingModel::execute_states(0x0E05, true, false), &[0, 22]);
+    assert_eq!(timing.instruction_cycles(0x0E05, true, false), 10);
+    assert_eq!(timing.instruction_cycles(0x0E05, false, false), 9);
+}
+
+#[test]
+fn test_memory_instructions_pay_latency() {
+    let timing = TimingModel { memory_latency: 5 };
+    assert_eq!(timing.instruction_cycles(0x2000, false, false), 8 + 1 + 5 + 1); // LD
+    assert_eq!(timing.instruction_cycles(0x3000, false, false), 8 + 1 + 1 + 5); // ST
+    assert_eq!(timing.instruction_cycles(0xA000, false, false), 8 + 1 + 5 + 1 + 5 + 1); // LDI
+    assert_eq!(timing.instruction_cycles(0xB000, false, false), 8 + 1 + 5 + 1 + 1 + 5); // STI
+
+    let fast = TimingModel { memory_latency: 1 };
+    assert_eq!(fast.instruction_cycles(0x2000, false, false), 7);
+}
+
+#[test]
+fn test_control_transfers() {
+    let timing = TimingModel::default();
+    assert_eq!(TimingModel::execute_states(0x4800, false, false), &[4, 21]); // JSR
+    assert_eq!(TimingModel::execute_states(0x4080, false, false), &[4, 20]); // JSRR
+    assert_eq!(timing.instruction_cycles(0xF025, false, false), 8 + 1 + 5 + 1); // TRAP
+    assert_eq!(timing.instruction_cycles(0x8000, false, false), 8 + 1 + 5 + 1 + 1 + 5 + 1 + 1 + 1); // RTI
+    assert_eq!(TimingModel::execute_states(0x8000, false, true).last(), Some(&59));
+}
+
+#[test]
+fn test_interrupt_entry() {
+    let timing = TimingModel::default();
+    assert_eq!(timing.interrupt_cycles(false), 6 + 3 * 5);
+    assert_eq!(timing.interrupt_cycles(true), timing.interrupt_cycles(false) + 1);
+}
+
+#[test]
+fn test_vm_accumulates_cycles() {
+    use crate::hardware::Registers;
+    use crate::vm::VM;
+
+    let mut vm = VM::new();
+    // ADD R0, R0, #1 ; BRp x3000 is taken once R0 is positive ; HALT never reached
+    vm.memory_write(0x3000, 0x1021);
+    vm.memory_write(0x3001, 0x03FE);
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    vm.set_timing(TimingModel { memory_latency: 3 });
+
+    vm.step();
+    assert_eq!(vm.last_cycles(), 7);
+    vm.step();
+    assert_eq!(vm.last_cycles(), 8);
+    assert_eq!(vm.cycles(), 15);
+}
@@ -0,0 +1,140 @@
+use std::io::Write;
+
+use crate::disasm::{disassemble, opcode_from_name};
+use crate::cli::parse_range;
+
+/// Architectural state written by one instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access
+{
+    Register(usize, u16),
+    Memory(u16, u16),
+}
+
+const REGISTER_NAMES: [&str; 10] = ["R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "PC", "COND"];
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum TraceFormat
+{
+    /// One aligned, human-readable line per instruction.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Which instructions get logged. Empty lists match everything.
+#[derive(Default)]
+pub struct TraceFilter
+{
+    ranges: Vec<(u16, u16)>,
+    opcodes: Vec<u16>,
+}
+
+impl TraceFilter
+{
+    /// Adds an inclusive address range written `x3000:x30FF`, or a single address.
+    pub fn add_range(&mut self, range: &str) -> Result<(), String>
+    {
+        self.ranges.push(parse_range(range)?);
+        Ok(())
+    }
+
+    /// Adds a comma separated list of mnemonics such as `LD,LDR,JSR`.
+    pub fn add_opcodes(&mut self, names: &str) -> Result<(), String>
+    {
+        for name in names.split(',').map(str::trim)
+        {
+            self.opcodes.push(opcode_from_name(name).ok_or_else(|| format!("unknown opcode `{name}`"))?);
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, pc: u16, inst: u16) -> bool
+    {
+        (self.ranges.is_empty() || self.ranges.iter().any(|&(start, end)| (start..=end).contains(&pc)))
+            && (self.opcodes.is_empty() || self.opcodes.contains(&(inst >> 12)))
+    }
+}
+
+pub struct Tracer
+{
+    format: TraceFormat,
+    filter: TraceFilter,
+    out: Box<dyn Write>,
+}
+
+impl Tracer
+{
+    pub fn new(format: TraceFormat, filter: TraceFilter, out: Box<dyn Write>) -> Self
+    {
+        Self { format, filter, out }
+    }
+
+    pub fn wants(&self, pc: u16, inst: u16) -> bool
+    {
+        self.filter.matches(pc, inst)
+    }
+
+    pub fn record(&mut self, step: u64, pc: u16, inst: u16, writes: &[Access])
+    {
+        let line = match self.format
+        {
+            TraceFormat::Text => text_line(step, pc, inst, writes),
+            TraceFormat::Json => json_line(step, pc, inst, writes),
+        };
+        // A closed trace sink must not take the program down with it
+        let _ = writeln!(self.out, "{line}");
+    }
+
+    pub fn flush(&mut self)
+    {
+        let _ = self.out.flush();
+    }
+}
+
+pub fn text_line(step: u64, pc: u16, inst: u16, writes: &[Access]) -> String
+{
+    let mut line = format!("{step:>8}  x{pc:04X}  x{inst:04X}  {:<18}", disassemble(pc, inst));
+    for access in writes
+    {
+        match *access
+        {
+            Access::Register(9, flags) => line += &format!(" CC={}", cond_letters(flags)),
+            Access::Register(r, value) => line += &format!(" {}=x{value:04X}", REGISTER_NAMES[r]),
+            Access::Memory(address, value) => line += &format!(" [x{address:04X}]=x{value:04X}"),
+        }
+    }
+    line.trim_end().to_string()
+}
+
+pub fn json_line(step: u64, pc: u16, inst: u16, writes: &[Access]) -> String
+{
+    let mut registers = serde_json::Map::new();
+    let mut memory = Vec::new();
+    for access in writes
+    {
+        match *access
+        {
+            Access::Register(r, value) => { registers.insert(REGISTER_NAMES[r].to_string(), value.into()); }
+            Access::Memory(address, value) => memory.push(serde_json::json!([address, value])),
+        }
+    }
+    serde_json::json!({
+        "step": step,
+        "pc": pc,
+        "inst": inst,
+        "asm": disassemble(pc, inst),
+        "regs": registers,
+        "mem": memory,
+    })
+    .to_string()
+}
+
+fn cond_letters(flags: u16) -> String
+{
+    ["n", "z", "p"].iter().zip([4, 2, 1]).filter(|&(_, bit)| flags & bit != 0).map(|(l, _)| *l).collect()
+}
+
+#[cfg(test)]
+mod tests;
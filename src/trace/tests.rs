@@ -0,0 +1,90 @@
+use super::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::hardware::Registers;
+use crate::vm::VM;
+
+/// Trace sink the test can read back after the VM is done with it.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn traced_vm(format: TraceFormat, filter: TraceFilter, program: &[u16]) -> (VM, SharedBuffer) {
+    let buffer = SharedBuffer::default();
+    let mut vm = VM::new();
+    vm.set_tracer(Tracer::new(format, filter, Box::new(buffer.clone())));
+    for (i, word) in program.iter().enumerate() {
+        vm.memory_write(0x3000 + i as u16, *word);
+    }
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    (vm, buffer)
+}
+
+fn lines(buffer: &SharedBuffer) -> Vec<String> {
+    String::from_utf8(buffer.0.borrow().clone()).unwrap().lines().map(String::from).collect()
+}
+
+// ADD R1, R1, #3 ; ST R1, x3003 ; BRp x3000 ; (data)
+const PROGRAM: [u16; 3] = [0b0001_001_001_1_00011, 0b0011_001_000000001, 0b0000_001_111111101];
+
+#[test]
+fn test_text_trace_lists_writes() {
+    let (mut vm, buffer) = traced_vm(TraceFormat::Text, TraceFilter::default(), &PROGRAM);
+    for _ in 0..3 {
+        vm.step();
+    }
+    assert_eq!(
+        lines(&buffer),
+        vec![
+            "       0  x3000  x1263  ADD R1, R1, #3     R1=x0003 CC=p",
+            "       1  x3001  x3201  ST R1, x3003       [x3003]=x0003",
+            "       2  x3002  x03FD  BRp x3000          PC=x3000",
+        ]
+    );
+}
+
+#[test]
+fn test_json_trace_lines() {
+    let (mut vm, buffer) = traced_vm(TraceFormat::Json, TraceFilter::default(), &PROGRAM);
+    vm.step();
+    vm.step();
+    let records: Vec<serde_json::Value> = lines(&buffer).iter().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(records[0]["step"], 0);
+    assert_eq!(records[0]["pc"], 0x3000);
+    assert_eq!(records[0]["asm"], "ADD R1, R1, #3");
+    assert_eq!(records[0]["regs"]["R1"], 3);
+    assert_eq!(records[0]["regs"]["COND"], 1);
+    assert_eq!(records[1]["mem"], serde_json::json!([[0x3003, 3]]));
+}
+
+#[test]
+fn test_filter_by_opcode_and_range() {
+    let mut filter = TraceFilter::default();
+    filter.add_opcodes("st,br").unwrap();
+    let (mut vm, buffer) = traced_vm(TraceFormat::Text, filter, &PROGRAM);
+    for _ in 0..6 {
+        vm.step();
+    }
+    assert_eq!(lines(&buffer).len(), 4);
+    assert!(lines(&buffer).iter().all(|l| !l.contains("ADD")));
+
+    let mut filter = TraceFilter::default();
+    filter.add_range("x3001:x3002").unwrap();
+    filter.add_range("x3000").unwrap();
+    assert!(filter.matches(0x3000, 0x1263));
+    assert!(filter.matches(0x3002, 0x0000));
+    assert!(!filter.matches(0x3003, 0x0000));
+    assert!(filter.add_range("x3002:x3001").is_err());
+    assert!(TraceFilter::default().add_opcodes("ADD,MUL").is_err());
+}
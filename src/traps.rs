@@ -1,3 +1,7 @@
+use std::rc::Rc;
+
+use crate::vm::VM;
+
 
 pub enum Traps
 {
@@ -7,6 +11,11 @@ pub enum Traps
     TRAP_IN = 0x23,    /* get character from keyboard, echoed onto the terminal */
     TRAP_PUTSP = 0x24, /* output a byte string */
     TRAP_HALT = 0x25,   /* halt the program */
+    // Extensions, only serviced when the VM has extended traps enabled
+    TRAP_PUTD = 0x26,   /* output R0 as a signed decimal number */
+    TRAP_PUTX = 0x27,   /* output R0 as four hex digits */
+    TRAP_GETD = 0x28,   /* read a signed decimal number into R0, echoed */
+    TRAP_NEWLN = 0x29,  /* output a newline */
     TRAP_INVALID
 }
 
@@ -21,7 +30,36 @@ impl From<u16> for Traps {
             0x23 => Traps::TRAP_IN ,    
             0x24 => Traps::TRAP_PUTSP,  
             0x25 => Traps::TRAP_HALT,
+            0x26 => Traps::TRAP_PUTD,
+            0x27 => Traps::TRAP_PUTX,
+            0x28 => Traps::TRAP_GETD,
+            0x29 => Traps::TRAP_NEWLN,
             _ => Traps::TRAP_INVALID
         }
     }
-}
\ No newline at end of file
+}
+
+impl Traps {
+    /// Not part of the LC-3 specification, see `VM::set_extended_traps`.
+    pub fn is_extended(&self) -> bool {
+        matches!(self, Traps::TRAP_PUTD | Traps::TRAP_PUTX | Traps::TRAP_GETD | Traps::TRAP_NEWLN)
+    }
+}
+
+/// A TRAP service routine written in Rust, see `VM::register_trap`. It runs
+/// after R7 has been set to the return address and can use the whole VM API.
+/// Handlers are `Fn` so they can be re-entered; keep state in a `Cell` or
+/// `RefCell` if they need any.
+pub type TrapHandler = Rc<dyn Fn(&mut VM)>;
+
+/// What services a TRAP vector.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TrapBinding
+{
+    /// One of the VM's own routines, x20-x25 and, when enabled, x26-x29.
+    BuiltIn,
+    /// A handler registered with `VM::register_trap`.
+    Handler,
+    /// A routine in memory at this address, see `VM::set_vectored_traps`.
+    Routine(u16),
+}
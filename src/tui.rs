@@ -0,0 +1,561 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use crate::cli::parse_number;
+use crate::disasm::disassemble;
+use crate::hardware::Registers;
+use crate::keyboard::Keyboard;
+use crate::symbols::SymbolTable;
+use crate::vm::VM;
+
+/// Instructions executed between redraws while running.
+const RUN_BATCH: u64 = 20_000;
+
+/// Why execution stopped.
+#[derive(Debug, PartialEq)]
+pub enum Stop
+{
+    Breakpoint(u16),
+    Halted,
+    /// GETC or IN is next and no key has been typed yet.
+    Input,
+    Crashed(String),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Mode
+{
+    Paused,
+    Running,
+    /// Waiting for a key for the program; `resume` continues afterwards
+    /// instead of stopping after the one instruction.
+    Input
+    {
+        resume: bool,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Style
+{
+    Plain,
+    Current,
+    Selected,
+    Breakpoint,
+}
+
+/// Interactive debugger state behind `lc3box tui`. Program output is taken
+/// from the VM's captured display, keys typed while the program runs are
+/// queued for it.
+pub struct Debugger
+{
+    pub breakpoints: BTreeSet<u16>,
+    symbols: SymbolTable,
+    cursor: u16, // selected line in the disassembly
+    memory_start: u16,
+    mode: Mode,
+    command: Option<String>, // command line being edited after `:`
+    status: String,
+    leaving: Option<u16>, // breakpoint execution resumes from, not hit again
+    quit: bool,
+}
+
+fn pc(vm: &mut VM) -> u16
+{
+    vm.register_read(Registers::R_PC.into())
+}
+
+/// True when the next instruction is GETC or IN and no key is queued.
+fn waiting_for_input(vm: &mut VM) -> bool
+{
+    let pc = pc(vm);
+    let inst = vm.memory_peek(pc);
+    inst >> 12 == 0xF && matches!(inst & 0xFF, 0x20 | 0x23) && matches!(vm.keyboard(), Keyboard::Queue(keys) if keys.is_empty())
+}
+
+impl Debugger
+{
+    pub fn new(symbols: SymbolTable, vm: &mut VM) -> Self
+    {
+        let pc = pc(vm);
+        Self
+        {
+            breakpoints: BTreeSet::new(),
+            symbols,
+            cursor: pc,
+            memory_start: pc & !7,
+            mode: Mode::Paused,
+            command: None,
+            status: String::new(),
+            leaving: None,
+            quit: false,
+        }
+    }
+
+    /// `x3000`, a decimal number or a label from the symbol table.
+    fn address(&self, text: &str) -> Result<u16, String>
+    {
+        self.symbols.address(text).map_or_else(|| parse_number(text), Ok)
+    }
+
+    /// Executes one instruction, unless the VM has halted or would block on input.
+    pub fn step(&mut self, vm: &mut VM) -> Option<Stop>
+    {
+        if !vm.state_read()
+        {
+            return Some(Stop::Halted);
+        }
+        if waiting_for_input(vm)
+        {
+            return Some(Stop::Input);
+        }
+        vm.step();
+        if let Some(fault) = vm.fault()
+        {
+            return Some(Stop::Crashed(fault.to_string()));
+        }
+        (!vm.state_read()).then_some(Stop::Halted)
+    }
+
+    /// Runs up to `budget` instructions, stopping early at breakpoints.
+    pub fn continue_for(&mut self, vm: &mut VM, budget: u64) -> Option<Stop>
+    {
+        for _ in 0..budget
+        {
+            let pc = pc(vm);
+            if self.breakpoints.contains(&pc) && self.leaving != Some(pc)
+            {
+                return Some(Stop::Breakpoint(pc));
+            }
+            let stop = self.step(vm);
+            self.leaving = None;
+            if stop.is_some()
+            {
+                return stop;
+            }
+        }
+        None
+    }
+
+    fn resume(&mut self, vm: &mut VM)
+    {
+        self.leaving = Some(pc(vm));
+        self.mode = Mode::Running;
+        self.status = "running".to_string();
+    }
+
+    fn stopped(&mut self, vm: &mut VM, stop: Option<Stop>, resume: bool)
+    {
+        self.mode = Mode::Paused;
+        self.status = match stop
+        {
+            None => String::new(),
+            Some(Stop::Breakpoint(address)) => format!("breakpoint at {}", self.symbols.describe(address)),
+            Some(Stop::Halted) => "program halted".to_string(),
+            Some(Stop::Input) =>
+            {
+                self.mode = Mode::Input { resume };
+                "program is waiting for a key".to_string()
+            }
+            Some(Stop::Crashed(reason)) => format!("VM stopped: {reason}"),
+        };
+        self.cursor = pc(vm);
+    }
+
+    fn toggle_breakpoint(&mut self, address: u16)
+    {
+        if !self.breakpoints.remove(&address)
+        {
+            self.breakpoints.insert(address);
+        }
+    }
+
+    fn send_key(vm: &mut VM, byte: u8)
+    {
+        if let Keyboard::Queue(keys) = vm.keyboard()
+        {
+            keys.push_back(byte);
+        }
+    }
+
+    /// Executes a command line:
+    /// `step [n]`, `continue`, `break <addr>`, `delete <addr>`, `mem <addr>`,
+    /// `set <reg> <value>`, `input <text>` or `quit`. Addresses may be labels.
+    pub fn command(&mut self, vm: &mut VM, line: &str) -> Result<(), String>
+    {
+        let mut words = line.split_whitespace();
+        let Some(name) = words.next()
+        else
+        {
+            return Ok(());
+        };
+        let argument = words.next();
+        let address = |argument: Option<&str>| argument.ok_or("expected an address").map_err(str::to_string).and_then(|a| self.address(a));
+        match name
+        {
+            "s" | "step" =>
+            {
+                let count = argument.map_or(Ok(1), |n| n.parse::<u64>().map_err(|_| format!("invalid count `{n}`")))?;
+                let mut stop = None;
+                for _ in 0..count
+                {
+                    stop = self.step(vm);
+                    if stop.is_some()
+                    {
+                        break;
+                    }
+                }
+                self.stopped(vm, stop, false);
+            }
+            "c" | "continue" => self.resume(vm),
+            "b" | "break" =>
+            {
+                let address = address(argument)?;
+                self.breakpoints.insert(address);
+                self.status = format!("breakpoint set at {}", self.symbols.describe(address));
+            }
+            "d" | "delete" =>
+            {
+                let address = address(argument)?;
+                if !self.breakpoints.remove(&address)
+                {
+                    return Err(format!("no breakpoint at x{address:04X}"));
+                }
+                self.status = format!("breakpoint at {} deleted", self.symbols.describe(address));
+            }
+            "m" | "mem" => self.memory_start = address(argument)? & !7,
+            "set" =>
+            {
+                let register = argument.ok_or("expected a register")?.to_ascii_uppercase();
+                let index = match register.as_str()
+                {
+                    "PC" => Registers::R_PC as usize,
+                    r => r
+                        .strip_prefix('R')
+                        .and_then(|n| n.parse::<usize>().ok())
+                        .filter(|&n| n < 8)
+                        .ok_or_else(|| format!("unknown register `{register}`"))?,
+                };
+                let value = parse_number(words.next().ok_or("expected a value")?)?;
+                vm.register_write(index, value);
+                if index == Registers::R_PC as usize
+                {
+                    self.cursor = value;
+                }
+            }
+            "input" =>
+            {
+                let text = line.trim_start()[name.len()..].trim_start().replace("\\n", "\n");
+                for byte in text.bytes()
+                {
+                    Self::send_key(vm, byte);
+                }
+            }
+            "q" | "quit" => self.quit = true,
+            other => return Err(format!("unknown command `{other}`")),
+        }
+        Ok(())
+    }
+
+    /// Handles a key press according to the current mode.
+    pub fn key(&mut self, vm: &mut VM, key: KeyEvent)
+    {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL)
+        {
+            self.quit = true;
+            return;
+        }
+        if let Some(line) = &mut self.command
+        {
+            match key.code
+            {
+                KeyCode::Char(c) => line.push(c),
+                KeyCode::Backspace =>
+                {
+                    line.pop();
+                }
+                KeyCode::Enter =>
+                {
+                    let line = self.command.take().unwrap_or_default();
+                    if let Err(message) = self.command(vm, &line)
+                    {
+                        self.status = message;
+                    }
+                }
+                KeyCode::Esc => self.command = None,
+                _ => {}
+            }
+            return;
+        }
+        let byte = match key.code
+        {
+            KeyCode::Char(c) if c.is_ascii() => Some(c as u8),
+            KeyCode::Enter => Some(b'\n'),
+            KeyCode::Backspace => Some(0x08),
+            _ => None,
+        };
+        match (self.mode, key.code)
+        {
+            (Mode::Running | Mode::Input { .. }, KeyCode::Esc) => self.stopped(vm, None, false),
+            (Mode::Running, _) =>
+            {
+                if let Some(byte) = byte
+                {
+                    Self::send_key(vm, byte);
+                }
+            }
+            (Mode::Input { resume }, _) =>
+            {
+                let Some(byte) = byte else { return };
+                Self::send_key(vm, byte);
+                if resume
+                {
+                    self.mode = Mode::Running;
+                    self.status = "running".to_string();
+                }
+                else
+                {
+                    let stop = self.step(vm);
+                    self.stopped(vm, stop, false);
+                }
+            }
+            (Mode::Paused, KeyCode::Char('s')) =>
+            {
+                let stop = self.step(vm);
+                self.stopped(vm, stop, false);
+            }
+            (Mode::Paused, KeyCode::Char('c')) => self.resume(vm),
+            (Mode::Paused, KeyCode::Char('b')) => self.toggle_breakpoint(self.cursor),
+            (Mode::Paused, KeyCode::Char(':')) => self.command = Some(String::new()),
+            (Mode::Paused, KeyCode::Char('q')) => self.quit = true,
+            (Mode::Paused, KeyCode::Up) => self.cursor = self.cursor.wrapping_sub(1),
+            (Mode::Paused, KeyCode::Down) => self.cursor = self.cursor.wrapping_add(1),
+            (Mode::Paused, KeyCode::PageUp) => self.memory_start = self.memory_start.wrapping_sub(64),
+            (Mode::Paused, KeyCode::PageDown) => self.memory_start = self.memory_start.wrapping_add(64),
+            _ => {}
+        }
+    }
+
+    /// Disassembly around the selected line; `*` marks breakpoints, `>` the PC.
+    pub fn disassembly(&self, vm: &mut VM, rows: usize) -> Vec<(Style, String)>
+    {
+        let pc = pc(vm);
+        let start = self.cursor.wrapping_sub((rows / 3) as u16);
+        (0..rows as u16)
+            .map(|i| {
+                let address = start.wrapping_add(i);
+                let inst = vm.memory_peek(address);
+                let breakpoint = self.breakpoints.contains(&address);
+                let label = self.symbols.label(address).unwrap_or("");
+                let line = format!(
+                    "{}{} x{address:04X}  x{inst:04X}  {label:<10} {}",
+                    if breakpoint { '*' } else { ' ' },
+                    if address == pc { '>' } else { ' ' },
+                    disassemble(address, inst)
+                );
+                let style = if address == pc
+                {
+                    Style::Current
+                }
+                else if address == self.cursor
+                {
+                    Style::Selected
+                }
+                else if breakpoint
+                {
+                    Style::Breakpoint
+                }
+                else
+                {
+                    Style::Plain
+                };
+                (style, line)
+            })
+            .collect()
+    }
+
+    pub fn registers(vm: &mut VM) -> Vec<String>
+    {
+        let mut lines: Vec<String> = (0..4)
+            .map(|i| {
+                let (low, high) = (vm.register_read(i), vm.register_read(i + 4));
+                format!("R{i} x{low:04X} {:>6}   R{} x{high:04X} {:>6}", low as i16, i + 4, high as i16)
+            })
+            .collect();
+        let cond = vm.register_read(Registers::R_COND.into());
+        lines.push(format!(
+            "PC x{:04X}          N={} Z={} P={}",
+            pc(vm),
+            (cond >> 2) & 1,
+            (cond >> 1) & 1,
+            cond & 1
+        ));
+        lines
+    }
+
+    /// Words from R6 upwards, the top of the stack first.
+    pub fn stack(vm: &mut VM, rows: usize) -> Vec<String>
+    {
+        let sp = vm.register_read(6);
+        (0..rows as u16)
+            .map(|i| {
+                let address = sp.wrapping_add(i);
+                let marker = if i == 0 { "R6>" } else { "   " };
+                format!("{marker} x{address:04X}  x{:04X}", vm.memory_peek(address))
+            })
+            .collect()
+    }
+
+    /// Hex dump with `per_row` words and their low bytes as ASCII per line.
+    pub fn memory(&self, vm: &VM, rows: usize, per_row: u16) -> Vec<String>
+    {
+        (0..rows as u16)
+            .map(|row| {
+                let start = self.memory_start.wrapping_add(row * per_row);
+                let words: Vec<u16> = (0..per_row).map(|i| vm.memory_peek(start.wrapping_add(i))).collect();
+                let hex: Vec<String> = words.iter().map(|word| format!("{word:04X}")).collect();
+                let ascii: String = words
+                    .iter()
+                    .map(|&word| if (0x20..0x7F).contains(&word) { word as u8 as char } else { '.' })
+                    .collect();
+                format!("x{start:04X}  {}  {ascii}", hex.join(" "))
+            })
+            .collect()
+    }
+
+    /// The last `rows` lines the program printed.
+    pub fn console(vm: &mut VM, rows: usize) -> Vec<String>
+    {
+        let text = String::from_utf8_lossy(vm.display().captured()).into_owned();
+        let lines: Vec<&str> = text.split('\n').collect();
+        lines[lines.len().saturating_sub(rows)..].iter().map(|line| line.replace('\r', "")).collect()
+    }
+
+    fn draw(&self, vm: &mut VM, out: &mut impl Write, width: u16, height: u16) -> io::Result<()>
+    {
+        let (width, height) = (width as usize, height as usize);
+        queue!(out, Clear(ClearType::All))?;
+        if width < 40 || height < 16
+        {
+            return queue!(out, MoveTo(0, 0), Print("terminal too small"));
+        }
+        let console_rows = (height / 4).max(5);
+        let top_rows = height - console_rows - 2;
+        let left = width * 11 / 20;
+        let right = width - left - 1;
+
+        let disassembly = self.disassembly(vm, top_rows - 1);
+        draw_pane(out, 0, 0, left, "Disassembly", &disassembly)?;
+        let plain = |lines: Vec<String>| lines.into_iter().map(|line| (Style::Plain, line)).collect::<Vec<_>>();
+        let registers = plain(Self::registers(vm));
+        draw_pane(out, left + 1, 0, right, "Registers", &registers)?;
+        let stack_rows = 8.min(top_rows.saturating_sub(registers.len() + 4));
+        let stack = plain(Self::stack(vm, stack_rows));
+        draw_pane(out, left + 1, registers.len() + 1, right, "Stack", &stack)?;
+        let memory_top = registers.len() + stack.len() + 2;
+        let per_row = if right >= 58 { 8 } else { 4 };
+        let memory = plain(self.memory(vm, top_rows.saturating_sub(memory_top + 1), per_row));
+        draw_pane(out, left + 1, memory_top, right, "Memory", &memory)?;
+        let console = plain(Self::console(vm, console_rows - 1));
+        draw_pane(out, 0, top_rows, width, "Console", &console)?;
+
+        let state = match self.mode
+        {
+            _ if !vm.state_read() => "halted",
+            Mode::Paused => "paused",
+            Mode::Running => "running",
+            Mode::Input { .. } => "input",
+        };
+        let status = format!("[{state}] {} instructions  {}", vm.steps(), self.status);
+        queue!(out, MoveTo(0, (height - 2) as u16), SetAttribute(Attribute::Reverse), Print(fit(&status, width)), SetAttribute(Attribute::Reset))?;
+        let help = match (&self.command, self.mode)
+        {
+            (Some(line), _) => format!(":{line}"),
+            (None, Mode::Paused) => "s step  c continue  b breakpoint  Up/Down select  PgUp/PgDn memory  : command  q quit".to_string(),
+            (None, Mode::Running) => "keys go to the program  Esc pause".to_string(),
+            (None, Mode::Input { .. }) => "type a key for the program  Esc cancel".to_string(),
+        };
+        queue!(out, MoveTo(0, (height - 1) as u16), Print(fit(&help, width)))?;
+        out.flush()
+    }
+
+    /// Takes over the terminal until the user quits.
+    pub fn interact(&mut self, vm: &mut VM) -> io::Result<()>
+    {
+        let mut out = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(out, EnterAlternateScreen, Hide)?;
+        let result = self.event_loop(vm, &mut out);
+        execute!(out, ResetColor, Show, LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+        result
+    }
+
+    fn event_loop(&mut self, vm: &mut VM, out: &mut impl Write) -> io::Result<()>
+    {
+        while !self.quit
+        {
+            let (width, height) = terminal::size()?;
+            self.draw(vm, out, width, height)?;
+            if self.mode == Mode::Running
+            {
+                if let Some(stop) = self.continue_for(vm, RUN_BATCH)
+                {
+                    self.stopped(vm, Some(stop), true);
+                }
+                while event::poll(Duration::ZERO)?
+                {
+                    if let Event::Key(key) = event::read()?
+                        && key.kind == KeyEventKind::Press
+                    {
+                        self.key(vm, key);
+                    }
+                }
+            }
+            else if let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                self.key(vm, key);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Pads or cuts `text` to exactly `width` columns.
+fn fit(text: &str, width: usize) -> String
+{
+    let mut line: String = text.chars().take(width).collect();
+    let len = line.chars().count();
+    line.extend(std::iter::repeat_n(' ', width - len));
+    line
+}
+
+fn draw_pane(out: &mut impl Write, x: usize, y: usize, width: usize, title: &str, lines: &[(Style, String)]) -> io::Result<()>
+{
+    let heading = format!("-- {title} ");
+    queue!(out, MoveTo(x as u16, y as u16), SetAttribute(Attribute::Bold), Print(fit(&format!("{heading:-<width$}"), width)), SetAttribute(Attribute::Reset))?;
+    for (row, (style, line)) in lines.iter().enumerate()
+    {
+        queue!(out, MoveTo(x as u16, (y + 1 + row) as u16))?;
+        match style
+        {
+            Style::Plain => {}
+            Style::Current => queue!(out, SetAttribute(Attribute::Reverse))?,
+            Style::Selected => queue!(out, SetAttribute(Attribute::Underlined))?,
+            Style::Breakpoint => queue!(out, SetForegroundColor(Color::Red))?,
+        }
+        queue!(out, Print(fit(line, width)), ResetColor, SetAttribute(Attribute::Reset))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,103 @@
+use super::*;
+use std::collections::VecDeque;
+
+use crate::display::Display;
+
+fn debugger(words: &[u16]) -> (Debugger, VM) {
+    let mut vm = VM::new();
+    vm.set_keyboard(Keyboard::Queue(VecDeque::new()));
+    vm.set_display(Display::captured_only());
+    for (i, &word) in words.iter().enumerate() {
+        vm.memory_write(0x3000 + i as u16, word);
+    }
+    vm.register_write(Registers::R_PC.into(), 0x3000);
+    let mut symbols = SymbolTable::default();
+    symbols.extend("LOOP 3001\nDONE 3003");
+    (Debugger::new(symbols, &mut vm), vm)
+}
+
+fn press(debugger: &mut Debugger, vm: &mut VM, code: KeyCode) {
+    debugger.key(vm, KeyEvent::new(code, KeyModifiers::NONE));
+}
+
+const COUNTDOWN: [u16; 4] = [
+    0x1023, // ADD R0, R0, #3
+    0x103F, // LOOP: ADD R0, R0, #-1
+    0x03FE, // BRp LOOP
+    0xF025, // DONE: HALT
+];
+
+#[test]
+fn test_continue_stops_at_breakpoints_and_resumes_past_them() {
+    let (mut debugger, mut vm) = debugger(&COUNTDOWN);
+    debugger.command(&mut vm, "break loop").unwrap();
+    assert!(debugger.breakpoints.contains(&0x3001));
+
+    assert_eq!(debugger.continue_for(&mut vm, 100), Some(Stop::Breakpoint(0x3001)));
+    assert_eq!(vm.steps(), 1);
+    debugger.command(&mut vm, "continue").unwrap();
+    assert_eq!(debugger.continue_for(&mut vm, 100), Some(Stop::Breakpoint(0x3001)));
+    assert_eq!(vm.register_read(0), 2);
+
+    debugger.command(&mut vm, "delete LOOP").unwrap();
+    assert_eq!(debugger.continue_for(&mut vm, 100), Some(Stop::Halted));
+    assert_eq!(debugger.step(&mut vm), Some(Stop::Halted));
+}
+
+#[test]
+fn test_step_key_and_breakpoint_toggle() {
+    let (mut debugger, mut vm) = debugger(&COUNTDOWN);
+    press(&mut debugger, &mut vm, KeyCode::Char('s'));
+    assert_eq!(pc(&mut vm), 0x3001);
+    press(&mut debugger, &mut vm, KeyCode::Down);
+    press(&mut debugger, &mut vm, KeyCode::Char('b'));
+    assert!(debugger.breakpoints.contains(&0x3002));
+    press(&mut debugger, &mut vm, KeyCode::Char('b'));
+    assert!(debugger.breakpoints.is_empty());
+}
+
+#[test]
+fn test_command_line_keys() {
+    let (mut debugger, mut vm) = debugger(&COUNTDOWN);
+    for code in [KeyCode::Char(':'), KeyCode::Char('s'), KeyCode::Char(' '), KeyCode::Char('3'), KeyCode::Enter] {
+        press(&mut debugger, &mut vm, code);
+    }
+    assert_eq!(vm.steps(), 3);
+    debugger.command(&mut vm, "set r5 x1234").unwrap();
+    assert_eq!(vm.register_read(5), 0x1234);
+    assert!(debugger.command(&mut vm, "set r9 1").is_err());
+    assert!(debugger.command(&mut vm, "jump").is_err());
+}
+
+#[test]
+fn test_getc_waits_for_a_typed_key() {
+    let (mut debugger, mut vm) = debugger(&[0xF020, 0xF025]);
+    press(&mut debugger, &mut vm, KeyCode::Char('s'));
+    assert_eq!(vm.steps(), 0);
+    assert_eq!(debugger.mode, Mode::Input { resume: false });
+
+    press(&mut debugger, &mut vm, KeyCode::Char('y'));
+    assert_eq!(vm.register_read(0), b'y' as u16);
+    assert_eq!(debugger.mode, Mode::Paused);
+
+    debugger.command(&mut vm, "input ok\\n").unwrap();
+    assert!(matches!(vm.keyboard(), Keyboard::Queue(keys) if keys == b"ok\n"));
+}
+
+#[test]
+fn test_panes() {
+    let (mut debugger, mut vm) = debugger(&COUNTDOWN);
+    debugger.command(&mut vm, "break DONE").unwrap();
+    let lines = debugger.disassembly(&mut vm, 6);
+    assert_eq!(lines[2], (Style::Current, " > x3000  x1023             ADD R0, R0, #3".to_string()));
+    assert_eq!(lines[5].0, Style::Breakpoint);
+    assert!(lines[5].1.starts_with("*  x3003  xF025  DONE"));
+
+    vm.register_write(6, 0x3002);
+    assert_eq!(Debugger::stack(&mut vm, 2), ["R6> x3002  x03FE", "    x3003  xF025"]);
+    assert_eq!(Debugger::registers(&mut vm)[0], "R0 x0000      0   R4 x0000      0");
+    assert_eq!(debugger.memory(&vm, 1, 4)[0], "x3000  1023 103F 03FE F025  ....");
+
+    vm.display().write(b"one\ntwo\nthree");
+    assert_eq!(Debugger::console(&mut vm, 2), ["two", "three"]);
+}
@@ -1,11 +1,134 @@
-use crate::hardware::{self, CondtionalFlags, Registers,Memory_Mapped_registers::MR_KBDR,Memory_Mapped_registers::MR_KBSR};
-use std::io;
-use std::io::Read;
-use crate::input_buffering::check_key;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::hardware::{self, CondtionalFlags, Registers,Memory_Mapped_registers::MR_KBDR,Memory_Mapped_registers::MR_KBSR,Memory_Mapped_registers::MR_DSR,Memory_Mapped_registers::MR_DDR};
+use crate::display::Display;
+use crate::keyboard::Keyboard;
+use crate::operations::OPCODE_TABLE;
+use crate::trace::{Access, Tracer};
+use crate::profile::Profiler;
+use crate::coverage::Coverage;
+use crate::timing::TimingModel;
+use crate::decode::{decode, Decoded};
+use crate::jit::Jit;
+use crate::traps::{TrapBinding, TrapHandler, Traps};
+use crate::disk::{Disk, DISK_PRIORITY, DISK_VECTOR};
+use crate::disasm::disassemble;
+use crate::operations::sign_extension;
+use crate::protection::{self, Protection, Region};
 pub struct VM {
     state:bool,
     memory: [u16; hardware::MEMORY_MAX], // 65,536 memory locations
     registers: [u16; hardware::Registers::R_COUNT as usize],   // R0-R7, PC, COND
+    keyboard: Keyboard,
+    display: Display,
+    steps: u64, // instructions executed so far
+    tracer: Option<Tracer>,
+    write_log: Option<Vec<Access>>, // writes of the instruction being traced
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    timing: Option<TimingModel>,
+    cycles: u64,      // total cycles under the timing model
+    last_cycles: u64, // cycles of the most recent instruction
+    decoded: Vec<Option<(u16, Decoded)>>, // per address, empty when predecoding is off
+    jit: Option<Jit>,
+    extended_traps: bool, // service TRAP x26-x29, see `Traps::is_extended`
+    trap_handlers: BTreeMap<u8, TrapHandler>, // take precedence over the built-in routines
+    fault: Option<Fault>, // why the VM stopped, when it was not HALT
+    user_mode: bool, // PSR[15]
+    priority: u8,    // PSR[10:8]
+    saved_ssp: u16,  // R6 of the mode not running
+    saved_usp: u16,
+    disk: Option<Disk>,
+    rom: Option<(u16, u16)>, // first and last address, writes are ignored
+    vectored_traps: bool,    // TRAP through the vector table, see `set_vectored_traps`
+    access_control: bool,    // see `set_access_control`
+    regions: Vec<Region>,    // see `protect`
+    previous_pc: Option<u16>, // of the last instruction `step_observed` ran
+}
+
+/// Bit 15 of the PSR, set in user mode.
+pub const PSR_USER: u16 = 1 << 15;
+/// Start of the interrupt vector table, x0100-x01FF.
+pub const INTERRUPT_TABLE: u16 = 0x0100;
+/// Exception vector of an access control violation, entered through x0102.
+pub const ACV_VECTOR: u8 = 0x02;
+/// The only addresses user mode may access; below is system space, above the device page.
+pub const USER_SPACE: std::ops::Range<u16> = 0x3000..0xFE00;
+/// R6 the first interrupt from user mode switches to, as on the LC-3.
+const INITIAL_SSP: u16 = 0x3000;
+
+/// An instruction the VM cannot carry out. It stops the VM like HALT does.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Fault
+{
+    /// The reserved opcode 1101.
+    IllegalOpcode { pc: u16, instruction: u16 },
+    /// RTI in user mode.
+    PrivilegeViolation { pc: u16 },
+    /// TRAP to a vector with no service routine.
+    UnknownTrap { pc: u16, vector: u8 },
+    /// PUTS or PUTSP went all the way around memory without finding the terminator.
+    UnterminatedString { address: u16 },
+    /// The instruction at `pc` would access `address` outside `USER_SPACE`
+    /// in user mode, with no ACV handler to take the exception.
+    AccessViolation { pc: u16, instruction: u16, address: u16 },
+    /// The store at `pc` would write `address` in a `Protection::ReadOnly` region.
+    WriteProtected { pc: u16, instruction: u16, address: u16 },
+    /// The PC reached `pc` in a `Protection::NoExecute` region, coming from
+    /// the instruction at `from`.
+    ExecuteProtected { pc: u16, from: Option<u16> },
+    /// Replaying recorded input, the program read the keyboard differently
+    /// at `step` than the recording did at `recorded`.
+    ReplayDiverged { step: u64, recorded: u64 },
+    /// A device interrupted through `vector` before `pc`, but the interrupt
+    /// vector table holds no service routine for it.
+    UnhandledInterrupt { pc: u16, vector: u8 },
+}
+
+impl fmt::Display for Fault
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            Fault::IllegalOpcode { pc, instruction } => write!(f, "illegal opcode x{instruction:04X} at x{pc:04X}"),
+            Fault::PrivilegeViolation { pc } => write!(f, "privilege violation: RTI in user mode at x{pc:04X}"),
+            Fault::UnknownTrap { pc, vector } => write!(f, "unknown trap vector x{vector:02X} at x{pc:04X}"),
+            Fault::UnterminatedString { address } => write!(f, "string at x{address:04X} has no terminating zero"),
+            Fault::AccessViolation { pc, instruction, address } if pc == address =>
+            {
+                write!(f, "access control violation: user mode fetched x{instruction:04X} from x{pc:04X} in protected memory")
+            }
+            Fault::AccessViolation { pc, instruction, address } =>
+            {
+                write!(f, "access control violation at x{pc:04X} ({}): user mode may not access x{address:04X}", disassemble(pc, instruction))
+            }
+            Fault::WriteProtected { pc, instruction, address } =>
+            {
+                write!(f, "write to read-only memory at x{pc:04X} ({}): x{address:04X} is read-only", disassemble(pc, instruction))
+            }
+            Fault::ExecuteProtected { pc, from: Some(from) } => write!(f, "execution reached non-executable x{pc:04X} from x{from:04X}"),
+            Fault::ExecuteProtected { pc, from: None } => write!(f, "execution started at non-executable x{pc:04X}"),
+            Fault::ReplayDiverged { step, recorded } =>
+            {
+                write!(f, "input replay diverged at step {step}: the recording polled the keyboard at step {recorded}")
+            }
+            Fault::UnhandledInterrupt { pc, vector } =>
+            {
+                write!(f, "interrupt x{vector:02X} before x{pc:04X}: M[x{:04X}] holds no service routine", INTERRUPT_TABLE + vector as u16)
+            }
+        }
+    }
+}
+
+/// Everything a program can observe: registers and memory.
+#[derive(Clone, PartialEq)]
+pub struct ArchState
+{
+    pub registers: [u16; hardware::Registers::R_COUNT as usize],
+    pub memory: Box<[u16]>,
 }
 
 impl VM
@@ -16,7 +139,680 @@ impl VM
         {
             state: true,
             memory: [0;hardware::MEMORY_MAX],
-            registers: [0; hardware::Registers::R_COUNT as usize]
+            registers: [0; hardware::Registers::R_COUNT as usize],
+            keyboard: Keyboard::Terminal,
+            display: Display::new(),
+            steps: 0,
+            tracer: None,
+            write_log: None,
+            profiler: None,
+            coverage: None,
+            timing: None,
+            cycles: 0,
+            last_cycles: 0,
+            decoded: vec![None; hardware::MEMORY_MAX],
+            jit: None,
+            extended_traps: false,
+            trap_handlers: BTreeMap::new(),
+            fault: None,
+            user_mode: false,
+            priority: 0,
+            saved_ssp: INITIAL_SSP,
+            saved_usp: 0,
+            disk: None,
+            rom: None,
+            vectored_traps: false,
+            access_control: false,
+            regions: Vec::new(),
+            previous_pc: None,
+        }
+    }
+
+    fn observed(&self) -> bool
+    {
+        self.tracer.is_some() || self.profiler.is_some() || self.coverage.is_some() || self.timing.is_some() || self.disk.is_some()
+            || (self.access_control && self.user_mode) || !self.regions.is_empty()
+    }
+
+    /// Runs the translated block starting at PC when the JIT has one, else a
+    /// single `step`. Observed runs always go through `step`.
+    pub fn run_block(&mut self)
+    {
+        let pc = self.registers[Registers::R_PC as usize];
+        let observed = self.observed();
+        let block = match &mut self.jit
+        {
+            Some(jit) if !observed => jit.block_at(pc, &self.memory),
+            _ => return self.step(),
+        };
+        let Some(block) = block else
+        {
+            let instruction = self.memory[pc as usize];
+            self.step();
+            if let Some(jit) = &mut self.jit
+            {
+                jit.interpreted(instruction);
+            }
+            return;
+        };
+        for op in &block.ops
+        {
+            self.registers[Registers::R_PC as usize] = op.next_pc;
+            (op.run)(self);
+            self.last_cycles = 1;
+            self.cycles += 1;
+            self.steps += 1;
+            // A fault stops the block at its instruction, as it stops `step`
+            if !self.state || op.store && self.jit.as_ref().is_some_and(Jit::aborted)
+            {
+                break;
+            }
+        }
+        if let Some(jit) = &mut self.jit
+        {
+            jit.block_done();
+        }
+    }
+
+    /// Fetch, decode and execute a single instruction.
+    pub fn step(&mut self)
+    {
+        if self.observed()
+        {
+            return self.step_observed();
+        }
+        let pc = self.registers[Registers::R_PC as usize];
+        self.registers[Registers::R_PC as usize] = pc.wrapping_add(1);
+        match self.fetch(pc)
+        {
+            (_, Some(decoded)) => decoded.execute(self),
+            (instruction, None) => OPCODE_TABLE[(instruction >> 12) as usize](instruction, self),
+        }
+        self.last_cycles = 1;
+        self.cycles += 1;
+        self.steps += 1;
+    }
+
+    /// `step` with a tracer, profiler, coverage, timing model or device
+    /// attached. Kept out of line so the bookkeeping does not slow down
+    /// unobserved runs.
+    #[inline(never)]
+    fn step_observed(&mut self)
+    {
+        self.service_devices();
+        if self.access_control && self.user_mode && !self.check_access()
+        {
+            return;
+        }
+        if !self.regions.is_empty() && !self.check_regions()
+        {
+            return;
+        }
+        let instruction_register = self.register_read(Registers::R_PC.into());
+        self.register_write(Registers::R_PC.into(), instruction_register.wrapping_add(1)); // PC incremented
+        let (instruction, decoded) = self.fetch(instruction_register);
+        let cond = self.registers[Registers::R_COND as usize];
+        let traced = self.tracer.as_ref().is_some_and(|tracer| tracer.wants(instruction_register, instruction));
+        if traced
+        {
+            self.write_log = Some(Vec::new());
+        }
+        match decoded
+        {
+            Some(decoded) => decoded.execute(self),
+            None => OPCODE_TABLE[(instruction >> 12) as usize](instruction, self),
+        }
+        if traced
+        {
+            let writes = self.write_log.take().unwrap_or_default();
+            if let Some(tracer) = &mut self.tracer
+            {
+                tracer.record(self.steps, instruction_register, instruction, &writes);
+            }
+        }
+        self.last_cycles = match &self.timing
+        {
+            Some(timing) => timing.instruction_cycles(instruction, (instruction >> 9) & cond & 7 != 0, self.user_mode),
+            None => 1,
+        };
+        self.cycles += self.last_cycles;
+        if let Some(profiler) = &mut self.profiler
+        {
+            profiler.record(instruction_register, instruction, self.registers[Registers::R_PC as usize], self.last_cycles);
+        }
+        if let Some(coverage) = &mut self.coverage
+        {
+            coverage.record(instruction_register, instruction, cond);
+        }
+        self.previous_pc = Some(instruction_register);
+        self.steps += 1;
+    }
+
+    /// Lets the disk make progress and takes its interrupt once the
+    /// processor runs at a lower priority.
+    fn service_devices(&mut self)
+    {
+        let Some(mut disk) = self.disk.take() else
+        {
+            return;
+        };
+        disk.tick(self);
+        if disk.interrupt_requested() && self.interrupt(DISK_VECTOR, DISK_PRIORITY)
+        {
+            disk.acknowledge();
+        }
+        self.disk = Some(disk);
+    }
+
+    /// Enters the service routine at M[x0100 + `vector`] if `priority` is
+    /// above the current one. PSR and PC are pushed on the supervisor stack,
+    /// switching to it from user mode. Stops with `Fault::UnhandledInterrupt`
+    /// when that entry is empty. Returns whether it was taken.
+    pub fn interrupt(&mut self,vector:u8,priority:u8) -> bool
+    {
+        if priority <= self.priority
+        {
+            return false;
+        }
+        if self.memory[(INTERRUPT_TABLE + vector as u16) as usize] == 0
+        {
+            self.raise(Fault::UnhandledInterrupt { pc: self.registers[Registers::R_PC as usize], vector });
+            return false;
+        }
+        let was_user = self.user_mode;
+        self.enter(INTERRUPT_TABLE + vector as u16, priority);
+        if let Some(timing) = &self.timing
+        {
+            self.cycles += timing.interrupt_cycles(was_user);
+        }
+        true
+    }
+
+    /// Before a user mode instruction runs: takes the ACV exception if the
+    /// instruction would touch memory outside `USER_SPACE`, or stops with
+    /// `Fault::AccessViolation` when M[x0102] holds no handler. Returns
+    /// whether execution goes on.
+    fn check_access(&mut self) -> bool
+    {
+        let pc = self.registers[Registers::R_PC as usize];
+        let Some(address) = self.protected_access(pc) else
+        {
+            return true;
+        };
+        if self.memory[(INTERRUPT_TABLE + ACV_VECTOR as u16) as usize] == 0
+        {
+            self.raise(Fault::AccessViolation { pc, instruction: self.memory[pc as usize], address });
+            return false;
+        }
+        // PC still points at the violating instruction, which is what gets pushed.
+        self.enter(INTERRUPT_TABLE + ACV_VECTOR as u16, self.priority);
+        if let Some(timing) = &self.timing
+        {
+            self.cycles += timing.interrupt_cycles(true);
+        }
+        true
+    }
+
+    /// The first address outside `USER_SPACE` the instruction at `pc`
+    /// fetches, loads or stores, if any.
+    fn protected_access(&self,pc:u16) -> Option<u16>
+    {
+        let protected = |address: u16| !USER_SPACE.contains(&address);
+        if protected(pc)
+        {
+            return Some(pc);
+        }
+        let (address, pointer) = self.data_addresses(pc)?;
+        if protected(address)
+        {
+            return Some(address);
+        }
+        pointer.filter(|&pointer| protected(pointer))
+    }
+
+    /// The effective address of the load or store at `pc` and, for LDI and
+    /// STI, the address read from it. Looks at memory without side effects.
+    fn data_addresses(&self,pc:u16) -> Option<(u16, Option<u16>)>
+    {
+        let instruction = self.memory[pc as usize];
+        let address = match instruction >> 12
+        {
+            // LD, ST, LDI, STI
+            0x2 | 0x3 | 0xA | 0xB => pc.wrapping_add(1).wrapping_add(sign_extension(instruction, 9)),
+            // LDR, STR
+            0x6 | 0x7 => self.registers[(instruction >> 6 & 7) as usize].wrapping_add(sign_extension(instruction, 6)),
+            _ => return None,
+        };
+        let pointer = matches!(instruction >> 12, 0xA | 0xB).then(|| self.memory[address as usize]);
+        Some((address, pointer))
+    }
+
+    /// Where the ST, STI or STR at `pc` would write. Looks at memory without
+    /// side effects.
+    pub fn store_address(&self,pc:u16) -> Option<u16>
+    {
+        match (self.memory[pc as usize] >> 12, self.data_addresses(pc))
+        {
+            (0x3 | 0x7, Some((address, _))) => Some(address),
+            (0xB, Some((_, Some(pointer)))) => Some(pointer),
+            _ => None,
+        }
+    }
+
+    /// Before an instruction runs: stops with `Fault::ExecuteProtected` if
+    /// it is in a no-execute region, or `Fault::WriteProtected` if it would
+    /// store into a read-only one. Returns whether execution goes on.
+    fn check_regions(&mut self) -> bool
+    {
+        let pc = self.registers[Registers::R_PC as usize];
+        if protection::find(&self.regions, pc, Protection::NoExecute).is_some()
+        {
+            self.raise(Fault::ExecuteProtected { pc, from: self.previous_pc });
+            return false;
+        }
+        let instruction = self.memory[pc as usize];
+        let Some(target) = self.store_address(pc) else
+        {
+            return true;
+        };
+        if protection::find(&self.regions, target, Protection::ReadOnly).is_some()
+        {
+            self.raise(Fault::WriteProtected { pc, instruction, address: target });
+            return false;
+        }
+        true
+    }
+
+    /// Guards `first` to `last` with `protection` from now on, see `Fault::WriteProtected`
+    /// and `Fault::ExecuteProtected`. `name` says where the region came from,
+    /// such as the image file, in diagnostics. Only instructions are checked:
+    /// TRAP routines and DMA still write anywhere. A range with `last` below
+    /// `first` wraps past xFFFF and becomes two regions.
+    pub fn protect(&mut self,first:u16,last:u16,protection:Protection,name:&str)
+    {
+        if last < first
+        {
+            self.protect(first, 0xFFFF, protection, name);
+            return self.protect(0, last, protection, name);
+        }
+        self.regions.push(Region { first, last, protection, name: name.to_string() });
+    }
+
+    pub fn regions(&self) -> &[Region]
+    {
+        &self.regions
+    }
+
+    /// The region the current fault hit, for pointing at its origin.
+    pub fn fault_region(&self) -> Option<&Region>
+    {
+        match self.fault?
+        {
+            Fault::WriteProtected { address, .. } => protection::find(&self.regions, address, Protection::ReadOnly),
+            Fault::ExecuteProtected { pc, .. } => protection::find(&self.regions, pc, Protection::NoExecute),
+            _ => None,
+        }
+    }
+
+    /// Checks every memory access of user mode instructions, see `USER_SPACE`.
+    /// Supervisor mode and the built-in TRAP routines are not restricted. Off by default.
+    pub fn set_access_control(&mut self,enabled:bool)
+    {
+        self.access_control = enabled;
+    }
+
+    pub fn access_control(&self) -> bool
+    {
+        self.access_control
+    }
+
+    /// Enters the TRAP service routine at M[`vector`] the way `interrupt`
+    /// does, keeping the current priority. The routine returns with RTI.
+    pub fn enter_trap(&mut self,vector:u8)
+    {
+        self.enter(vector as u16, self.priority);
+    }
+
+    /// Pushes PSR and PC on the supervisor stack and jumps to M[`entry`] in
+    /// supervisor mode at `priority`.
+    fn enter(&mut self,entry:u16,priority:u8)
+    {
+        let psr = self.psr();
+        if self.user_mode
+        {
+            self.saved_usp = self.registers[Registers::R_R6 as usize];
+            self.register_write(Registers::R_R6.into(), self.saved_ssp);
+        }
+        for value in [psr, self.registers[Registers::R_PC as usize]]
+        {
+            let sp = self.registers[Registers::R_R6 as usize].wrapping_sub(1);
+            self.register_write(Registers::R_R6.into(), sp);
+            self.memory_write(sp, value);
+        }
+        self.user_mode = false;
+        self.priority = priority;
+        let handler = self.memory_read(entry);
+        self.register_write(Registers::R_PC.into(), handler);
+    }
+
+    /// RTI: pops PC and PSR from the supervisor stack, switching to the user
+    /// stack when the PSR goes back to user mode.
+    pub fn return_from_interrupt(&mut self)
+    {
+        let sp = self.registers[Registers::R_R6 as usize];
+        let pc = self.memory_read(sp);
+        let psr = self.memory_read(sp.wrapping_add(1));
+        self.register_write(Registers::R_R6.into(), sp.wrapping_add(2));
+        self.register_write(Registers::R_PC.into(), pc);
+        if psr & PSR_USER != 0 && !self.user_mode
+        {
+            self.saved_ssp = self.registers[Registers::R_R6 as usize];
+            self.register_write(Registers::R_R6.into(), self.saved_usp);
+        }
+        self.set_psr(psr);
+    }
+
+    /// Privilege in bit 15, priority in bits 10:8 and the condition codes in 2:0.
+    pub fn psr(&self) -> u16
+    {
+        (self.user_mode as u16 * PSR_USER) | (self.priority as u16) << 8 | self.registers[Registers::R_COND as usize] & 7
+    }
+
+    /// Sets privilege, priority and condition codes without switching stacks.
+    pub fn set_psr(&mut self,psr:u16)
+    {
+        self.user_mode = psr & PSR_USER != 0;
+        self.priority = (psr >> 8 & 7) as u8;
+        self.register_write(Registers::R_COND.into(), psr & 7);
+    }
+
+    pub fn user_mode(&self) -> bool
+    {
+        self.user_mode
+    }
+
+    /// (supervisor, user) stack pointers last saved on a mode switch. The
+    /// one for the running mode is stale, R6 holds the live value.
+    pub fn saved_stack_pointers(&self) -> (u16, u16)
+    {
+        (self.saved_ssp, self.saved_usp)
+    }
+
+    pub fn set_saved_stack_pointers(&mut self,ssp:u16,usp:u16)
+    {
+        self.saved_ssp = ssp;
+        self.saved_usp = usp;
+    }
+
+    /// Puts `words` at `start` as read-only memory: from now on writes to
+    /// those addresses, by programs or DMA, are ignored.
+    pub fn set_rom(&mut self,start:u16,words:&[u16])
+    {
+        self.rom = None;
+        for (i, &word) in words.iter().enumerate()
+        {
+            self.memory_write(start.wrapping_add(i as u16), word);
+        }
+        if !words.is_empty()
+        {
+            self.rom = Some((start, start.wrapping_add((words.len() - 1) as u16)));
+        }
+    }
+
+    /// First and last address of the ROM set with `set_rom`.
+    pub fn rom(&self) -> Option<(u16, u16)>
+    {
+        self.rom
+    }
+
+    /// Attaches `disk` to its registers at xFE10-xFE16.
+    pub fn set_disk(&mut self,disk:Disk)
+    {
+        self.disk = Some(disk);
+    }
+
+    pub fn disk(&mut self) -> Option<&mut Disk>
+    {
+        self.disk.as_mut()
+    }
+
+    /// The instruction at `pc` and, with predecoding on, its decoded form.
+    /// Decoded instructions are cached until `memory_write` changes the word;
+    /// the device page is never cached.
+    #[inline(always)]
+    fn fetch(&mut self,pc:u16) -> (u16, Option<Decoded>)
+    {
+        if let Some(&Some((instruction, decoded))) = self.decoded.get(pc as usize)
+        {
+            return (instruction, Some(decoded));
+        }
+        let instruction = self.memory_read(pc);
+        if self.decoded.is_empty() || pc >= MR_KBSR as u16
+        {
+            return (instruction, None);
+        }
+        let decoded = decode(instruction);
+        self.decoded[pc as usize] = Some((instruction, decoded));
+        (instruction, Some(decoded))
+    }
+
+    /// Switches between predecoded execution (the default) and dispatching
+    /// every fetched word through `OPCODE_TABLE`.
+    pub fn set_predecode(&mut self,enabled:bool)
+    {
+        self.decoded = if enabled { vec![None; hardware::MEMORY_MAX] } else { Vec::new() };
+    }
+
+    /// Services the convenience traps x26-x29 (PUTD, PUTX, GETD, NEWLN).
+    /// Off by default, so they fault like any unknown vector as on a real LC-3.
+    pub fn set_extended_traps(&mut self,enabled:bool)
+    {
+        self.extended_traps = enabled;
+    }
+
+    pub fn extended_traps(&self) -> bool
+    {
+        self.extended_traps
+    }
+
+    /// Lets programs in memory service TRAPs, as an operating system does on
+    /// a real LC-3: a vector whose entry in the table at x0000-x00FF is not
+    /// zero enters the routine it points to with `enter_trap`. Vectors left
+    /// at zero keep their built-in routine. Off by default.
+    pub fn set_vectored_traps(&mut self,enabled:bool)
+    {
+        self.vectored_traps = enabled;
+    }
+
+    pub fn vectored_traps(&self) -> bool
+    {
+        self.vectored_traps
+    }
+
+    /// Services TRAP `vector` with `handler` from now on, overriding the
+    /// built-in routine if there is one. Returns the handler it replaces.
+    pub fn register_trap(&mut self,vector:u8,handler:impl Fn(&mut VM) + 'static) -> Option<TrapHandler>
+    {
+        self.trap_handlers.insert(vector, Rc::new(handler))
+    }
+
+    /// Removes the handler for `vector`, bringing back the built-in routine if any.
+    pub fn unregister_trap(&mut self,vector:u8) -> Option<TrapHandler>
+    {
+        self.trap_handlers.remove(&vector)
+    }
+
+    pub fn trap_handler(&self,vector:u8) -> Option<TrapHandler>
+    {
+        self.trap_handlers.get(&vector).cloned()
+    }
+
+    /// What TRAP `vector` runs, `None` when it faults as an unknown trap.
+    pub fn trap_binding(&self,vector:u8) -> Option<TrapBinding>
+    {
+        if self.trap_handlers.contains_key(&vector)
+        {
+            return Some(TrapBinding::Handler);
+        }
+        let routine = self.memory[vector as usize];
+        if self.vectored_traps && routine != 0
+        {
+            return Some(TrapBinding::Routine(routine));
+        }
+        match Traps::from(vector as u16)
+        {
+            Traps::TRAP_INVALID => None,
+            trap if trap.is_extended() && !self.extended_traps => None,
+            _ => Some(TrapBinding::BuiltIn),
+        }
+    }
+
+    /// Every vector with a service routine, in ascending order.
+    pub fn bound_traps(&self) -> Vec<(u8, TrapBinding)>
+    {
+        (0..=u8::MAX).filter_map(|vector| Some((vector, self.trap_binding(vector)?))).collect()
+    }
+
+    /// Turns on translation of hot blocks for `run_block`.
+    pub fn set_jit(&mut self,jit:Jit)
+    {
+        self.jit = Some(jit);
+    }
+
+    pub fn jit(&self) -> Option<&Jit>
+    {
+        self.jit.as_ref()
+    }
+
+    pub fn set_tracer(&mut self,tracer:Tracer)
+    {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn tracer(&mut self) -> Option<&mut Tracer>
+    {
+        self.tracer.as_mut()
+    }
+
+    pub fn set_profiler(&mut self,profiler:Profiler)
+    {
+        self.profiler = Some(profiler);
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler>
+    {
+        self.profiler.as_ref()
+    }
+
+    pub fn set_coverage(&mut self,coverage:Coverage)
+    {
+        self.coverage = Some(coverage);
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage>
+    {
+        self.coverage.as_ref()
+    }
+
+    pub fn set_timing(&mut self,timing:TimingModel)
+    {
+        self.timing = Some(timing);
+    }
+
+    /// Cycles executed so far; without a timing model every instruction is one cycle.
+    pub fn cycles(&self) -> u64
+    {
+        self.cycles
+    }
+
+    /// Cycle cost of the instruction `step` executed last.
+    pub fn last_cycles(&self) -> u64
+    {
+        self.last_cycles
+    }
+
+    pub fn steps(&self) -> u64
+    {
+        self.steps
+    }
+
+    /// Restores the counters of a saved run, see `Snapshot`.
+    pub fn set_counters(&mut self,steps:u64,cycles:u64)
+    {
+        self.steps = steps;
+        self.cycles = cycles;
+    }
+
+    /// Counts an instruction executed outside `step`, e.g. by the microcode
+    /// simulator, so step-timed input stays in sync.
+    pub fn count_step(&mut self)
+    {
+        self.steps += 1;
+    }
+
+    pub fn arch_state(&self) -> ArchState
+    {
+        ArchState { registers: self.registers, memory: self.memory.into() }
+    }
+
+    /// R0-R7, PC and COND without copying memory like `arch_state`.
+    pub fn registers(&self) -> [u16; hardware::Registers::R_COUNT as usize]
+    {
+        self.registers
+    }
+
+    pub fn set_registers(&mut self,registers:&[u16; hardware::Registers::R_COUNT as usize])
+    {
+        self.registers = *registers;
+    }
+
+    pub fn set_arch_state(&mut self,state:&ArchState)
+    {
+        self.registers = state.registers;
+        self.memory.copy_from_slice(&state.memory);
+        self.decoded.fill(None);
+        if let Some(jit) = &mut self.jit
+        {
+            jit.flush();
+        }
+    }
+
+    pub fn set_keyboard(&mut self,keyboard:Keyboard)
+    {
+        self.keyboard = keyboard;
+    }
+
+    pub fn keyboard(&mut self) -> &mut Keyboard
+    {
+        &mut self.keyboard
+    }
+
+    pub fn set_display(&mut self,display:Display)
+    {
+        self.display = display;
+    }
+
+    pub fn display(&mut self) -> &mut Display
+    {
+        &mut self.display
+    }
+
+    /// Next byte from the keyboard for GETC/IN, `None` once headless input hits EOF.
+    pub fn keyboard_read(&mut self) -> Option<u8>
+    {
+        let byte = self.keyboard.read_byte();
+        self.check_replay();
+        byte
+    }
+
+    /// Stops with `Fault::ReplayDiverged` once replayed input no longer
+    /// matches what the program does.
+    fn check_replay(&mut self)
+    {
+        if let Some(recorded) = self.keyboard.replay_diverged()
+            && self.fault.is_none()
+        {
+            self.raise(Fault::ReplayDiverged { step: self.steps, recorded });
         }
     }
 
@@ -24,37 +820,86 @@ impl VM
     {
         if (address == (MR_KBSR as u16))
         {
-            if (check_key())
+            let key = if self.keyboard.key_ready(self.steps) { self.keyboard.read_byte() } else { None };
+            self.check_replay();
+            if let Some(byte) = key
             {
-                self.memory_write(MR_KBSR as u16,  1<< 15);
-                let mut buffer = [0u8; 1];
-                // read one byte from stdin (line-buffered: requires Enter)
-                io::stdin().read_exact(&mut buffer).unwrap();
-                self.memory_write(MR_KBDR as u16,  buffer[0] as u16);
+                self.memory[MR_KBSR as usize] = 1 << 15;
+                self.memory[MR_KBDR as usize] = byte as u16;
             }
             else
             {
-                self.memory_write(MR_KBSR as u16,  0);
+                self.memory[MR_KBSR as usize] = 0;
             }
         }
+        else if address == MR_DSR as u16
+        {
+            // The display never backs up, it is always ready for the next character
+            self.memory[address as usize] = 1 << 15;
+        }
+        else if let Some(disk) = &self.disk
+            && Disk::is_register(address)
+        {
+            self.memory[address as usize] = disk.register(address);
+        }
+        self.memory[address as usize]
+    }
+
+    /// Reads memory without the device side effects of `memory_read`, for
+    /// debuggers and reports.
+    pub fn memory_peek(&self,address:u16) -> u16
+    {
         self.memory[address as usize]
     }
 
     pub fn memory_write(&mut self,address:u16,value:u16)
     {
+        if let Some((first, last)) = self.rom
+            && (first..=last).contains(&address)
+        {
+            return;
+        }
+        if address == MR_DDR as u16
+        {
+            self.display.write(&[value as u8]);
+        }
+        if let Some(disk) = &mut self.disk
+            && Disk::is_register(address)
+        {
+            disk.write_register(address, value);
+        }
+        if let Some(log) = &mut self.write_log
+        {
+            log.push(Access::Memory(address, value));
+        }
+        if let Some(entry) = self.decoded.get_mut(address as usize)
+        {
+            *entry = None;
+        }
+        if let Some(jit) = &mut self.jit
+        {
+            jit.invalidate(address);
+        }
         self.memory[address as usize] = value;
     }
+    #[inline(always)]
     pub fn register_read(&mut self,register:usize) -> u16
     {
         if register>Registers::R_COUNT as usize {panic!("Invalid register given");}
         self.registers[register]
     }
 
+    #[inline(always)]
     pub fn register_write(&mut self,register:usize,value:u16)
     {
         if register>Registers::R_COUNT as usize {panic!("Invalid register given");}
+        if let Some(log) = &mut self.write_log
+        {
+            log.push(Access::Register(register, value));
+        }
         self.registers[register] = value;
     }
+    #[inline(always)]
     pub fn update_flags(&mut self,register_no:usize)
     {
         self.registers[Registers::R_COND as usize]  = if self.registers[register_no] == 0
@@ -68,8 +913,24 @@ impl VM
         else
         {
             CondtionalFlags::FL_POS as u16
+        };
+        if let Some(log) = &mut self.write_log
+        {
+            log.push(Access::Register(Registers::R_COND.into(), self.registers[Registers::R_COND as usize]));
         }
     }
+    /// Stops the VM with `fault`.
+    pub fn raise(&mut self,fault:Fault)
+    {
+        self.fault = Some(fault);
+        self.state = false;
+    }
+
+    pub fn fault(&self) -> Option<Fault>
+    {
+        self.fault
+    }
+
     pub fn state_change(&mut self)
     {
         self.state = !self.state